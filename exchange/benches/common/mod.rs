@@ -0,0 +1,157 @@
+// Shared by the bench targets, each of them uses only part of it
+#![allow(dead_code)]
+
+use exchange::command::Command;
+use exchange::order::Order;
+use exchange::order_matching_system::order_book::OrderBook;
+use exchange::order_matching_system::order_store::OrderStore;
+use std::time::{Duration, Instant};
+
+// Price levels on each side of the book
+pub const DEPTHS: [u64; 3] = [10, 100, 1_000];
+const ORDERS_PER_LEVEL: u64 = 10;
+const ORDER_AMOUNT: u64 = 10;
+const MID: u64 = 100_000;
+
+// Linear congruential generator, the workload must be the same on every run and every revision
+pub struct Lcg(pub u64);
+
+impl Lcg {
+    pub fn next(&mut self, bound: u64) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 33) % bound
+    }
+}
+
+pub fn funded_order_book() -> OrderBook {
+    let mut order_book = OrderBook::default();
+    for trader in ["C1", "C2", "C3", "C4"] {
+        order_book.execute(Command::parse(&format!("deposit {} USD 1000000000000", trader)).unwrap());
+        for asset in ["A", "B"] {
+            order_book.execute(Command::parse(&format!("deposit {} {} 1000000000", trader, asset)).unwrap());
+        }
+    }
+    order_book
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    // a bid joining one of the existing levels
+    Insert,
+    // a buy filling the order at the front of the best ask
+    Match,
+    // a buy taking every ask level at once
+    Sweep,
+    // a random resting bid leaving its level
+    Cancel,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 4] = [Operation::Insert, Operation::Match, Operation::Sweep, Operation::Cancel];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Insert => "insert",
+            Operation::Match => "match",
+            Operation::Sweep => "sweep",
+            Operation::Cancel => "cancel",
+        }
+    }
+}
+
+// Book of asset A with `levels` price levels on each side and ORDERS_PER_LEVEL orders on each level:
+// C1's bids below MID and C2's asks above it.
+pub struct DepthBook {
+    pub order_book: OrderBook,
+    levels: u64,
+    // resting bids as (id, price)
+    bids: Vec<(usize, u64)>,
+    random: Lcg,
+}
+
+impl DepthBook {
+    pub fn new(levels: u64) -> Self {
+        let mut book = DepthBook {
+            order_book: funded_order_book(),
+            levels,
+            bids: Vec::new(),
+            random: Lcg(42),
+        };
+        for level in 1..=levels {
+            for _ in 0..ORDERS_PER_LEVEL {
+                let order_id = book.add_bid(MID - level);
+                book.bids.push((order_id, MID - level));
+            }
+        }
+        book.add_asks();
+        book
+    }
+
+    // Next order of the book, in orders.txt format
+    fn order(&mut self, serialized: String) -> Order {
+        Order {
+            id: self.order_book.next_order_id(),
+            ..Order::parse(&serialized)
+        }
+    }
+
+    fn add_bid(&mut self, price: u64) -> usize {
+        let order = self.order(format!("C1 b A {} {}", price, ORDER_AMOUNT));
+        let id = order.id;
+        self.order_book.limit(order);
+        id
+    }
+
+    fn add_asks(&mut self) {
+        for level in 1..=self.levels {
+            for _ in 0..ORDERS_PER_LEVEL {
+                let order = self.order(format!("C2 s A {} {}", MID + level, ORDER_AMOUNT));
+                self.order_book.limit(order);
+            }
+        }
+    }
+
+    // Runs one operation through OrderBook::limit or OrderBook::cancel and returns the time it took,
+    // without the market data and ticker updates of execute. What the operation took out of the book
+    // or added to it is put back outside the measured time, so every operation sees the same depth.
+    pub fn measure(&mut self, operation: Operation) -> Duration {
+        let serialized = match operation {
+            Operation::Insert => format!("C1 b A {} {}", MID - 1 - self.random.next(self.levels), ORDER_AMOUNT),
+            Operation::Match => format!("C3 b A {} {}", MID + 1, ORDER_AMOUNT),
+            Operation::Sweep => format!("C3 b A {} {}", MID + self.levels, self.levels * ORDERS_PER_LEVEL * ORDER_AMOUNT),
+            Operation::Cancel => {
+                let cancelled = self.random.next(self.bids.len() as u64) as usize;
+                let (order_id, price) = self.bids[cancelled];
+                let start = Instant::now();
+                self.order_book.cancel(order_id);
+                let elapsed = start.elapsed();
+                self.bids[cancelled] = (self.add_bid(price), price);
+                return elapsed;
+            }
+        };
+        let order = self.order(serialized);
+        let order_id = order.id;
+
+        let start = Instant::now();
+        self.order_book.limit(order);
+        let elapsed = start.elapsed();
+
+        match operation {
+            Operation::Insert => {
+                self.order_book.cancel(order_id);
+            }
+            Operation::Match => {
+                let order = self.order(format!("C2 s A {} {}", MID + 1, ORDER_AMOUNT));
+                self.order_book.limit(order);
+            }
+            Operation::Sweep => self.add_asks(),
+            Operation::Cancel => unreachable!(),
+        }
+        // nothing reads them here, they would only grow over millions of iterations
+        self.order_book.trades.clear();
+        self.order_book.market_data.drain();
+        self.order_book.order_store = OrderStore::default();
+        self.order_book.pnl.positions.clear();
+        elapsed
+    }
+}
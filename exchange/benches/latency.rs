@@ -0,0 +1,46 @@
+mod common;
+
+use common::{DepthBook, Operation, DEPTHS};
+use std::time::Duration;
+
+const SAMPLES: usize = 100_000;
+// a sweep rebuilds the whole ask side after it, fewer of them are enough
+const SWEEP_SAMPLES: usize = 5_000;
+const PERCENTILES: [f64; 4] = [50.0, 90.0, 99.0, 99.9];
+
+// Latency percentiles of single orders against books of every depth. Criterion reports means,
+// this keeps every sample: cargo bench --bench latency
+fn main() {
+    println!(
+        "{:<8} {:>6} {:>12} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "op", "levels", "orders/s", "p50 ns", "p90 ns", "p99 ns", "p99.9 ns", "max ns"
+    );
+    for operation in Operation::ALL {
+        for levels in DEPTHS {
+            let mut book = DepthBook::new(levels);
+            let samples = match operation {
+                Operation::Sweep => SWEEP_SAMPLES,
+                _ => SAMPLES,
+            };
+            // warms the caches and the allocator up before anything is kept
+            for _ in 0..samples / 10 {
+                book.measure(operation);
+            }
+            let mut latencies: Vec<Duration> = (0..samples).map(|_| book.measure(operation)).collect();
+            latencies.sort_unstable();
+
+            let total: Duration = latencies.iter().sum();
+            let percentile = |p: f64| latencies[((p / 100.0 * samples as f64) as usize).min(samples - 1)].as_nanos();
+            print!(
+                "{:<8} {:>6} {:>12.0}",
+                operation.name(),
+                levels,
+                samples as f64 / total.as_secs_f64()
+            );
+            for p in PERCENTILES {
+                print!(" {:>9}", percentile(p));
+            }
+            println!(" {:>9}", latencies[samples - 1].as_nanos());
+        }
+    }
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use common::{funded_order_book, DepthBook, Lcg, Operation, DEPTHS};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use exchange::command::Command;
+use exchange::order_flow::{OrderFlow, OrderFlowConfig};
+use exchange::order_matching_system::order_book::OrderBook;
+use std::time::Duration;
+
+const COMMANDS: usize = 10_000;
+
+// New orders around a common price so that about half of them cross, with cancels and amends
+// of recent ids mixed in. Buyers and sellers are different traders.
+fn commands(seed: u64) -> Vec<Command> {
+    let mut random = Lcg(seed);
+    let mut next_order_id = 0;
+    (0..COMMANDS)
+        .map(|_| {
+            let serialized = match random.next(10) {
+                0 if next_order_id > 0 => format!("cancel {}", next_order_id - 1 - random.next(next_order_id.min(100))),
+                1 if next_order_id > 0 => format!(
+                    "amend {} {} {}",
+                    next_order_id - 1 - random.next(next_order_id.min(100)),
+                    95 + random.next(10),
+                    1 + random.next(50)
+                ),
+                _ => {
+                    next_order_id += 1;
+                    let buy = random.next(2) == 0;
+                    let trader = if buy { ["C1", "C3"] } else { ["C2", "C4"] }[random.next(2) as usize];
+                    format!(
+                        "new {} {} {} {} {}",
+                        trader,
+                        if buy { "b" } else { "s" },
+                        ["A", "B"][random.next(2) as usize],
+                        95 + random.next(10),
+                        1 + random.next(50)
+                    )
+                }
+            };
+            Command::parse(&serialized).unwrap()
+        })
+        .collect()
+}
+
+fn execute(c: &mut Criterion) {
+    let commands = commands(42);
+    let mut group = c.benchmark_group("order_book");
+    group.throughput(Throughput::Elements(COMMANDS as u64));
+    group.bench_function("execute", |b| {
+        b.iter_batched(
+            || (funded_order_book(), commands.clone()),
+            |(mut order_book, commands)| {
+                for command in commands {
+                    order_book.execute(command);
+                }
+                order_book
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+// Cancels every order of one deep price level, newest first, so each one sits at the back of the queue
+fn cancel_deep_queue(c: &mut Criterion) {
+    let deep_queue = || {
+        let mut order_book = funded_order_book();
+        for _ in 0..COMMANDS {
+            order_book.execute(Command::parse("new C1 b A 100 1").unwrap());
+        }
+        order_book
+    };
+    let cancels: Vec<Command> = (0..COMMANDS).rev().map(|order_id| Command::Cancel { order: order_id.into() }).collect();
+
+    let mut group = c.benchmark_group("order_book");
+    group.throughput(Throughput::Elements(COMMANDS as u64));
+    group.bench_function("cancel_deep_queue", |b| {
+        b.iter_batched(
+            || (deep_queue(), cancels.clone()),
+            |(mut order_book, cancels)| {
+                for command in cancels {
+                    order_book.execute(command);
+                }
+                order_book
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+// OrderFlow with its default config: four traders on two assets, a fifth of the commands cancel
+fn generated_flow(c: &mut Criterion) {
+    let flow = OrderFlow::new(OrderFlowConfig::default());
+    let deposits = flow.deposits();
+    let commands: Vec<Command> = flow.take(COMMANDS).collect();
+
+    let mut group = c.benchmark_group("order_book");
+    group.throughput(Throughput::Elements(COMMANDS as u64));
+    group.bench_function("generated_flow", |b| {
+        b.iter_batched(
+            || {
+                let mut order_book = OrderBook::default();
+                for deposit in deposits.clone() {
+                    order_book.execute(deposit);
+                }
+                (order_book, commands.clone())
+            },
+            |(mut order_book, commands)| {
+                for command in commands {
+                    order_book.execute(command);
+                }
+                order_book
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+// One order at a time against books of every depth, e.g. depth/sweep/1000
+fn depth(c: &mut Criterion) {
+    let mut group = c.benchmark_group("depth");
+    group.throughput(Throughput::Elements(1));
+    for operation in Operation::ALL {
+        for levels in DEPTHS {
+            let mut book = DepthBook::new(levels);
+            group.bench_with_input(BenchmarkId::new(operation.name(), levels), &levels, |b, _| {
+                b.iter_custom(|iterations| (0..iterations).map(|_| book.measure(operation)).sum::<Duration>())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, execute, cancel_deep_queue, generated_flow, depth);
+criterion_main!(benches);
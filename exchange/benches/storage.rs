@@ -0,0 +1,263 @@
+mod common;
+
+use common::Lcg;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use exchange::asset_name::AssetName;
+use exchange::order::{Direction, Order};
+use exchange::order_matching_system::arena::OrderArena;
+use exchange::order_matching_system::limit_tree::LimitTree;
+use exchange::order_matching_system::matching_policy::MatchingPolicy;
+use exchange::trade::Trade;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+
+const COMMANDS: usize = 10_000;
+
+// The same limit orders and cancels run against the two ways the book has stored orders: shared
+// Rc<RefCell<Order>> in VecDeque queues, as before the arena, and arena slots linked into the queues
+// by handles, as LimitTree does now. Only matching and resting are measured, without traders,
+// settlement or listeners, so the difference is the storage alone.
+#[derive(Clone)]
+enum Operation {
+    New(Order),
+    Cancel(usize),
+}
+
+trait Book: Default {
+    fn new_order(&mut self, order: Order, trades: &mut Vec<Trade>);
+    fn cancel(&mut self, order_id: usize);
+}
+
+// Orders around a common price so that about half of them cross, with cancels of recent ids mixed in
+fn operations(seed: u64) -> Vec<Operation> {
+    let mut random = Lcg(seed);
+    let mut next_order_id = 0;
+    (0..COMMANDS)
+        .map(|_| match random.next(10) {
+            0 if next_order_id > 0 => Operation::Cancel((next_order_id - 1 - random.next(next_order_id.min(100))) as usize),
+            _ => {
+                next_order_id += 1;
+                Operation::New(order(
+                    next_order_id as usize - 1,
+                    if random.next(2) == 0 { Direction::Buy } else { Direction::Sell },
+                    95 + random.next(10),
+                    1 + random.next(50),
+                ))
+            }
+        })
+        .collect()
+}
+
+// Bids resting on one price level, then their cancels newest first so each one sits at the back of the queue
+fn deep_queue() -> (Vec<Operation>, Vec<Operation>) {
+    let bids = (0..COMMANDS).map(|order_id| Operation::New(order(order_id, Direction::Buy, 100, 1)));
+    let cancels = (0..COMMANDS).rev().map(Operation::Cancel);
+    (bids.collect(), cancels.collect())
+}
+
+fn order(id: usize, direction: Direction, price: u64, amount: u64) -> Order {
+    Order {
+        id,
+        trader_name: if direction == Direction::Buy { "C1" } else { "C2" }.to_string(),
+        direction,
+        asset: AssetName::A,
+        price,
+        amount,
+        ..Default::default()
+    }
+}
+
+fn run<B: Book>(mut book: B, operations: Vec<Operation>) -> (B, Vec<Trade>) {
+    let mut trades = Vec::new();
+    for operation in operations {
+        match operation {
+            Operation::New(order) => book.new_order(order, &mut trades),
+            Operation::Cancel(order_id) => book.cancel(order_id),
+        }
+    }
+    (book, trades)
+}
+
+// The book after the setup, with the operations to measure on it
+fn prepared<B: Book>(setup: &[Operation], operations: &[Operation]) -> (B, Vec<Operation>) {
+    (run(B::default(), setup.to_vec()).0, operations.to_vec())
+}
+
+struct ArenaBook {
+    orders: OrderArena,
+    bids: LimitTree,
+    asks: LimitTree,
+}
+
+impl Default for ArenaBook {
+    fn default() -> Self {
+        ArenaBook {
+            orders: OrderArena::default(),
+            bids: LimitTree::new(Direction::Buy),
+            asks: LimitTree::new(Direction::Sell),
+        }
+    }
+}
+
+impl Book for ArenaBook {
+    fn new_order(&mut self, order: Order, trades: &mut Vec<Trade>) {
+        let (id, direction) = (order.id, order.direction);
+        let handle = self.orders.insert(order);
+        let (opposite, own) = match direction {
+            Direction::Buy => (&mut self.asks, &mut self.bids),
+            Direction::Sell => (&mut self.bids, &mut self.asks),
+        };
+        opposite.market(handle, MatchingPolicy::Fifo, &mut self.orders, trades);
+        if self.orders.contains_key(&id) {
+            own.new_limit(handle, &mut self.orders);
+        }
+    }
+
+    fn cancel(&mut self, order_id: usize) {
+        let Some(handle) = self.orders.handle(&order_id) else {
+            return;
+        };
+        let limits = match self.orders[handle].direction {
+            Direction::Buy => &mut self.bids,
+            Direction::Sell => &mut self.asks,
+        };
+        if limits.remove(handle, &mut self.orders).is_some() {
+            self.orders.remove(&order_id);
+        }
+    }
+}
+
+struct RcLimit {
+    volume: u64,
+    orders: VecDeque<Rc<RefCell<Order>>>,
+}
+
+// The book's storage before the arena: every order shared between the id index and its limit's queue
+#[derive(Default)]
+struct RcBook {
+    orders: BTreeMap<usize, Rc<RefCell<Order>>>,
+    bids: BTreeMap<u64, Rc<RefCell<RcLimit>>>,
+    asks: BTreeMap<u64, Rc<RefCell<RcLimit>>>,
+}
+
+impl Book for RcBook {
+    fn new_order(&mut self, order: Order, trades: &mut Vec<Trade>) {
+        let order = Rc::new(RefCell::new(order));
+        let direction = order.borrow().direction;
+        let opposite = match direction {
+            Direction::Buy => &mut self.asks,
+            Direction::Sell => &mut self.bids,
+        };
+        while order.borrow().amount > 0 {
+            let best = match direction {
+                Direction::Buy => opposite.first_key_value(),
+                Direction::Sell => opposite.last_key_value(),
+            };
+            let Some((&price, limit)) = best else {
+                break;
+            };
+            let crosses = match direction {
+                Direction::Buy => order.borrow().price >= price,
+                Direction::Sell => order.borrow().price <= price,
+            };
+            if !crosses {
+                break;
+            }
+            let limit = limit.clone();
+            let mut limit = limit.borrow_mut();
+            let matched_order = limit.orders.front().unwrap().clone();
+            let mut matched = matched_order.borrow_mut();
+            let mut incoming = order.borrow_mut();
+            let amount = matched.amount.min(incoming.amount);
+            let (buy, sell) = match direction {
+                Direction::Buy => (&*incoming, &*matched),
+                Direction::Sell => (&*matched, &*incoming),
+            };
+            trades.push(Trade {
+                asset: buy.asset,
+                price,
+                amount,
+                buy_order_id: buy.id,
+                sell_order_id: sell.id,
+                buyer: buy.trader_name.clone(),
+                seller: sell.trader_name.clone(),
+                ..Default::default()
+            });
+            matched.amount -= amount;
+            incoming.amount -= amount;
+            limit.volume -= amount;
+            if matched.amount == 0 {
+                self.orders.remove(&matched.id);
+                limit.orders.pop_front();
+            }
+            if limit.orders.is_empty() {
+                opposite.remove(&price);
+            }
+        }
+
+        if order.borrow().amount == 0 {
+            return;
+        }
+        let (id, price, amount) = (order.borrow().id, order.borrow().price, order.borrow().amount);
+        let own = match direction {
+            Direction::Buy => &mut self.bids,
+            Direction::Sell => &mut self.asks,
+        };
+        let limit = own.entry(price).or_insert_with(|| {
+            Rc::new(RefCell::new(RcLimit {
+                volume: 0,
+                orders: VecDeque::new(),
+            }))
+        });
+        limit.borrow_mut().volume += amount;
+        limit.borrow_mut().orders.push_back(order.clone());
+        self.orders.insert(id, order);
+    }
+
+    // The queue is searched for the order, as the VecDeque gives no way to it from the id
+    fn cancel(&mut self, order_id: usize) {
+        let Some(order) = self.orders.remove(&order_id) else {
+            return;
+        };
+        let order = order.borrow();
+        let limits = match order.direction {
+            Direction::Buy => &mut self.bids,
+            Direction::Sell => &mut self.asks,
+        };
+        let Some(limit) = limits.get(&order.price).cloned() else {
+            return;
+        };
+        let mut limit = limit.borrow_mut();
+        if let Some(position) = limit.orders.iter().position(|queued| queued.borrow().id == order_id) {
+            limit.orders.remove(position);
+            limit.volume -= order.amount;
+        }
+        if limit.orders.is_empty() {
+            limits.remove(&order.price);
+        }
+    }
+}
+
+// e.g. storage/execute/arena against storage/execute/rc_refcell
+fn storage(c: &mut Criterion) {
+    let mut group = c.benchmark_group("storage");
+    group.throughput(Throughput::Elements(COMMANDS as u64));
+    for (name, (setup, operations)) in [("execute", (Vec::new(), operations(42))), ("cancel_deep_queue", deep_queue())] {
+        // both storages have to do the same work
+        let rc_trades = run(prepared::<RcBook>(&setup, &operations).0, operations.clone()).1;
+        let arena_trades = run(prepared::<ArenaBook>(&setup, &operations).0, operations.clone()).1;
+        assert_eq!(rc_trades, arena_trades);
+
+        group.bench_function(BenchmarkId::new(name, "rc_refcell"), |b| {
+            b.iter_batched(|| prepared::<RcBook>(&setup, &operations), |(book, operations)| run(book, operations), BatchSize::LargeInput)
+        });
+        group.bench_function(BenchmarkId::new(name, "arena"), |b| {
+            b.iter_batched(|| prepared::<ArenaBook>(&setup, &operations), |(book, operations)| run(book, operations), BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, storage);
+criterion_main!(benches);
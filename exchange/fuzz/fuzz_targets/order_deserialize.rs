@@ -0,0 +1,16 @@
+#![no_main]
+
+use exchange::order::Order;
+use libfuzzer_sys::fuzz_target;
+
+// Any line either fails to parse or parses to an order whose serialized form parses back to it.
+// Order::deserialize is try_parse with an expect, so only try_parse is fuzzed.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(serialized_str) = std::str::from_utf8(data) {
+        if let Some(order) = Order::try_parse(serialized_str) {
+            let serialized = order.serialize();
+            let reparsed = Order::try_parse(&serialized).expect("Serialized order doesn't parse");
+            assert_eq!(reparsed.serialize(), serialized);
+        }
+    }
+});
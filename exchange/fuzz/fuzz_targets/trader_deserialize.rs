@@ -0,0 +1,16 @@
+#![no_main]
+
+use exchange::trader::Trader;
+use libfuzzer_sys::fuzz_target;
+
+// Any line either fails to parse or parses to a trader whose serialized form parses back to it.
+// Trader::deserialize is try_parse with an expect, so only try_parse is fuzzed.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(serialized_str) = std::str::from_utf8(data) {
+        if let Some(trader) = Trader::try_parse(serialized_str) {
+            let serialized = trader.serialize();
+            let reparsed = Trader::try_parse(&serialized).expect("Serialized trader doesn't parse");
+            assert_eq!(reparsed.serialize(), serialized);
+        }
+    }
+});
@@ -0,0 +1,946 @@
+use crate::api::http::{json_string, Request, Response};
+use crate::api::websocket::{self, OPCODE_CLOSE, OPCODE_PING, OPCODE_PONG, OPCODE_TEXT};
+use crate::asset_name::AssetName;
+use crate::candles::{BarKind, Candle, CandleAggregator};
+use crate::command::{Command, OrderRef};
+use crate::order::{Direction, Order, TimeInForce};
+use crate::order_matching_system::journal::Journal;
+use crate::order_matching_system::level3::{Level3, QueueLevel};
+use crate::order_matching_system::limit_tree::Level;
+use crate::order_matching_system::ticker::Ticker;
+use crate::order_matching_system::order_book::{OrderBook, Outcome};
+use crate::order_matching_system::order_store::{OrderRecord, OrderStatus};
+use crate::order_matching_system::pnl::TraderPnl;
+use crate::order_matching_system::session::SessionScheduler;
+use crate::order_matching_system::settlement::{INSUFFICIENT_FUNDS, UNKNOWN_TRADER};
+use crate::trade::Trade;
+use crate::udp_feed::publisher::MarketDataPublisher;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub const API_ADDR: &str = "127.0.0.1:8080";
+pub const API_KEYS_PATH: &str = "resources/api_keys.txt";
+const DEFAULT_DEPTH: usize = 10;
+// How long the engine stays idle before the market data feed sends a heartbeat
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+// One "<trader name> <api key>" pair per line
+pub fn load_api_keys<P: AsRef<Path>>(path: P) -> HashMap<String, String> {
+    let file = File::open(path).expect("Unable to open api keys file");
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| {
+            let (trader_name, api_key) = line.split_once(' ')?;
+            Some((api_key.trim().to_string(), trader_name.to_string()))
+        })
+        .collect()
+}
+
+// One of the caller's orders, by the exchange id or by their client order id while it is live
+enum OrderKey {
+    Id(usize),
+    Client(String),
+}
+
+enum ApiRequest {
+    NewOrder(Order),
+    Cancel(OrderKey),
+    Order(OrderKey),
+    // live orders without a status, otherwise every order that has it
+    Orders(Option<OrderStatus>),
+    Account,
+    Pnl,
+    Depth(AssetName, usize),
+    // only_own keeps the caller's orders, otherwise every trader is anonymised
+    Book { asset: AssetName, only_own: bool },
+    Candles(AssetName, BarKind),
+    Ticker(AssetName),
+}
+
+enum EngineMessage {
+    Request {
+        trader_name: String,
+        request: ApiRequest,
+        reply: Sender<Response>,
+    },
+    Subscribe {
+        trader_name: String,
+        events: Sender<String>,
+    },
+}
+
+// The book lives on the engine thread alone and connections talk to it over a channel
+pub struct ApiServer {
+    listener: TcpListener,
+    api_keys: Arc<HashMap<String, String>>,
+    engine: Sender<EngineMessage>,
+}
+
+impl ApiServer {
+    pub fn bind<A, F>(
+        addr: A,
+        api_keys: HashMap<String, String>,
+        journal: Option<Journal>,
+        candles: Vec<CandleAggregator>,
+        market_data: Option<MarketDataPublisher>,
+        scheduler: Option<SessionScheduler>,
+        make_order_book: F,
+    ) -> io::Result<Self>
+    where
+        A: ToSocketAddrs,
+        F: FnOnce() -> OrderBook + Send + 'static,
+    {
+        let listener = TcpListener::bind(addr)?;
+        let (engine, requests) = mpsc::channel();
+        thread::spawn(move || {
+            let mut engine = Engine {
+                order_book: make_order_book(),
+                journal,
+                subscribers: Vec::new(),
+                candles,
+                market_data,
+                scheduler,
+            };
+            engine.run(requests);
+        });
+        Ok(ApiServer {
+            listener,
+            api_keys: Arc::new(api_keys),
+            engine,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn run(&self) {
+        for stream in self.listener.incoming().map_while(Result::ok) {
+            let engine = self.engine.clone();
+            let api_keys = self.api_keys.clone();
+            thread::spawn(move || {
+                let _ = handle_connection(stream, engine, api_keys);
+            });
+        }
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, engine: Sender<EngineMessage>, api_keys: Arc<HashMap<String, String>>) -> io::Result<()> {
+    let request = match Request::read(&stream)? {
+        Ok(request) => request,
+        Err(response) => return response.write(&mut stream),
+    };
+    let api_key = request.header("x-api-key").or(request.query.get("api_key").map(String::as_str));
+    let Some(trader_name) = api_key.and_then(|api_key| api_keys.get(api_key)).cloned() else {
+        return Response::error(401, "Missing or unknown API key").write(&mut stream);
+    };
+
+    if request.path == "/ws" {
+        let Some(key) = request.header("sec-websocket-key") else {
+            return Response::error(400, "Expected a WebSocket upgrade").write(&mut stream);
+        };
+        // subscribe before the handshake completes so no event after it can be missed
+        let (events, receiver) = mpsc::channel();
+        let _ = engine.send(EngineMessage::Subscribe { trader_name, events });
+        stream.write_all(websocket::handshake_response(key).as_bytes())?;
+        return serve_websocket(stream, receiver);
+    }
+
+    let response = match route(&request) {
+        Ok(request) => {
+            let (reply, response) = mpsc::channel();
+            let _ = engine.send(EngineMessage::Request {
+                trader_name,
+                request,
+                reply,
+            });
+            response.recv().unwrap_or_else(|_| Response::error(500, "Engine is not running"))
+        }
+        Err(response) => response,
+    };
+    response.write(&mut stream)
+}
+
+fn route(request: &Request) -> Result<ApiRequest, Response> {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["orders"]) => parse_new_order(&request.form()).map(ApiRequest::NewOrder),
+        ("GET", ["orders"]) => match request.query.get("status") {
+            Some(status) => status.parse().map(|status| ApiRequest::Orders(Some(status))).map_err(|_| Response::error(400, "Bad status")),
+            None => Ok(ApiRequest::Orders(None)),
+        },
+        ("GET", ["orders", order_id]) => parse_order_id(order_id).map(|order_id| ApiRequest::Order(OrderKey::Id(order_id))),
+        ("DELETE", ["orders", order_id]) => {
+            parse_order_id(order_id).map(|order_id| ApiRequest::Cancel(OrderKey::Id(order_id)))
+        }
+        ("GET", ["orders", "client", id]) => Ok(ApiRequest::Order(OrderKey::Client(id.to_string()))),
+        ("DELETE", ["orders", "client", id]) => Ok(ApiRequest::Cancel(OrderKey::Client(id.to_string()))),
+        ("GET", ["account"]) => Ok(ApiRequest::Account),
+        ("GET", ["pnl"]) => Ok(ApiRequest::Pnl),
+        ("GET", ["depth", asset]) => {
+            let asset = parse_asset(asset)?;
+            let levels = match request.query.get("levels") {
+                Some(levels) => levels.parse().map_err(|_| Response::error(400, "Bad levels"))?,
+                None => DEFAULT_DEPTH,
+            };
+            Ok(ApiRequest::Depth(asset, levels))
+        }
+        ("GET", ["book", asset]) => Ok(ApiRequest::Book {
+            asset: parse_asset(asset)?,
+            only_own: matches!(request.query.get("mine").map(String::as_str), Some("1") | Some("true")),
+        }),
+        ("GET", ["ticker", asset]) => parse_asset(asset).map(ApiRequest::Ticker),
+        ("GET", ["candles", asset]) => {
+            let asset = parse_asset(asset)?;
+            let interval = request.query.get("interval").map_or("1m", String::as_str);
+            let kind = interval.parse().map_err(|_| Response::error(400, "Bad interval"))?;
+            Ok(ApiRequest::Candles(asset, kind))
+        }
+        (_, ["orders"]) | (_, ["orders", _]) | (_, ["orders", "client", _]) | (_, ["account"]) | (_, ["pnl"]) | (_, ["depth", _]) | (_, ["book", _]) | (_, ["candles", _]) | (_, ["ticker", _]) => {
+            Err(Response::error(405, "Method not allowed"))
+        }
+        _ => Err(Response::error(404, "Not found")),
+    }
+}
+
+fn parse_order_id(order_id: &str) -> Result<usize, Response> {
+    order_id.parse().map_err(|_| Response::error(400, "Bad order id"))
+}
+
+fn parse_asset(asset: &str) -> Result<AssetName, Response> {
+    match AssetName::from_str(asset) {
+        Ok(asset) if asset != AssetName::Unknown => Ok(asset),
+        _ => Err(Response::error(400, "Unknown asset")),
+    }
+}
+
+fn parse_new_order(form: &HashMap<String, String>) -> Result<Order, Response> {
+    let field = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+    let direction = match field("side") {
+        "buy" | "b" => Direction::Buy,
+        "sell" | "s" => Direction::Sell,
+        _ => return Err(Response::error(400, "side must be buy or sell")),
+    };
+    let price = field("price").parse::<u64>().ok().filter(|price| *price > 0);
+    let amount = field("amount").parse::<u64>().ok().filter(|amount| *amount > 0);
+    let (Some(price), Some(amount)) = (price, amount) else {
+        return Err(Response::error(400, "price and amount must be positive integers"));
+    };
+    let time_in_force = match field("time_in_force") {
+        "" | "gtc" => TimeInForce::Gtc,
+        "day" => TimeInForce::Day,
+        _ => return Err(Response::error(400, "time_in_force must be gtc or day")),
+    };
+    // the id goes into the journal as one word
+    let client_order_id = match field("client_order_id") {
+        "" => None,
+        id if id.contains(char::is_whitespace) => {
+            return Err(Response::error(400, "client_order_id must not contain spaces"));
+        }
+        id => Some(id.to_string()),
+    };
+    Ok(Order {
+        direction,
+        asset: parse_asset(field("asset"))?,
+        price,
+        amount,
+        time_in_force,
+        client_order_id,
+        ..Default::default()
+    })
+}
+
+fn serve_websocket(mut stream: TcpStream, events: Receiver<String>) -> io::Result<()> {
+    loop {
+        match events.recv_timeout(Duration::from_millis(50)) {
+            Ok(event) => websocket::write_frame(&mut stream, OPCODE_TEXT, event.as_bytes())?,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+
+        // only start reading once a frame has arrived, so a timeout never splits it
+        stream.set_nonblocking(true)?;
+        let available = stream.peek(&mut [0u8; 1]);
+        stream.set_nonblocking(false)?;
+        match available {
+            Ok(0) => return Ok(()),
+            Ok(_) => match websocket::read_frame(&mut stream) {
+                Ok((OPCODE_CLOSE, payload)) => return websocket::write_frame(&mut stream, OPCODE_CLOSE, &payload),
+                Ok((OPCODE_PING, payload)) => websocket::write_frame(&mut stream, OPCODE_PONG, &payload)?,
+                Ok(_) => {}
+                Err(error) if error.kind() == ErrorKind::InvalidData => {
+                    let code = websocket::CLOSE_MESSAGE_TOO_BIG.to_be_bytes();
+                    return websocket::write_frame(&mut stream, OPCODE_CLOSE, &code);
+                }
+                Err(error) => return Err(error),
+            },
+            Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+struct Engine {
+    order_book: OrderBook,
+    journal: Option<Journal>,
+    subscribers: Vec<(String, Sender<String>)>,
+    candles: Vec<CandleAggregator>,
+    market_data: Option<MarketDataPublisher>,
+    scheduler: Option<SessionScheduler>,
+}
+
+impl Engine {
+    fn run(&mut self, requests: Receiver<EngineMessage>) {
+        loop {
+            // the heartbeat interval bounds how late a scheduled transition is
+            if let Some(scheduler) = self.scheduler.as_mut() {
+                for command in scheduler.due(&self.order_book) {
+                    self.submit(command);
+                }
+            }
+            let message = match requests.recv_timeout(HEARTBEAT_INTERVAL) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Some(market_data) = self.market_data.as_ref() {
+                        let _ = market_data.heartbeat();
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+            match message {
+                EngineMessage::Request {
+                    trader_name,
+                    request,
+                    reply,
+                } => {
+                    let response = if self.order_book.users.contains_key(&trader_name) {
+                        self.handle(&trader_name, request)
+                    } else {
+                        Response::error(401, "Unknown trader")
+                    };
+                    let _ = reply.send(response);
+                }
+                EngineMessage::Subscribe { trader_name, events } => self.subscribers.push((trader_name, events)),
+            }
+        }
+    }
+
+    fn handle(&mut self, trader_name: &str, request: ApiRequest) -> Response {
+        match request {
+            ApiRequest::NewOrder(mut order) => {
+                order.trader_name = trader_name.to_string();
+                let (order_id, trades) = match self.submit(Command::NewOrder(order)) {
+                    (Outcome::Accepted(order_id), trades) => (order_id, trades),
+                    (Outcome::Rejected(reason), _) => return Response::error(rejection_status(reason), reason),
+                    (Outcome::Executed, _) => unreachable!("a new order is accepted or rejected"),
+                };
+
+                let resting = self.order_book.orders.get(&order_id).map(|resting| resting.amount);
+                let body = format!(
+                    "{{\"order_id\":{},\"status\":{},\"remaining\":{},\"trades\":[{}]}}",
+                    order_id,
+                    json_string(if resting.is_some() { "resting" } else { "filled" }),
+                    resting.unwrap_or(0),
+                    trades.iter().map(trade_json).collect::<Vec<_>>().join(",")
+                );
+                Response::json(201, body)
+            }
+            ApiRequest::Cancel(key) => {
+                let order_id = self.order_id(trader_name, key);
+                let Some(order) = order_id.and_then(|order_id| self.own_order(trader_name, order_id)) else {
+                    return Response::error(404, "Order not found");
+                };
+                if let (Outcome::Rejected(reason), _) = self.submit(Command::Cancel { order: order.id.into() }) {
+                    return Response::error(409, reason);
+                }
+                self.publish_to(trader_name, execution_report("cancelled", &order, None));
+                Response::json(200, format!("{{\"order_id\":{},\"status\":\"cancelled\"}}", order.id))
+            }
+            ApiRequest::Order(key) => {
+                let order_id = self.order_id(trader_name, key);
+                match order_id.and_then(|order_id| self.order_book.order_store.get(order_id)) {
+                    Some(record) if record.order.trader_name == trader_name => Response::json(200, record_json(record)),
+                    _ => Response::error(404, "Order not found"),
+                }
+            }
+            ApiRequest::Orders(Some(status)) => {
+                let records: Vec<String> = self
+                    .order_book
+                    .order_store
+                    .by_trader(trader_name)
+                    .into_iter()
+                    .filter(|record| record.status == status)
+                    .map(record_json)
+                    .collect();
+                Response::json(200, format!("[{}]", records.join(",")))
+            }
+            ApiRequest::Orders(None) => {
+                let orders: Vec<String> = self
+                    .order_book
+                    .orders
+                    .values()
+                    .filter(|order| order.trader_name == trader_name)
+                    .map(order_json)
+                    .collect();
+                Response::json(200, format!("[{}]", orders.join(",")))
+            }
+            ApiRequest::Account => Response::json(200, self.account_json(trader_name)),
+            ApiRequest::Pnl => Response::json(200, self.pnl_json(trader_name)),
+            ApiRequest::Depth(asset, levels) => Response::json(200, self.depth_json(asset, levels)),
+            ApiRequest::Book { asset, only_own } => {
+                let level3 = self.order_book.level3(asset, only_own.then_some(trader_name), !only_own);
+                Response::json(200, level3_json(&level3))
+            }
+            ApiRequest::Ticker(asset) => {
+                let ticker = self.order_book.tickers.get(&asset).cloned().unwrap_or(Ticker {
+                    asset,
+                    ..Default::default()
+                });
+                Response::json(200, ticker_json(&ticker))
+            }
+            ApiRequest::Candles(asset, kind) => match self.candles.iter().find(|candles| candles.kind == kind) {
+                Some(candles) => {
+                    let candles: Vec<String> = candles.candles(asset).iter().map(candle_json).collect();
+                    Response::json(200, format!("[{}]", candles.join(",")))
+                }
+                None => Response::error(404, "Interval is not aggregated"),
+            },
+        }
+    }
+
+    // Exchange id of the order the key names, a client order id only names a live order of the caller
+    fn order_id(&self, trader_name: &str, key: OrderKey) -> Option<usize> {
+        let order = match key {
+            OrderKey::Id(order_id) => OrderRef::Id(order_id),
+            OrderKey::Client(client_order_id) => OrderRef::Client {
+                trader_name: trader_name.to_string(),
+                client_order_id,
+            },
+        };
+        self.order_book.client_order_ids.resolve(&order)
+    }
+
+    fn own_order(&self, trader_name: &str, order_id: usize) -> Option<Order> {
+        let order = self.order_book.orders.get(&order_id)?.clone();
+        (order.trader_name == trader_name).then_some(order)
+    }
+
+    // Executes the command once, the book decides whether it is accepted. The owner of an accepted order
+    // hears of it before any of its fills.
+    fn submit(&mut self, command: Command) -> (Outcome, Vec<Trade>) {
+        let trades_before = self.order_book.trades.len();
+        let new_order = match &command {
+            Command::NewOrder(order) => Some(order.clone()),
+            _ => None,
+        };
+        let outcome = match self.journal.as_mut() {
+            Some(journal) => self.order_book.apply(journal, command),
+            None => self.order_book.execute(command),
+        };
+        if let (Some(mut order), Outcome::Accepted(order_id)) = (new_order, outcome) {
+            order.id = order_id;
+            let trader_name = order.trader_name.clone();
+            self.publish_to(&trader_name, execution_report("new", &order, None));
+        }
+        // the book only buffers the feed, a lost datagram is for subscribers to recover
+        let messages = self.order_book.market_data.drain();
+        if let Some(market_data) = self.market_data.as_mut() {
+            let _ = market_data.publish(&messages);
+        }
+
+        let trades = self.order_book.trades[trades_before..].to_vec();
+        for trade in &trades {
+            // candles are in milliseconds, bars of a replay fall where the trades did
+            for candles in &mut self.candles {
+                candles.on_trade(trade, trade.timestamp / 1_000_000);
+            }
+            let public_trade = format!("{{\"type\":\"trade\",\"trade\":{}}}", trade_json(trade));
+            self.subscribers.retain(|(_, events)| events.send(public_trade.clone()).is_ok());
+            for (order_id, trader_name) in [(trade.buy_order_id, &trade.buyer), (trade.sell_order_id, &trade.seller)] {
+                let order = Order {
+                    id: order_id,
+                    trader_name: trader_name.clone(),
+                    direction: if order_id == trade.buy_order_id { Direction::Buy } else { Direction::Sell },
+                    asset: trade.asset,
+                    price: trade.price,
+                    amount: trade.amount,
+                    update_timestamp: trade.timestamp,
+                    ..Default::default()
+                };
+                self.publish_to(trader_name, execution_report("fill", &order, Some(trade.id)));
+            }
+        }
+        (outcome, trades)
+    }
+
+    fn publish_to(&mut self, trader_name: &str, event: String) {
+        self.subscribers
+            .retain(|(subscriber, events)| subscriber != trader_name || events.send(event.clone()).is_ok());
+    }
+
+    fn account_json(&self, trader_name: &str) -> String {
+        let trader = &self.order_book.users[trader_name];
+        let mut assets = Vec::new();
+        let mut asset_name = AssetName::A;
+        while asset_name != AssetName::Unknown {
+            assets.push(format!(
+                "\"{:?}\":{{\"available\":{},\"reserved\":{}}}",
+                asset_name,
+                trader.assets_count.get(&asset_name).unwrap_or(&0),
+                trader.reserved_assets.get(&asset_name).unwrap_or(&0)
+            ));
+            asset_name = asset_name.next();
+        }
+        format!(
+            "{{\"trader\":{},\"usd\":{{\"available\":{},\"reserved\":{}}},\"assets\":{{{}}}}}",
+            json_string(&trader.name),
+            trader.usd_balance,
+            trader.reserved_usd,
+            assets.join(",")
+        )
+    }
+
+    // Totals and every traded asset, a trader who has not traded has no assets and zero totals
+    fn pnl_json(&self, trader_name: &str) -> String {
+        let pnl = self.order_book.pnl(trader_name).unwrap_or(TraderPnl {
+            trader_name: trader_name.to_string(),
+            assets: Vec::new(),
+        });
+        let optional = |value: Option<f64>| value.map_or("null".to_string(), |value| value.to_string());
+        let assets: Vec<String> = pnl
+            .assets
+            .iter()
+            .map(|asset| {
+                format!(
+                    "\"{:?}\":{{\"position\":{},\"average_cost\":{},\"realized\":{},\"unrealized\":{},\"mark\":{},\"fees\":{},\"volume\":{},\"notional\":{}}}",
+                    asset.asset,
+                    asset.position,
+                    optional(asset.average_cost),
+                    asset.realized,
+                    asset.unrealized,
+                    optional(asset.mark),
+                    asset.fees,
+                    asset.volume,
+                    asset.notional
+                )
+            })
+            .collect();
+        format!(
+            "{{\"trader\":{},\"realized\":{},\"unrealized\":{},\"fees\":{},\"volume\":{},\"notional\":{},\"assets\":{{{}}}}}",
+            json_string(trader_name),
+            pnl.realized(),
+            pnl.unrealized(),
+            pnl.assets.iter().map(|asset| asset.fees).sum::<u64>(),
+            pnl.assets.iter().map(|asset| asset.volume).sum::<u64>(),
+            pnl.assets.iter().map(|asset| asset.notional).sum::<u64>(),
+            assets.join(",")
+        )
+    }
+
+    fn depth_json(&self, asset: AssetName, levels: usize) -> String {
+        let depth = self.order_book.depth(asset, levels);
+        let side = |levels: &[Level]| {
+            levels
+                .iter()
+                .map(|level| {
+                    format!(
+                        "{{\"price\":{},\"volume\":{},\"orders\":{}}}",
+                        level.price, level.volume, level.order_count
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        format!(
+            "{{\"asset\":\"{:?}\",\"bids\":[{}],\"asks\":[{}]}}",
+            asset,
+            side(&depth.bids),
+            side(&depth.asks)
+        )
+    }
+}
+
+fn side_json(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Buy => "\"buy\"",
+        Direction::Sell => "\"sell\"",
+    }
+}
+
+fn client_order_id_json(order: &Order) -> String {
+    order.client_order_id.as_deref().map_or("null".to_string(), json_string)
+}
+
+fn order_json(order: &Order) -> String {
+    format!(
+        "{{\"order_id\":{},\"client_order_id\":{},\"trader\":{},\"asset\":\"{:?}\",\"side\":{},\"price\":{},\"amount\":{},\"entry_time\":{},\"update_time\":{}}}",
+        order.id,
+        client_order_id_json(order),
+        json_string(&order.trader_name),
+        order.asset,
+        side_json(order.direction),
+        order.price,
+        order.amount,
+        order.entry_timestamp,
+        order.update_timestamp
+    )
+}
+
+// The order as it is now with what happened to it, a rejected order has no id
+fn record_json(record: &OrderRecord) -> String {
+    let order = &record.order;
+    let order_id = if (record.status == OrderStatus::Rejected) { "null".to_string() } else { order.id.to_string() };
+    let average_price = record.average_price().map_or("null".to_string(), |price| price.to_string());
+    let trade_ids: Vec<String> = record.trade_ids.iter().map(usize::to_string).collect();
+    format!(
+        "{{\"order_id\":{},\"client_order_id\":{},\"trader\":{},\"asset\":\"{:?}\",\"side\":{},\"price\":{},\"amount\":{},\"entry_time\":{},\"update_time\":{},\"status\":\"{}\",\"filled\":{},\"average_price\":{},\"trade_ids\":[{}]}}",
+        order_id,
+        client_order_id_json(order),
+        json_string(&order.trader_name),
+        order.asset,
+        side_json(order.direction),
+        order.price,
+        order.amount,
+        order.entry_timestamp,
+        order.update_timestamp,
+        record.status,
+        record.filled,
+        average_price,
+        trade_ids.join(",")
+    )
+}
+
+fn level3_json(level3: &Level3) -> String {
+    let side = |levels: &[QueueLevel]| {
+        levels
+            .iter()
+            .map(|level| {
+                let entries: Vec<String> = level
+                    .entries
+                    .iter()
+                    .map(|entry| {
+                        format!(
+                            "{{\"position\":{},\"order_id\":{},\"trader\":{},\"amount\":{},\"entry_sequence\":{}}}",
+                            entry.position,
+                            entry.order_id,
+                            json_string(&entry.trader),
+                            entry.amount,
+                            entry.entry_sequence
+                        )
+                    })
+                    .collect();
+                format!("{{\"price\":{},\"orders\":[{}]}}", level.price, entries.join(","))
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    format!(
+        "{{\"asset\":\"{:?}\",\"bids\":[{}],\"asks\":[{}]}}",
+        level3.asset,
+        side(&level3.bids),
+        side(&level3.asks)
+    )
+}
+
+fn ticker_json(ticker: &Ticker) -> String {
+    let value = |value: Option<u64>| value.map_or("null".to_string(), |value| value.to_string());
+    let level = |level: Option<Level>| {
+        level.map_or("null".to_string(), |level| format!("{{\"price\":{},\"size\":{}}}", level.price, level.volume))
+    };
+    format!(
+        "{{\"asset\":\"{:?}\",\"last_price\":{},\"last_size\":{},\"best_bid\":{},\"best_ask\":{},\"open\":{},\"high\":{},\"low\":{},\"volume\":{},\"notional\":{},\"vwap\":{},\"trades\":{}}}",
+        ticker.asset,
+        value(ticker.last_price),
+        value(ticker.last_size),
+        level(ticker.best_bid),
+        level(ticker.best_ask),
+        value(ticker.open),
+        value(ticker.high),
+        value(ticker.low),
+        ticker.volume,
+        ticker.notional,
+        ticker.vwap().map_or("null".to_string(), |vwap| vwap.to_string()),
+        ticker.trade_count
+    )
+}
+
+fn candle_json(candle: &Candle) -> String {
+    format!(
+        "{{\"start\":{},\"end\":{},\"open\":{},\"high\":{},\"low\":{},\"close\":{},\"volume\":{},\"vwap\":{},\"trades\":{}}}",
+        candle.start,
+        candle.end,
+        candle.open,
+        candle.high,
+        candle.low,
+        candle.close,
+        candle.volume,
+        candle.vwap(),
+        candle.trade_count
+    )
+}
+
+fn trade_json(trade: &Trade) -> String {
+    format!(
+        "{{\"trade_id\":{},\"asset\":\"{:?}\",\"price\":{},\"amount\":{},\"time\":{}}}",
+        trade.id, trade.asset, trade.price, trade.amount, trade.timestamp
+    )
+}
+
+// A rejection the trader could avoid by asking differently is a bad request, anything else conflicts
+// with the state of the book
+fn rejection_status(reason: &str) -> u16 {
+    match reason {
+        INSUFFICIENT_FUNDS | UNKNOWN_TRADER => 400,
+        _ => 409,
+    }
+}
+
+fn execution_report(event: &str, order: &Order, trade_id: Option<usize>) -> String {
+    let trade_id = trade_id.map_or("null".to_string(), |trade_id| trade_id.to_string());
+    format!(
+        "{{\"type\":\"execution_report\",\"event\":{},\"trade_id\":{},\"order\":{}}}",
+        json_string(event),
+        trade_id,
+        order_json(order)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deserialize::Deserialize;
+    use crate::trader::Trader;
+    use std::io::Read;
+
+    fn start_server() -> SocketAddr {
+        let api_keys = [("key-c1", "C1"), ("key-c2", "C2"), ("key-c9", "C9")]
+            .into_iter()
+            .map(|(api_key, trader_name)| (api_key.to_string(), trader_name.to_string()))
+            .collect();
+        let candles = vec![CandleAggregator::new(BarKind::Ticks(1)), CandleAggregator::new("1h".parse().unwrap())];
+        let server = ApiServer::bind("127.0.0.1:0", api_keys, None, candles, None, None, || OrderBook {
+            users: ["C1 2000 10 5 15 0", "C2 1000 20 35 40 10"]
+                .into_iter()
+                .map(|serialized| Trader::deserialize(serialized.to_string()))
+                .map(|trader| (trader.name.clone(), trader))
+                .collect(),
+            ..Default::default()
+        })
+        .unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        addr
+    }
+
+    fn request(addr: SocketAddr, method: &str, path: &str, api_key: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nX-API-Key: {}\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            api_key,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    fn connect_websocket(addr: SocketAddr, api_key: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET /ws?api_key={} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            api_key
+        )
+        .unwrap();
+        let mut handshake = Vec::new();
+        while !handshake.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).unwrap();
+            handshake.push(byte[0]);
+        }
+        let handshake = String::from_utf8(handshake).unwrap();
+        assert!(handshake.starts_with("HTTP/1.1 101"));
+        assert!(handshake.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream
+    }
+
+    fn next_event(stream: &mut TcpStream) -> String {
+        let (opcode, payload) = websocket::read_frame(stream).unwrap();
+        assert_eq!(opcode, OPCODE_TEXT);
+        String::from_utf8(payload).unwrap()
+    }
+
+    #[test]
+    fn requests_without_known_api_key_are_rejected() {
+        let addr = start_server();
+
+        assert_eq!(request(addr, "GET", "/account", "wrong", "").0, 401);
+        assert_eq!(request(addr, "GET", "/account", "key-c9", "").0, 401);
+    }
+
+    #[test]
+    fn orders_are_submitted_queried_and_cancelled() {
+        let addr = start_server();
+
+        let (status, body) = request(addr, "POST", "/orders", "key-c2", "asset=A&side=sell&price=7&amount=20");
+        assert_eq!(status, 201);
+        assert_eq!(body, "{\"order_id\":0,\"status\":\"resting\",\"remaining\":20,\"trades\":[]}");
+
+        let (status, body) = request(addr, "POST", "/orders", "key-c1", "asset=A&side=buy&price=7&amount=5");
+        assert_eq!(status, 201);
+        assert!(body.contains("\"status\":\"filled\""));
+        assert!(body.contains("{\"trade_id\":0,\"asset\":\"A\",\"price\":7,\"amount\":5,\"time\":0}"));
+
+        assert_eq!(
+            request(addr, "GET", "/orders/0", "key-c2", ""),
+            (200, "{\"order_id\":0,\"client_order_id\":null,\"trader\":\"C2\",\"asset\":\"A\",\"side\":\"sell\",\"price\":7,\"amount\":15,\"entry_time\":0,\"update_time\":0,\"status\":\"partially_filled\",\"filled\":5,\"average_price\":7,\"trade_ids\":[0]}".to_string())
+        );
+        assert_eq!(request(addr, "GET", "/orders/0", "key-c1", "").0, 404);
+        assert_eq!(
+            request(addr, "GET", "/depth/A?levels=1", "key-c1", "").1,
+            "{\"asset\":\"A\",\"bids\":[],\"asks\":[{\"price\":7,\"volume\":15,\"orders\":1}]}"
+        );
+        assert!(request(addr, "GET", "/candles/A?interval=1t", "key-c1", "")
+            .1
+            .starts_with("[{\"start\":"));
+        assert!(request(addr, "GET", "/candles/A?interval=1h", "key-c1", "")
+            .1
+            .ends_with("\"open\":7,\"high\":7,\"low\":7,\"close\":7,\"volume\":5,\"vwap\":7,\"trades\":1}]"));
+        assert_eq!(request(addr, "GET", "/candles/A?interval=1s", "key-c1", "").0, 404);
+        assert_eq!(
+            request(addr, "GET", "/ticker/A", "key-c1", "").1,
+            "{\"asset\":\"A\",\"last_price\":7,\"last_size\":5,\"best_bid\":null,\"best_ask\":{\"price\":7,\"size\":15},\"open\":7,\"high\":7,\"low\":7,\"volume\":5,\"notional\":35,\"vwap\":7,\"trades\":1}"
+        );
+        assert!(request(addr, "GET", "/ticker/B", "key-c1", "").1.contains("\"last_price\":null"));
+        assert_eq!(
+            request(addr, "GET", "/pnl", "key-c1", "").1,
+            "{\"trader\":\"C1\",\"realized\":0,\"unrealized\":0,\"fees\":0,\"volume\":5,\"notional\":35,\"assets\":{\"A\":{\"position\":5,\"average_cost\":7,\"realized\":0,\"unrealized\":0,\"mark\":7,\"fees\":0,\"volume\":5,\"notional\":35}}}"
+        );
+        assert!(request(addr, "GET", "/pnl", "key-c2", "").1.contains("\"A\":{\"position\":-5,"));
+        request(addr, "POST", "/orders", "key-c1", "asset=A&side=sell&price=7&amount=2");
+        assert_eq!(
+            request(addr, "GET", "/book/A?mine=1", "key-c1", "").1,
+            "{\"asset\":\"A\",\"bids\":[],\"asks\":[{\"price\":7,\"orders\":[{\"position\":1,\"order_id\":2,\"trader\":\"C1\",\"amount\":2,\"entry_sequence\":2}]}]}"
+        );
+        assert!(request(addr, "GET", "/book/A", "key-c1", "").1.contains("\"trader\":\"T"));
+        assert_eq!(request(addr, "DELETE", "/orders/2", "key-c1", "").0, 200);
+        assert!(request(addr, "GET", "/account", "key-c1", "")
+            .1
+            .starts_with("{\"trader\":\"C1\",\"usd\":{\"available\":1965,\"reserved\":0},\"assets\":{\"A\":{\"available\":15,\"reserved\":0}"));
+
+        assert_eq!(request(addr, "DELETE", "/orders/0", "key-c1", "").0, 404);
+        assert_eq!(request(addr, "DELETE", "/orders/0", "key-c2", "").0, 200);
+        assert_eq!(request(addr, "GET", "/orders", "key-c2", ""), (200, "[]".to_string()));
+
+        // the store still knows the orders that left the book
+        assert!(request(addr, "GET", "/orders/1", "key-c1", "").1.contains("\"status\":\"filled\",\"filled\":5,\"average_price\":7,\"trade_ids\":[0]"));
+        assert!(request(addr, "GET", "/orders/2", "key-c1", "").1.contains("\"status\":\"cancelled\""));
+        let cancelled = request(addr, "GET", "/orders?status=cancelled", "key-c2", "").1;
+        assert!(cancelled.starts_with("[{\"order_id\":0,") && cancelled.ends_with("\"trade_ids\":[0]}]"), "{}", cancelled);
+        assert_eq!(request(addr, "GET", "/orders?status=filled", "key-c2", ""), (200, "[]".to_string()));
+        assert_eq!(request(addr, "GET", "/orders?status=open", "key-c2", "").0, 400);
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let addr = start_server();
+
+        assert_eq!(request(addr, "POST", "/orders", "key-c1", "asset=Z&side=buy&price=1&amount=1").0, 400);
+        assert_eq!(request(addr, "POST", "/orders", "key-c1", "asset=A&side=buy&price=0&amount=1").0, 400);
+        assert_eq!(request(addr, "POST", "/orders", "key-c1", "asset=A&side=buy&price=1000&amount=1000").0, 400);
+        assert_eq!(request(addr, "PUT", "/orders/1", "key-c1", "").0, 405);
+        assert_eq!(request(addr, "GET", "/candles/A?interval=1d", "key-c1", "").0, 400);
+        assert_eq!(request(addr, "GET", "/nowhere", "key-c1", "").0, 404);
+        assert_eq!(request(addr, "POST", "/orders", "key-c1", "asset=A&side=buy&price=1&amount=1&client_order_id=a+b").0, 400);
+
+        // the book rejected the unaffordable order without giving it an id
+        let (status, body) = request(addr, "POST", "/orders", "key-c1", "asset=A&side=buy&price=1&amount=1");
+        assert_eq!(status, 201);
+        assert!(body.starts_with("{\"order_id\":0,"));
+    }
+
+    #[test]
+    fn oversized_bodies_and_frames_are_refused_unread() {
+        let addr = start_server();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "POST /orders HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large"), "{}", response);
+
+        let mut c1_events = connect_websocket(addr, "key-c1");
+        let mut frame = vec![0x81, 0x80 | 127];
+        frame.extend_from_slice(&u64::MAX.to_be_bytes());
+        c1_events.write_all(&frame).unwrap();
+        let (opcode, payload) = websocket::read_frame(&mut c1_events).unwrap();
+        assert_eq!((opcode, payload), (OPCODE_CLOSE, websocket::CLOSE_MESSAGE_TOO_BIG.to_be_bytes().to_vec()));
+        assert_eq!(request(addr, "GET", "/account", "key-c1", "").0, 200);
+    }
+
+    #[test]
+    fn orders_are_found_and_cancelled_by_client_order_id() {
+        let addr = start_server();
+
+        let new_order = "asset=A&side=buy&price=5&amount=2&client_order_id=bid-1";
+        assert_eq!(request(addr, "POST", "/orders", "key-c1", new_order).0, 201);
+        assert_eq!(request(addr, "POST", "/orders", "key-c1", new_order), (409, "{\"error\":\"Duplicate client order id\"}".to_string()));
+        assert_eq!(request(addr, "POST", "/orders", "key-c2", "asset=A&side=sell&price=9&amount=2&client_order_id=bid-1").0, 201);
+
+        assert!(request(addr, "GET", "/orders/client/bid-1", "key-c1", "")
+            .1
+            .starts_with("{\"order_id\":0,\"client_order_id\":\"bid-1\",\"trader\":\"C1\""));
+        assert_eq!(request(addr, "GET", "/orders/client/bid-2", "key-c1", "").0, 404);
+        assert_eq!(
+            request(addr, "DELETE", "/orders/client/bid-1", "key-c1", ""),
+            (200, "{\"order_id\":0,\"status\":\"cancelled\"}".to_string())
+        );
+        assert_eq!(request(addr, "DELETE", "/orders/client/bid-1", "key-c1", "").0, 404);
+        assert_eq!(request(addr, "POST", "/orders", "key-c1", new_order).0, 201);
+    }
+
+    #[test]
+    fn websocket_pushes_own_execution_reports_and_public_trades() {
+        let addr = start_server();
+        let mut c2_events = connect_websocket(addr, "key-c2");
+        let mut c1_events = connect_websocket(addr, "key-c1");
+
+        request(addr, "POST", "/orders", "key-c2", "asset=A&side=sell&price=7&amount=20");
+        request(addr, "POST", "/orders", "key-c1", "asset=A&side=buy&price=7&amount=5");
+
+        let new = next_event(&mut c2_events);
+        assert!(new.starts_with("{\"type\":\"execution_report\",\"event\":\"new\""));
+        assert_eq!(
+            next_event(&mut c2_events),
+            "{\"type\":\"trade\",\"trade\":{\"trade_id\":0,\"asset\":\"A\",\"price\":7,\"amount\":5,\"time\":0}}"
+        );
+        let fill = next_event(&mut c2_events);
+        assert!(fill.contains("\"event\":\"fill\",\"trade_id\":0"));
+        assert!(fill.contains("\"side\":\"sell\""));
+
+        assert!(next_event(&mut c1_events).contains("\"event\":\"new\""));
+        assert!(next_event(&mut c1_events).contains("\"type\":\"trade\""));
+        assert!(next_event(&mut c1_events).contains("\"side\":\"buy\""));
+
+        websocket::write_frame(&mut c1_events, OPCODE_CLOSE, b"").unwrap();
+        assert_eq!(websocket::read_frame(&mut c1_events).unwrap().0, OPCODE_CLOSE);
+    }
+}
@@ -1,7 +1,7 @@
 use std::str::FromStr;
 use strum_macros::EnumString;
 
-#[derive(Debug, Default, Copy, Clone, Eq, Hash, PartialEq, EnumString)]
+#[derive(Debug, Default, Copy, Clone, Eq, Hash, PartialEq, PartialOrd, Ord, EnumString)]
 pub enum AssetName {
     #[default]
     A,
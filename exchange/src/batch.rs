@@ -0,0 +1,47 @@
+use crate::clock::{Clock, SimulatedClock};
+use crate::command::Command;
+use crate::order_matching_system::journal::Journal;
+use crate::order_matching_system::order_book::OrderBook;
+use crate::order_matching_system::session::{Schedule, SessionScheduler};
+
+// Executes commands one after another, each after the session transitions the schedule is due for,
+// journaling them when there is a journal. The book and the schedule run on the given clock, unless
+// a new order carries a timestamp: then it is a historical replay and the clock follows the orders' times.
+pub fn run(
+    order_book: &mut OrderBook,
+    commands: Vec<Command>,
+    schedule: Option<Schedule>,
+    clock: impl Clock + Clone + 'static,
+    mut journal: Option<&mut Journal>,
+) {
+    let historical = SimulatedClock::default();
+    let replay = commands
+        .iter()
+        .any(|command| matches!(command, Command::NewOrder(order) if order.entry_timestamp > 0));
+    let mut scheduler = if replay {
+        order_book.clock = Box::new(historical.clone());
+        schedule.map(|schedule| SessionScheduler::new(schedule, historical.clone()))
+    } else {
+        order_book.clock = Box::new(clock.clone());
+        schedule.map(|schedule| SessionScheduler::new(schedule, clock))
+    };
+
+    for command in commands {
+        if let Command::NewOrder(order) = &command {
+            historical.set(historical.now().max(order.entry_timestamp));
+        }
+        if let Some(scheduler) = scheduler.as_mut() {
+            for transition in scheduler.due(order_book) {
+                execute(order_book, journal.as_deref_mut(), transition);
+            }
+        }
+        execute(order_book, journal.as_deref_mut(), command);
+    }
+}
+
+fn execute(order_book: &mut OrderBook, journal: Option<&mut Journal>, command: Command) {
+    match journal {
+        Some(journal) => order_book.apply(journal, command),
+        None => order_book.execute(command),
+    };
+}
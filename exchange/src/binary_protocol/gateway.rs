@@ -0,0 +1,421 @@
+use crate::asset_name::AssetName;
+use crate::binary_protocol::messages::*;
+use crate::command::Command;
+use crate::order::Order;
+use crate::order_matching_system::journal::Journal;
+use crate::order_matching_system::listener::UNKNOWN_ORDER;
+use crate::order_matching_system::order_book::{OrderBook, Outcome};
+use crate::order_matching_system::settlement;
+use crate::trade::Trade;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::Path;
+
+const READ_BUFFER_LEN: usize = 4096;
+
+// State of the currently connected client
+struct Connection {
+    trader: Option<String>,
+    outgoing: Vec<u8>,
+    closed: bool,
+}
+
+impl Connection {
+    fn send(&mut self, encode: impl FnOnce(&mut [u8]) -> usize) {
+        let mut buffer = [0u8; 64];
+        let len = encode(&mut buffer);
+        self.outgoing.extend_from_slice(&buffer[..len]);
+    }
+}
+
+pub struct BinaryGateway {
+    pub order_book: OrderBook,
+    // trader name by API key, as for the HTTP API
+    api_keys: HashMap<String, String>,
+    journal: Option<Journal>,
+    listener: TcpListener,
+}
+
+impl BinaryGateway {
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+        api_keys: HashMap<String, String>,
+        order_book: OrderBook,
+        journal: Option<Journal>,
+    ) -> io::Result<Self> {
+        Ok(BinaryGateway {
+            order_book,
+            api_keys,
+            journal,
+            listener: TcpListener::bind(addr)?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Snapshot of the book, after which the journal starts over
+    pub fn save_checkpoint<P: AsRef<Path>>(&mut self, snapshot_path: P) -> io::Result<()> {
+        self.order_book.save_checkpoint(snapshot_path, self.journal.as_mut())
+    }
+
+    // Serves a single connection until the client disconnects or sends garbage
+    pub fn accept_one(&mut self) -> io::Result<()> {
+        let (mut stream, _) = self.listener.accept()?;
+        stream.set_nodelay(true)?;
+        let mut connection = Connection {
+            trader: None,
+            outgoing: Vec::new(),
+            closed: false,
+        };
+        let mut buffer = [0u8; READ_BUFFER_LEN];
+        let mut filled = 0;
+
+        while !connection.closed {
+            let read = stream.read(&mut buffer[filled..])?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+
+            // messages are handled straight out of the read buffer
+            let mut consumed = 0;
+            while !connection.closed {
+                match Message::decode(&buffer[consumed..filled]) {
+                    Ok((message, len)) => {
+                        consumed += len;
+                        self.on_message(&mut connection, message);
+                    }
+                    Err(DecodeError::Truncated) => break,
+                    Err(_) => connection.closed = true,
+                }
+            }
+            buffer.copy_within(consumed..filled, 0);
+            filled -= consumed;
+
+            stream.write_all(&connection.outgoing)?;
+            connection.outgoing.clear();
+        }
+        Ok(())
+    }
+
+    fn on_message(&mut self, connection: &mut Connection, message: Message) {
+        if let Message::Login(login) = message {
+            self.on_login(connection, login);
+            return;
+        }
+        if connection.trader.is_none() {
+            connection.send(|buffer| Rejected::encode(buffer, reject_reason::NOT_LOGGED_IN, 0));
+            connection.closed = true;
+            return;
+        }
+        match message {
+            Message::NewOrder(new_order) => self.on_new_order(connection, new_order),
+            Message::Cancel(cancel) => self.on_cancel(connection, cancel),
+            Message::Replace(replace) => self.on_replace(connection, replace),
+            // acknowledgements only ever flow from the exchange
+            _ => connection.closed = true,
+        }
+    }
+
+    // The API key must belong to the trader, who is then the owner of every order of the connection
+    fn on_login(&mut self, connection: &mut Connection, login: Login) {
+        let trader = login.trader();
+        let authenticated = self.api_keys.get(login.api_key()).is_some_and(|owner| owner == trader);
+        if connection.trader.is_some() || !authenticated || !self.order_book.users.contains_key(trader) {
+            connection.send(|buffer| Rejected::encode(buffer, reject_reason::UNKNOWN_TRADER, 0));
+            connection.closed = true;
+            return;
+        }
+        connection.trader = Some(trader.to_string());
+        connection.send(|buffer| LoginAccepted::encode(buffer, trader));
+    }
+
+    fn on_new_order(&mut self, connection: &mut Connection, new_order: NewOrder) {
+        let client_token = new_order.client_token();
+        let trader_name = connection.trader.clone().unwrap();
+        let order = new_order
+            .to_order(&trader_name)
+            .filter(|order| order.asset != AssetName::Unknown && order.price > 0 && order.amount > 0);
+        let Some(order) = order else {
+            connection.send(|buffer| Rejected::encode(buffer, reject_reason::INVALID_ORDER, client_token));
+            return;
+        };
+        match self.submit(Command::NewOrder(order.clone())) {
+            (Outcome::Accepted(order_id), trades) => {
+                let accepted = Order { id: order_id, ..order };
+                connection.send(|buffer| Accepted::encode(buffer, client_token, &accepted));
+                report_fills(connection, &trades);
+            }
+            (Outcome::Rejected(reason), _) => {
+                connection.send(|buffer| Rejected::encode(buffer, reject_code(reason), client_token))
+            }
+            (Outcome::Executed, _) => unreachable!("a new order is accepted or rejected"),
+        }
+    }
+
+    fn on_cancel(&mut self, connection: &mut Connection, cancel: Cancel) {
+        let order_id = cancel.order_id();
+        let Some(resting) = self.owned_order(connection, order_id) else {
+            connection.send(|buffer| Rejected::encode(buffer, reject_reason::UNKNOWN_ORDER, order_id));
+            return;
+        };
+        match self.submit(Command::Cancel { order: resting.id.into() }) {
+            (Outcome::Rejected(reason), _) => {
+                connection.send(|buffer| Rejected::encode(buffer, reject_code(reason), order_id))
+            }
+            _ => connection.send(|buffer| Cancelled::encode(buffer, order_id, resting.amount)),
+        }
+    }
+
+    fn on_replace(&mut self, connection: &mut Connection, replace: Replace) {
+        let order_id = replace.order_id();
+        let Some(resting) = self.owned_order(connection, order_id).filter(|_| replace.quantity() > 0) else {
+            connection.send(|buffer| Rejected::encode(buffer, reject_reason::UNKNOWN_ORDER, order_id));
+            return;
+        };
+        let (price, amount) = (replace.price(), replace.quantity());
+        if (price == 0) {
+            connection.send(|buffer| Rejected::encode(buffer, reject_reason::INSUFFICIENT_FUNDS, order_id));
+            return;
+        }
+        let amend = Command::Amend {
+            order: resting.id.into(),
+            price,
+            amount,
+        };
+        match self.submit(amend) {
+            (Outcome::Rejected(reason), _) => {
+                connection.send(|buffer| Rejected::encode(buffer, reject_code(reason), order_id))
+            }
+            (_, trades) => {
+                connection.send(|buffer| Replaced::encode(buffer, order_id, price, amount));
+                report_fills(connection, &trades);
+            }
+        }
+    }
+
+    fn owned_order(&self, connection: &Connection, order_id: u64) -> Option<Order> {
+        let order = self.order_book.orders.get(&usize::try_from(order_id).ok()?)?.clone();
+        (Some(&order.trader_name) == connection.trader.as_ref()).then_some(order)
+    }
+
+    // Runs the command through the journal and the book, which decides whether it is accepted
+    fn submit(&mut self, command: Command) -> (Outcome, Vec<Trade>) {
+        let trades_before = self.order_book.trades.len();
+        let outcome = match self.journal.as_mut() {
+            Some(journal) => self.order_book.apply(journal, command),
+            None => self.order_book.execute(command),
+        };
+        (outcome, self.order_book.trades[trades_before..].to_vec())
+    }
+}
+
+// Reports the fills of the client's own orders
+fn report_fills(connection: &mut Connection, trades: &[Trade]) {
+    let trader = connection.trader.clone();
+    for trade in trades {
+        for (order_id, owner) in [(trade.buy_order_id, &trade.buyer), (trade.sell_order_id, &trade.seller)] {
+            if Some(owner) == trader.as_ref() {
+                connection.send(|buffer| {
+                    Executed::encode(buffer, order_id as u64, trade.id as u64, trade.price, trade.amount)
+                });
+            }
+        }
+    }
+}
+
+// Reject reason of a command the book did not accept, anything but funds, traders and orders is the
+// instrument's session
+fn reject_code(reason: &str) -> u8 {
+    match reason {
+        settlement::INSUFFICIENT_FUNDS => reject_reason::INSUFFICIENT_FUNDS,
+        settlement::UNKNOWN_TRADER => reject_reason::UNKNOWN_TRADER,
+        UNKNOWN_ORDER => reject_reason::UNKNOWN_ORDER,
+        _ => reject_reason::SESSION,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deserialize::Deserialize;
+    use crate::order::Direction;
+    use crate::trader::Trader;
+    use std::net::TcpStream;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    struct Client {
+        stream: TcpStream,
+        buffer: Vec<u8>,
+    }
+
+    impl Client {
+        fn connect(addr: SocketAddr) -> Self {
+            let stream = TcpStream::connect(addr).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            Client {
+                stream,
+                buffer: Vec::new(),
+            }
+        }
+
+        fn send(&mut self, encode: impl FnOnce(&mut [u8]) -> usize) {
+            let mut buffer = [0u8; 64];
+            let len = encode(&mut buffer);
+            self.stream.write_all(&buffer[..len]).unwrap();
+        }
+
+        // Returns the raw bytes of the next message
+        fn receive(&mut self) -> Vec<u8> {
+            loop {
+                if let Ok((_, len)) = Message::decode(&self.buffer) {
+                    return self.buffer.drain(..len).collect();
+                }
+                let mut chunk = [0u8; 256];
+                let read = self.stream.read(&mut chunk).unwrap();
+                assert!(read > 0, "gateway closed the connection");
+                self.buffer.extend_from_slice(&chunk[..read]);
+            }
+        }
+    }
+
+    fn order(trader_name: &str, direction: Direction, price: u64, amount: u64) -> Order {
+        Order {
+            trader_name: trader_name.to_string(),
+            direction,
+            asset: AssetName::A,
+            price,
+            amount,
+            ..Default::default()
+        }
+    }
+
+    // Runs the gateway for one connection on its own thread
+    fn start_gateway(preloaded: Vec<Order>) -> (SocketAddr, mpsc::Receiver<Vec<String>>) {
+        let (addr_sender, addr_receiver) = mpsc::channel();
+        let (result_sender, result_receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut order_book = OrderBook {
+                users: ["C1 2000 10 5 15 0", "C2 1000 20 35 40 10"]
+                    .into_iter()
+                    .map(|serialized| Trader::deserialize(serialized.to_string()))
+                    .map(|trader| (trader.name.clone(), trader))
+                    .collect(),
+                ..Default::default()
+            };
+            for order in preloaded {
+                order_book.execute(Command::NewOrder(order));
+            }
+            let api_keys = [("key-c1", "C1"), ("key-c2", "C2")]
+                .into_iter()
+                .map(|(api_key, trader_name)| (api_key.to_string(), trader_name.to_string()))
+                .collect();
+            let mut gateway = BinaryGateway::bind("127.0.0.1:0", api_keys, order_book, None).unwrap();
+            addr_sender.send(gateway.local_addr().unwrap()).unwrap();
+            gateway.accept_one().unwrap();
+            let balances = gateway
+                .order_book
+                .users
+                .values()
+                .map(|trader| trader.serialize())
+                .collect();
+            result_sender.send(balances).unwrap();
+        });
+        (addr_receiver.recv().unwrap(), result_receiver)
+    }
+
+    #[test]
+    fn client_trades_through_the_gateway() {
+        // order 0 rests on the book for C2
+        let (addr, result) = start_gateway(vec![order("C2", Direction::Sell, 7, 20)]);
+        let mut client = Client::connect(addr);
+
+        client.send(|buffer| Login::encode(buffer, "C1", "key-c1"));
+        let login = client.receive();
+        assert!(matches!(Message::decode(&login), Ok((Message::LoginAccepted(accepted), _)) if accepted.trader() == "C1"));
+
+        client.send(|buffer| NewOrder::encode(buffer, 11, &order("C1", Direction::Buy, 7, 5)));
+        let accepted = client.receive();
+        let Ok((Message::Accepted(accepted), _)) = Message::decode(&accepted) else { panic!() };
+        assert_eq!((accepted.client_token(), accepted.order_id(), accepted.quantity()), (11, 1, 5));
+        let executed = client.receive();
+        let Ok((Message::Executed(executed), _)) = Message::decode(&executed) else { panic!() };
+        assert_eq!((executed.order_id(), executed.price(), executed.quantity()), (1, 7, 5));
+
+        client.send(|buffer| NewOrder::encode(buffer, 12, &order("C1", Direction::Buy, 5, 4)));
+        client.receive();
+        client.send(|buffer| Replace::encode(buffer, 2, 6, 3));
+        let replaced = client.receive();
+        let Ok((Message::Replaced(replaced), _)) = Message::decode(&replaced) else { panic!() };
+        assert_eq!((replaced.order_id(), replaced.price(), replaced.quantity()), (2, 6, 3));
+
+        client.send(|buffer| Cancel::encode(buffer, 2));
+        let cancelled = client.receive();
+        let Ok((Message::Cancelled(cancelled), _)) = Message::decode(&cancelled) else { panic!() };
+        assert_eq!((cancelled.order_id(), cancelled.quantity()), (2, 3));
+
+        // order 0 belongs to C2
+        client.send(|buffer| Cancel::encode(buffer, 0));
+        let rejected = client.receive();
+        let Ok((Message::Rejected(rejected), _)) = Message::decode(&rejected) else { panic!() };
+        assert_eq!((rejected.reason(), rejected.reference()), (reject_reason::UNKNOWN_ORDER, 0));
+
+        client.send(|buffer| NewOrder::encode(buffer, 13, &order("C1", Direction::Buy, 1000, 1000)));
+        let rejected = client.receive();
+        let Ok((Message::Rejected(rejected), _)) = Message::decode(&rejected) else { panic!() };
+        assert_eq!((rejected.reason(), rejected.reference()), (reject_reason::INSUFFICIENT_FUNDS, 13));
+
+        // the rejected order took no id, and the book refuses a replacement C1 cannot cover
+        client.send(|buffer| NewOrder::encode(buffer, 14, &order("C1", Direction::Sell, 9, 1)));
+        let accepted = client.receive();
+        let Ok((Message::Accepted(accepted), _)) = Message::decode(&accepted) else { panic!() };
+        assert_eq!((accepted.client_token(), accepted.order_id()), (14, 3));
+        client.send(|buffer| Replace::encode(buffer, 3, 9, 100));
+        let rejected = client.receive();
+        let Ok((Message::Rejected(rejected), _)) = Message::decode(&rejected) else { panic!() };
+        assert_eq!((rejected.reason(), rejected.reference()), (reject_reason::INSUFFICIENT_FUNDS, 3));
+        client.send(|buffer| Cancel::encode(buffer, 3));
+        client.receive();
+
+        drop(client);
+        assert_eq!(
+            result.recv().unwrap(),
+            vec!["C1 1965 15 5 15 0".to_string(), "C2 1035 0 35 40 10".to_string()]
+        );
+    }
+
+    #[test]
+    fn orders_before_login_close_the_connection() {
+        let (addr, result) = start_gateway(Vec::new());
+        let mut client = Client::connect(addr);
+
+        client.send(|buffer| NewOrder::encode(buffer, 1, &order("C1", Direction::Buy, 7, 5)));
+        let rejected = client.receive();
+        let Ok((Message::Rejected(rejected), _)) = Message::decode(&rejected) else { panic!() };
+        assert_eq!(rejected.reason(), reject_reason::NOT_LOGGED_IN);
+
+        let mut chunk = [0u8; 16];
+        assert_eq!(client.stream.read(&mut chunk).unwrap(), 0);
+        assert_eq!(result.recv().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn login_with_the_api_key_of_another_trader_closes_the_connection() {
+        let (addr, result) = start_gateway(Vec::new());
+        let mut client = Client::connect(addr);
+
+        client.send(|buffer| Login::encode(buffer, "C1", "key-c2"));
+        let rejected = client.receive();
+        let Ok((Message::Rejected(rejected), _)) = Message::decode(&rejected) else { panic!() };
+        assert_eq!(rejected.reason(), reject_reason::UNKNOWN_TRADER);
+
+        let mut chunk = [0u8; 16];
+        assert_eq!(client.stream.read(&mut chunk).unwrap(), 0);
+        assert_eq!(result.recv().unwrap().len(), 2);
+    }
+}
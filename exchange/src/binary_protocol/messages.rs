@@ -0,0 +1,525 @@
+use crate::asset_name::AssetName;
+use crate::order::{Direction, Order, TimeInForce};
+
+// Layouts are described in docs/binary_protocol.md. Every message starts with
+//   length: u16, msg_type: u8, reserved: u8
+// and all integers are little-endian at fixed offsets.
+pub const HEADER_LEN: usize = 4;
+
+pub mod msg_type {
+    pub const LOGIN: u8 = b'L';
+    pub const NEW_ORDER: u8 = b'O';
+    pub const CANCEL: u8 = b'X';
+    pub const REPLACE: u8 = b'U';
+
+    pub const LOGIN_ACCEPTED: u8 = b'a';
+    pub const ACCEPTED: u8 = b'A';
+    pub const CANCELLED: u8 = b'C';
+    pub const REPLACED: u8 = b'R';
+    pub const REJECTED: u8 = b'J';
+    pub const EXECUTED: u8 = b'E';
+}
+
+pub mod reject_reason {
+    pub const NOT_LOGGED_IN: u8 = 1;
+    pub const INVALID_ORDER: u8 = 2;
+    pub const INSUFFICIENT_FUNDS: u8 = 3;
+    pub const UNKNOWN_ORDER: u8 = 4;
+    pub const UNKNOWN_TRADER: u8 = 5;
+    // the instrument's session does not accept the order now
+    pub const SESSION: u8 = 6;
+}
+
+pub const TRADER_LEN: usize = 8;
+pub const API_KEY_LEN: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    Truncated,
+    BadLength(usize),
+    UnknownType(u8),
+}
+
+fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buffer[offset..offset + 8].try_into().unwrap())
+}
+
+fn write_u64(buffer: &mut [u8], offset: usize, value: u64) {
+    buffer[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+// ASCII field zero padded to its length
+fn read_text(buffer: &[u8]) -> &str {
+    let len = buffer.iter().position(|byte| *byte == 0).unwrap_or(buffer.len());
+    std::str::from_utf8(&buffer[..len]).unwrap_or_default()
+}
+
+fn write_text(buffer: &mut [u8], text: &str) {
+    let text = &text.as_bytes()[..text.len().min(buffer.len())];
+    buffer[..text.len()].copy_from_slice(text);
+}
+
+fn write_header(buffer: &mut [u8], len: usize, msg_type: u8) {
+    buffer[..len].fill(0);
+    buffer[0..2].copy_from_slice(&(len as u16).to_le_bytes());
+    buffer[2] = msg_type;
+}
+
+fn side_byte(direction: Direction) -> u8 {
+    match direction {
+        Direction::Buy => b'B',
+        Direction::Sell => b'S',
+    }
+}
+
+fn side_from_byte(side: u8) -> Option<Direction> {
+    match side {
+        b'B' => Some(Direction::Buy),
+        b'S' => Some(Direction::Sell),
+        _ => None,
+    }
+}
+
+fn asset_from_byte(asset: u8) -> Option<AssetName> {
+    let mut asset_name = AssetName::A;
+    while asset_name != AssetName::Unknown {
+        if asset_name.index() == asset as usize {
+            return Some(asset_name);
+        }
+        asset_name = asset_name.next();
+    }
+    None
+}
+
+// Declares a borrowed view over one message with its fixed length and type
+macro_rules! message_view {
+    ($name:ident, $len:expr, $msg_type:expr) => {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub struct $name<'a>(&'a [u8]);
+
+        impl<'a> $name<'a> {
+            pub const LEN: usize = $len;
+            pub const MSG_TYPE: u8 = $msg_type;
+
+            pub fn bytes(&self) -> &'a [u8] {
+                self.0
+            }
+        }
+    };
+}
+
+message_view!(Login, 44, msg_type::LOGIN);
+message_view!(NewOrder, 32, msg_type::NEW_ORDER);
+message_view!(Cancel, 16, msg_type::CANCEL);
+message_view!(Replace, 32, msg_type::REPLACE);
+message_view!(LoginAccepted, 12, msg_type::LOGIN_ACCEPTED);
+message_view!(Accepted, 40, msg_type::ACCEPTED);
+message_view!(Cancelled, 24, msg_type::CANCELLED);
+message_view!(Replaced, 32, msg_type::REPLACED);
+message_view!(Rejected, 16, msg_type::REJECTED);
+message_view!(Executed, 40, msg_type::EXECUTED);
+
+impl<'a> Login<'a> {
+    pub fn trader(&self) -> &'a str {
+        read_text(&self.0[4..4 + TRADER_LEN])
+    }
+
+    pub fn api_key(&self) -> &'a str {
+        read_text(&self.0[12..12 + API_KEY_LEN])
+    }
+
+    pub fn encode(buffer: &mut [u8], trader: &str, api_key: &str) -> usize {
+        write_header(buffer, Self::LEN, Self::MSG_TYPE);
+        write_text(&mut buffer[4..4 + TRADER_LEN], trader);
+        write_text(&mut buffer[12..12 + API_KEY_LEN], api_key);
+        Self::LEN
+    }
+}
+
+impl<'a> LoginAccepted<'a> {
+    pub fn trader(&self) -> &'a str {
+        read_text(&self.0[4..4 + TRADER_LEN])
+    }
+
+    pub fn encode(buffer: &mut [u8], trader: &str) -> usize {
+        write_header(buffer, Self::LEN, Self::MSG_TYPE);
+        write_text(&mut buffer[4..4 + TRADER_LEN], trader);
+        Self::LEN
+    }
+}
+
+impl<'a> NewOrder<'a> {
+    pub fn side(&self) -> Option<Direction> {
+        side_from_byte(self.0[4])
+    }
+
+    pub fn asset(&self) -> Option<AssetName> {
+        asset_from_byte(self.0[5])
+    }
+
+    pub fn client_token(&self) -> u64 {
+        read_u64(self.0, 8)
+    }
+
+    pub fn price(&self) -> u64 {
+        read_u64(self.0, 16)
+    }
+
+    pub fn quantity(&self) -> u64 {
+        read_u64(self.0, 24)
+    }
+
+    pub fn encode(buffer: &mut [u8], client_token: u64, order: &Order) -> usize {
+        write_header(buffer, Self::LEN, Self::MSG_TYPE);
+        buffer[4] = side_byte(order.direction);
+        buffer[5] = order.asset.index() as u8;
+        write_u64(buffer, 8, client_token);
+        write_u64(buffer, 16, order.price);
+        write_u64(buffer, 24, order.amount);
+        Self::LEN
+    }
+
+    pub fn to_order(&self, trader_name: &str) -> Option<Order> {
+        Some(Order {
+            id: usize::MAX,
+            trader_name: trader_name.to_string(),
+            direction: self.side()?,
+            asset: self.asset()?,
+            price: self.price(),
+            amount: self.quantity(),
+            entry_sequence: 0,
+            time_in_force: TimeInForce::Gtc,
+            entry_timestamp: 0,
+            update_timestamp: 0,
+            client_order_id: None,
+        })
+    }
+}
+
+impl<'a> Cancel<'a> {
+    pub fn order_id(&self) -> u64 {
+        read_u64(self.0, 8)
+    }
+
+    pub fn encode(buffer: &mut [u8], order_id: u64) -> usize {
+        write_header(buffer, Self::LEN, Self::MSG_TYPE);
+        write_u64(buffer, 8, order_id);
+        Self::LEN
+    }
+}
+
+impl<'a> Replace<'a> {
+    pub fn order_id(&self) -> u64 {
+        read_u64(self.0, 8)
+    }
+
+    pub fn price(&self) -> u64 {
+        read_u64(self.0, 16)
+    }
+
+    pub fn quantity(&self) -> u64 {
+        read_u64(self.0, 24)
+    }
+
+    pub fn encode(buffer: &mut [u8], order_id: u64, price: u64, quantity: u64) -> usize {
+        write_header(buffer, Self::LEN, Self::MSG_TYPE);
+        write_u64(buffer, 8, order_id);
+        write_u64(buffer, 16, price);
+        write_u64(buffer, 24, quantity);
+        Self::LEN
+    }
+}
+
+impl<'a> Accepted<'a> {
+    pub fn side(&self) -> Option<Direction> {
+        side_from_byte(self.0[4])
+    }
+
+    pub fn asset(&self) -> Option<AssetName> {
+        asset_from_byte(self.0[5])
+    }
+
+    pub fn client_token(&self) -> u64 {
+        read_u64(self.0, 8)
+    }
+
+    pub fn order_id(&self) -> u64 {
+        read_u64(self.0, 16)
+    }
+
+    pub fn price(&self) -> u64 {
+        read_u64(self.0, 24)
+    }
+
+    pub fn quantity(&self) -> u64 {
+        read_u64(self.0, 32)
+    }
+
+    pub fn encode(buffer: &mut [u8], client_token: u64, order: &Order) -> usize {
+        write_header(buffer, Self::LEN, Self::MSG_TYPE);
+        buffer[4] = side_byte(order.direction);
+        buffer[5] = order.asset.index() as u8;
+        write_u64(buffer, 8, client_token);
+        write_u64(buffer, 16, order.id as u64);
+        write_u64(buffer, 24, order.price);
+        write_u64(buffer, 32, order.amount);
+        Self::LEN
+    }
+}
+
+impl<'a> Cancelled<'a> {
+    pub fn order_id(&self) -> u64 {
+        read_u64(self.0, 8)
+    }
+
+    pub fn quantity(&self) -> u64 {
+        read_u64(self.0, 16)
+    }
+
+    pub fn encode(buffer: &mut [u8], order_id: u64, quantity: u64) -> usize {
+        write_header(buffer, Self::LEN, Self::MSG_TYPE);
+        write_u64(buffer, 8, order_id);
+        write_u64(buffer, 16, quantity);
+        Self::LEN
+    }
+}
+
+impl<'a> Replaced<'a> {
+    pub fn order_id(&self) -> u64 {
+        read_u64(self.0, 8)
+    }
+
+    pub fn price(&self) -> u64 {
+        read_u64(self.0, 16)
+    }
+
+    pub fn quantity(&self) -> u64 {
+        read_u64(self.0, 24)
+    }
+
+    pub fn encode(buffer: &mut [u8], order_id: u64, price: u64, quantity: u64) -> usize {
+        Replace::encode(buffer, order_id, price, quantity);
+        buffer[2] = Self::MSG_TYPE;
+        Self::LEN
+    }
+}
+
+impl<'a> Rejected<'a> {
+    pub fn reason(&self) -> u8 {
+        self.0[4]
+    }
+
+    // client token for a rejected new order, order id otherwise
+    pub fn reference(&self) -> u64 {
+        read_u64(self.0, 8)
+    }
+
+    pub fn encode(buffer: &mut [u8], reason: u8, reference: u64) -> usize {
+        write_header(buffer, Self::LEN, Self::MSG_TYPE);
+        buffer[4] = reason;
+        write_u64(buffer, 8, reference);
+        Self::LEN
+    }
+}
+
+impl<'a> Executed<'a> {
+    pub fn order_id(&self) -> u64 {
+        read_u64(self.0, 8)
+    }
+
+    pub fn trade_id(&self) -> u64 {
+        read_u64(self.0, 16)
+    }
+
+    pub fn price(&self) -> u64 {
+        read_u64(self.0, 24)
+    }
+
+    pub fn quantity(&self) -> u64 {
+        read_u64(self.0, 32)
+    }
+
+    pub fn encode(buffer: &mut [u8], order_id: u64, trade_id: u64, price: u64, quantity: u64) -> usize {
+        write_header(buffer, Self::LEN, Self::MSG_TYPE);
+        write_u64(buffer, 8, order_id);
+        write_u64(buffer, 16, trade_id);
+        write_u64(buffer, 24, price);
+        write_u64(buffer, 32, quantity);
+        Self::LEN
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Message<'a> {
+    Login(Login<'a>),
+    NewOrder(NewOrder<'a>),
+    Cancel(Cancel<'a>),
+    Replace(Replace<'a>),
+    LoginAccepted(LoginAccepted<'a>),
+    Accepted(Accepted<'a>),
+    Cancelled(Cancelled<'a>),
+    Replaced(Replaced<'a>),
+    Rejected(Rejected<'a>),
+    Executed(Executed<'a>),
+}
+
+impl<'a> Message<'a> {
+    // Borrows the first message in the buffer without copying it and returns its length,
+    // Truncated means more bytes are needed
+    pub fn decode(buffer: &'a [u8]) -> Result<(Message<'a>, usize), DecodeError> {
+        if buffer.len() < HEADER_LEN {
+            return Err(DecodeError::Truncated);
+        }
+        let len = u16::from_le_bytes([buffer[0], buffer[1]]) as usize;
+        let expected = match buffer[2] {
+            msg_type::LOGIN => Login::LEN,
+            msg_type::NEW_ORDER => NewOrder::LEN,
+            msg_type::CANCEL => Cancel::LEN,
+            msg_type::REPLACE => Replace::LEN,
+            msg_type::LOGIN_ACCEPTED => LoginAccepted::LEN,
+            msg_type::ACCEPTED => Accepted::LEN,
+            msg_type::CANCELLED => Cancelled::LEN,
+            msg_type::REPLACED => Replaced::LEN,
+            msg_type::REJECTED => Rejected::LEN,
+            msg_type::EXECUTED => Executed::LEN,
+            unknown => return Err(DecodeError::UnknownType(unknown)),
+        };
+        if len != expected {
+            return Err(DecodeError::BadLength(len));
+        }
+        if buffer.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+        let bytes = &buffer[..len];
+        let message = match buffer[2] {
+            msg_type::LOGIN => Message::Login(Login(bytes)),
+            msg_type::NEW_ORDER => Message::NewOrder(NewOrder(bytes)),
+            msg_type::CANCEL => Message::Cancel(Cancel(bytes)),
+            msg_type::REPLACE => Message::Replace(Replace(bytes)),
+            msg_type::LOGIN_ACCEPTED => Message::LoginAccepted(LoginAccepted(bytes)),
+            msg_type::ACCEPTED => Message::Accepted(Accepted(bytes)),
+            msg_type::CANCELLED => Message::Cancelled(Cancelled(bytes)),
+            msg_type::REPLACED => Message::Replaced(Replaced(bytes)),
+            msg_type::REJECTED => Message::Rejected(Rejected(bytes)),
+            _ => Message::Executed(Executed(bytes)),
+        };
+        Ok((message, len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order() -> Order {
+        Order {
+            id: 42,
+            trader_name: "C1".to_string(),
+            direction: Direction::Sell,
+            asset: AssetName::C,
+            price: 1_000_000_007,
+            amount: 12,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn new_order_round_trips_to_order() {
+        let mut buffer = [0u8; 64];
+        let len = NewOrder::encode(&mut buffer, 7, &order());
+
+        let Ok((Message::NewOrder(new_order), consumed)) = Message::decode(&buffer[..len]) else {
+            panic!("expected a new order");
+        };
+
+        assert_eq!(consumed, NewOrder::LEN);
+        assert_eq!(new_order.client_token(), 7);
+        assert_eq!(new_order.to_order("C1"), Some(Order { id: usize::MAX, ..order() }));
+    }
+
+    #[test]
+    fn new_order_layout_is_little_endian_at_fixed_offsets() {
+        let mut buffer = [0u8; NewOrder::LEN];
+        NewOrder::encode(&mut buffer, 0x0102, &order());
+
+        assert_eq!(&buffer[..8], &[32, 0, b'O', 0, b'S', 2, 0, 0]);
+        assert_eq!(&buffer[8..10], &[0x02, 0x01]);
+        assert_eq!(&buffer[16..24], &1_000_000_007u64.to_le_bytes());
+        assert_eq!(&buffer[24..32], &12u64.to_le_bytes());
+    }
+
+    #[test]
+    fn accepted_round_trips_order_fields() {
+        let mut buffer = [0u8; Accepted::LEN];
+        Accepted::encode(&mut buffer, 7, &order());
+
+        let Ok((Message::Accepted(accepted), _)) = Message::decode(&buffer) else {
+            panic!("expected an accepted message");
+        };
+
+        assert_eq!(
+            (accepted.side(), accepted.asset(), accepted.client_token(), accepted.order_id()),
+            (Some(Direction::Sell), Some(AssetName::C), 7, 42)
+        );
+        assert_eq!((accepted.price(), accepted.quantity()), (1_000_000_007, 12));
+    }
+
+    #[test]
+    fn every_message_type_round_trips() {
+        let mut buffer = [0u8; 64];
+
+        let len = Login::encode(&mut buffer, "C12345678", "c1-7f3a9d2e51b84c06");
+        let Ok((Message::Login(login), 44)) = Message::decode(&buffer[..len]) else { panic!() };
+        assert_eq!((login.trader(), login.api_key()), ("C1234567", "c1-7f3a9d2e51b84c06"));
+
+        let len = LoginAccepted::encode(&mut buffer, "C1");
+        assert!(matches!(Message::decode(&buffer[..len]), Ok((Message::LoginAccepted(login), _)) if login.trader() == "C1"));
+
+        let len = Cancel::encode(&mut buffer, 9);
+        assert!(matches!(Message::decode(&buffer[..len]), Ok((Message::Cancel(cancel), _)) if cancel.order_id() == 9));
+
+        let len = Replace::encode(&mut buffer, 9, 10, 11);
+        let Ok((Message::Replace(replace), _)) = Message::decode(&buffer[..len]) else { panic!() };
+        assert_eq!((replace.order_id(), replace.price(), replace.quantity()), (9, 10, 11));
+
+        let len = Cancelled::encode(&mut buffer, 9, 3);
+        let Ok((Message::Cancelled(cancelled), _)) = Message::decode(&buffer[..len]) else { panic!() };
+        assert_eq!((cancelled.order_id(), cancelled.quantity()), (9, 3));
+
+        let len = Replaced::encode(&mut buffer, 9, 10, 11);
+        let Ok((Message::Replaced(replaced), _)) = Message::decode(&buffer[..len]) else { panic!() };
+        assert_eq!((replaced.order_id(), replaced.price(), replaced.quantity()), (9, 10, 11));
+
+        let len = Rejected::encode(&mut buffer, reject_reason::INSUFFICIENT_FUNDS, 7);
+        let Ok((Message::Rejected(rejected), _)) = Message::decode(&buffer[..len]) else { panic!() };
+        assert_eq!((rejected.reason(), rejected.reference()), (reject_reason::INSUFFICIENT_FUNDS, 7));
+
+        let len = Executed::encode(&mut buffer, 9, 1, 10, 2);
+        let Ok((Message::Executed(executed), _)) = Message::decode(&buffer[..len]) else { panic!() };
+        assert_eq!((executed.order_id(), executed.trade_id(), executed.price(), executed.quantity()), (9, 1, 10, 2));
+    }
+
+    #[test]
+    fn decode_reports_truncated_and_malformed_input() {
+        let mut buffer = [0u8; NewOrder::LEN];
+        NewOrder::encode(&mut buffer, 7, &order());
+
+        assert_eq!(Message::decode(&buffer[..3]), Err(DecodeError::Truncated));
+        assert_eq!(Message::decode(&buffer[..20]), Err(DecodeError::Truncated));
+        buffer[0] = 31;
+        assert_eq!(Message::decode(&buffer), Err(DecodeError::BadLength(31)));
+        buffer[2] = b'?';
+        assert_eq!(Message::decode(&buffer), Err(DecodeError::UnknownType(b'?')));
+    }
+
+    #[test]
+    fn unknown_side_or_asset_is_not_an_order() {
+        let mut buffer = [0u8; NewOrder::LEN];
+        NewOrder::encode(&mut buffer, 7, &order());
+        buffer[5] = 9;
+
+        let Ok((Message::NewOrder(new_order), _)) = Message::decode(&buffer) else { panic!() };
+
+        assert_eq!(new_order.to_order("C1"), None);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::asset_name::AssetName;
+use crate::trade::Trade;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+pub const CSV_HEADER: &str = "asset,start,end,open,high,low,close,volume,vwap,trades";
+
+// When a bar closes: on the first trade of the next interval, after a number of trades,
+// or once the traded amount reaches the threshold (the trade that crosses it stays in the bar)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarKind {
+    Time { millis: u64 },
+    Ticks(u64),
+    Volume(u64),
+}
+
+// "500ms", "1s", "1m", "1h", "100t" for tick bars and "1000v" for volume bars
+impl FromStr for BarKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (count, unit) = s.split_at(split);
+        let count: u64 = count
+            .parse()
+            .ok()
+            .filter(|count| *count > 0)
+            .ok_or_else(|| format!("Bad bar size: {}", s))?;
+        let millis = |unit_millis: u64| BarKind::Time {
+            millis: count * unit_millis,
+        };
+        match unit {
+            "ms" => Ok(millis(1)),
+            "s" => Ok(millis(1_000)),
+            "m" => Ok(millis(60_000)),
+            "h" => Ok(millis(3_600_000)),
+            "t" => Ok(BarKind::Ticks(count)),
+            "v" => Ok(BarKind::Volume(count)),
+            _ => Err(format!("Bad bar unit: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for BarKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            BarKind::Time { millis } if millis % 3_600_000 == 0 => write!(f, "{}h", millis / 3_600_000),
+            BarKind::Time { millis } if millis % 60_000 == 0 => write!(f, "{}m", millis / 60_000),
+            BarKind::Time { millis } if millis % 1_000 == 0 => write!(f, "{}s", millis / 1_000),
+            BarKind::Time { millis } => write!(f, "{}ms", millis),
+            BarKind::Ticks(count) => write!(f, "{}t", count),
+            BarKind::Volume(amount) => write!(f, "{}v", amount),
+        }
+    }
+}
+
+// Timestamps are milliseconds. Time bars start at the interval boundary, other bars at their first trade.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candle {
+    pub asset: AssetName,
+    pub start: u64,
+    pub end: u64,
+    pub open: u64,
+    pub high: u64,
+    pub low: u64,
+    pub close: u64,
+    pub volume: u64,
+    pub notional: u64,
+    pub trade_count: u64,
+}
+
+impl Candle {
+    fn new(asset: AssetName, start: u64, trade: &Trade, timestamp: u64) -> Self {
+        Candle {
+            asset,
+            start,
+            end: timestamp,
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: trade.amount,
+            notional: trade.price * trade.amount,
+            trade_count: 1,
+        }
+    }
+
+    fn add(&mut self, trade: &Trade, timestamp: u64) {
+        self.end = timestamp;
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.close = trade.price;
+        self.volume += trade.amount;
+        self.notional += trade.price * trade.amount;
+        self.trade_count += 1;
+    }
+
+    pub fn vwap(&self) -> f64 {
+        self.notional as f64 / self.volume as f64
+    }
+
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{:?},{},{},{},{},{},{},{},{:.4},{}",
+            self.asset,
+            self.start,
+            self.end,
+            self.open,
+            self.high,
+            self.low,
+            self.close,
+            self.volume,
+            self.vwap(),
+            self.trade_count
+        )
+    }
+}
+
+// Builds bars of one kind per asset from the trade stream
+pub struct CandleAggregator {
+    pub kind: BarKind,
+    pub completed: Vec<Candle>,
+    current: BTreeMap<AssetName, Candle>,
+    csv: Option<LineWriter<File>>,
+}
+
+impl CandleAggregator {
+    pub fn new(kind: BarKind) -> Self {
+        CandleAggregator {
+            kind,
+            completed: Vec::new(),
+            current: BTreeMap::new(),
+            csv: None,
+        }
+    }
+
+    // Appends every completed bar to the file, the header is written to a new file only
+    pub fn with_csv<P: AsRef<Path>>(mut self, path: P) -> io::Result<Self> {
+        let exists = path.as_ref().exists();
+        let mut file = LineWriter::new(OpenOptions::new().create(true).append(true).open(path)?);
+        if !exists {
+            writeln!(file, "{}", CSV_HEADER)?;
+        }
+        self.csv = Some(file);
+        Ok(self)
+    }
+
+    pub fn on_trade(&mut self, trade: &Trade, timestamp: u64) {
+        let start = match self.kind {
+            BarKind::Time { millis } => timestamp - timestamp % millis,
+            BarKind::Ticks(_) | BarKind::Volume(_) => timestamp,
+        };
+        let same_interval = |candle: &Candle| !matches!(self.kind, BarKind::Time { .. }) || candle.start == start;
+        match self.current.get_mut(&trade.asset) {
+            Some(candle) if same_interval(candle) => candle.add(trade, timestamp),
+            _ => {
+                if let Some(candle) = self.current.remove(&trade.asset) {
+                    self.complete(candle);
+                }
+                self.current.insert(trade.asset, Candle::new(trade.asset, start, trade, timestamp));
+            }
+        }
+
+        let candle = &self.current[&trade.asset];
+        let full = match self.kind {
+            BarKind::Time { .. } => false,
+            BarKind::Ticks(count) => candle.trade_count >= count,
+            BarKind::Volume(amount) => candle.volume >= amount,
+        };
+        if full {
+            let candle = self.current.remove(&trade.asset).unwrap();
+            self.complete(candle);
+        }
+    }
+
+    fn complete(&mut self, candle: Candle) {
+        if let Some(csv) = self.csv.as_mut() {
+            // losing the file must not stop trading, the bar stays queryable
+            let _ = writeln!(csv, "{}", candle.to_csv());
+        }
+        self.completed.push(candle);
+    }
+
+    // Completed bars of the asset followed by the one still being built
+    pub fn candles(&self, asset: AssetName) -> Vec<Candle> {
+        self.completed
+            .iter()
+            .chain(self.current.get(&asset))
+            .filter(|candle| candle.asset == asset)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(asset: AssetName, price: u64, amount: u64) -> Trade {
+        Trade {
+            asset,
+            price,
+            amount,
+            ..Default::default()
+        }
+    }
+
+    fn ohlcv(candle: &Candle) -> (u64, u64, u64, u64, u64, u64) {
+        (candle.open, candle.high, candle.low, candle.close, candle.volume, candle.trade_count)
+    }
+
+    #[test]
+    fn bar_kinds_parse_and_display() {
+        assert_eq!("1s".parse(), Ok(BarKind::Time { millis: 1_000 }));
+        assert_eq!("1m".parse(), Ok(BarKind::Time { millis: 60_000 }));
+        assert_eq!("1h".parse(), Ok(BarKind::Time { millis: 3_600_000 }));
+        assert_eq!("250ms".parse(), Ok(BarKind::Time { millis: 250 }));
+        assert_eq!("100t".parse(), Ok(BarKind::Ticks(100)));
+        assert_eq!("1000v".parse(), Ok(BarKind::Volume(1000)));
+        assert!("0s".parse::<BarKind>().is_err());
+        assert!("1d".parse::<BarKind>().is_err());
+        assert!("m".parse::<BarKind>().is_err());
+        for kind in ["1s", "1m", "1h", "250ms", "100t", "1000v"] {
+            assert_eq!(kind.parse::<BarKind>().unwrap().to_string(), kind);
+        }
+    }
+
+    #[test]
+    fn time_bars_split_on_interval_boundaries_per_asset() {
+        let mut aggregator = CandleAggregator::new("1s".parse().unwrap());
+        aggregator.on_trade(&trade(AssetName::A, 10, 2), 1_200);
+        aggregator.on_trade(&trade(AssetName::B, 50, 1), 1_300);
+        aggregator.on_trade(&trade(AssetName::A, 12, 1), 1_500);
+        aggregator.on_trade(&trade(AssetName::A, 9, 3), 1_999);
+        aggregator.on_trade(&trade(AssetName::A, 11, 4), 3_100);
+
+        let candles = aggregator.candles(AssetName::A);
+        assert_eq!(candles.len(), 2);
+        assert_eq!((candles[0].start, candles[0].end), (1_000, 1_999));
+        assert_eq!(ohlcv(&candles[0]), (10, 12, 9, 9, 6, 3));
+        assert_eq!(candles[0].vwap(), (20 + 12 + 27) as f64 / 6.0);
+        assert_eq!((candles[1].start, ohlcv(&candles[1])), (3_000, (11, 11, 11, 11, 4, 1)));
+        assert_eq!(aggregator.candles(AssetName::B).len(), 1);
+    }
+
+    #[test]
+    fn tick_and_volume_bars_close_on_their_thresholds() {
+        let mut ticks = CandleAggregator::new(BarKind::Ticks(2));
+        let mut volume = CandleAggregator::new(BarKind::Volume(5));
+        for (timestamp, (price, amount)) in [(10, 3), (11, 1), (12, 4), (13, 2), (14, 1)].into_iter().enumerate() {
+            ticks.on_trade(&trade(AssetName::A, price, amount), timestamp as u64);
+            volume.on_trade(&trade(AssetName::A, price, amount), timestamp as u64);
+        }
+
+        let tick_bars: Vec<_> = ticks.candles(AssetName::A).iter().map(ohlcv).collect();
+        assert_eq!(tick_bars, vec![(10, 11, 10, 11, 4, 2), (12, 13, 12, 13, 6, 2), (14, 14, 14, 14, 1, 1)]);
+        assert_eq!(ticks.completed.len(), 2);
+
+        let volume_bars: Vec<_> = volume.candles(AssetName::A).iter().map(ohlcv).collect();
+        assert_eq!(volume_bars, vec![(10, 12, 10, 12, 8, 3), (13, 14, 13, 14, 3, 2)]);
+    }
+
+    #[test]
+    fn completed_bars_are_appended_to_csv() {
+        let path = std::env::temp_dir().join("exchange_candles.csv");
+        let _ = std::fs::remove_file(&path);
+        let mut aggregator = CandleAggregator::new(BarKind::Ticks(1)).with_csv(&path).unwrap();
+        aggregator.on_trade(&trade(AssetName::C, 8, 4), 5);
+        drop(aggregator);
+        let mut aggregator = CandleAggregator::new(BarKind::Ticks(1)).with_csv(&path).unwrap();
+        aggregator.on_trade(&trade(AssetName::C, 9, 1), 6);
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\nC,5,5,8,8,8,8,4,8.0000,1\nC,6,6,9,9,9,9,1,9.0000,1\n", CSV_HEADER)
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+// Source of the current time in nanoseconds since the Unix epoch
+pub trait Clock: Send {
+    fn now(&self) -> u64;
+
+    // Seconds since midnight UTC
+    fn second_of_day(&self) -> u64 {
+        self.now() / NANOS_PER_SECOND % SECONDS_PER_DAY
+    }
+}
+
+// A book or engine nobody gave a clock to runs on simulated time from 0, so tests are repeatable
+impl Default for Box<dyn Clock> {
+    fn default() -> Self {
+        Box::new(SimulatedClock::default())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+    }
+}
+
+// Time that only moves when it is told to. Clones share it, so a test can keep one and hand another out.
+#[derive(Debug, Default, Clone)]
+pub struct SimulatedClock(Arc<AtomicU64>);
+
+impl SimulatedClock {
+    pub fn new(now: u64) -> Self {
+        SimulatedClock(Arc::new(AtomicU64::new(now)))
+    }
+
+    pub fn set(&self, now: u64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, nanos: u64) {
+        self.0.fetch_add(nanos, Ordering::SeqCst);
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
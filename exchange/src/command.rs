@@ -0,0 +1,138 @@
+use crate::asset_name::AssetName;
+use crate::order::Order;
+use crate::order_matching_system::session::SessionState;
+use std::fmt;
+use std::str::FromStr;
+
+// A live order as a command names it: by the id the exchange gave it, or by the trader's own id for it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum OrderRef {
+    Id(usize),
+    Client { trader_name: String, client_order_id: String },
+}
+
+impl OrderRef {
+    // "<order id>" or "<trader> <client order id>"
+    fn parse(parts: &[&str]) -> Option<OrderRef> {
+        match parts {
+            [order_id] => Some(OrderRef::Id(order_id.parse().ok()?)),
+            [trader_name, client_order_id] => Some(OrderRef::Client {
+                trader_name: trader_name.to_string(),
+                client_order_id: client_order_id.to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl From<usize> for OrderRef {
+    fn from(order_id: usize) -> Self {
+        OrderRef::Id(order_id)
+    }
+}
+
+impl fmt::Display for OrderRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderRef::Id(order_id) => write!(f, "{}", order_id),
+            OrderRef::Client { trader_name, client_order_id } => write!(f, "{} {}", trader_name, client_order_id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    NewOrder(Order),
+    Cancel { order: OrderRef },
+    Amend { order: OrderRef, price: u64, amount: u64 },
+    // asset None deposits usd
+    Deposit { trader_name: String, asset: Option<AssetName>, amount: u64 },
+    // moves the instrument to another session state, by the schedule or forced by an admin
+    Session { asset: AssetName, state: SessionState },
+}
+
+impl Command {
+    pub fn serialize(&self) -> String {
+        match self {
+            Command::NewOrder(order) => format!("new {}", order.serialize()),
+            Command::Cancel { order } => format!("cancel {}", order),
+            Command::Amend { order, price, amount } => {
+                format!("amend {} {} {}", order, price, amount)
+            }
+            Command::Deposit { trader_name, asset, amount } => {
+                let asset = match asset {
+                    Some(asset) => format!("{:?}", asset),
+                    None => "USD".to_string(),
+                };
+                format!("deposit {} {} {}", trader_name, asset, amount)
+            }
+            Command::Session { asset, state } => format!("session {:?} {}", asset, state),
+        }
+    }
+
+    pub fn parse(serialized_str: &str) -> Option<Command> {
+        let (kind, rest) = serialized_str.split_once(' ')?;
+        let parts: Vec<&str> = rest.split(' ').collect();
+        match (kind, parts.len()) {
+            ("new", 5..=7) => Some(Command::NewOrder(Order::try_parse(rest)?)),
+            ("cancel", 1..=2) => Some(Command::Cancel {
+                order: OrderRef::parse(&parts)?,
+            }),
+            ("amend", 3..=4) => {
+                let (order, price_and_amount) = parts.split_at(parts.len() - 2);
+                Some(Command::Amend {
+                    order: OrderRef::parse(order)?,
+                    price: price_and_amount[0].parse().ok()?,
+                    amount: price_and_amount[1].parse().ok()?,
+                })
+            }
+            ("deposit", 3) => Some(Command::Deposit {
+                trader_name: parts[0].to_string(),
+                asset: match parts[1] {
+                    "USD" => None,
+                    asset => Some(AssetName::from_str(asset).ok()?),
+                },
+                amount: parts[2].parse().ok()?,
+            }),
+            ("session", 2) => Some(Command::Session {
+                asset: AssetName::from_str(parts[0]).ok()?,
+                state: parts[1].parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsed_commands_serialize_back() {
+        for serialized_str in [
+            "new C1 b A 8 10",
+            "cancel 3",
+            "amend 3 9 5",
+            "deposit C2 USD 100",
+            "deposit C2 B 4",
+            "new C1 s B 8 10 day",
+            "session A halted",
+            "new C1 b A 8 10 cl=o1",
+            "cancel C1 o1",
+            "amend C1 o1 9 5",
+        ] {
+            let command = Command::parse(serialized_str).unwrap();
+            assert_eq!(command.serialize(), serialized_str);
+        }
+    }
+
+    #[test]
+    fn malformed_command_is_not_parsed() {
+        assert_eq!(Command::parse("cancel"), None);
+        assert_eq!(Command::parse("cancel x"), None);
+        assert_eq!(Command::parse("amend x 9 5"), None);
+        assert_eq!(Command::parse("withdraw C1 USD 1"), None);
+        assert_eq!(Command::parse("session A lunch"), None);
+        assert_eq!(Command::parse("new C1 x A 8 10"), None);
+    }
+}
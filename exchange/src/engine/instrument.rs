@@ -0,0 +1,120 @@
+use crate::asset_name::AssetName;
+use crate::command::Command;
+use crate::engine::spsc::{self, Consumer, Producer};
+use crate::order_matching_system::listener::OrderEvent;
+use crate::order_matching_system::matching_policy::MatchingPolicy;
+use crate::order_matching_system::order_book::OrderBook;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+pub struct InstrumentInput {
+    pub sequence: u64,
+    // when the risk stage admitted the command, the book stamps what it causes with it
+    pub timestamp: u64,
+    pub command: Command,
+}
+
+// Events of one command in the order the book reported them. Trade ids are assigned when outputs are
+// merged, that is also where the events are settled.
+pub struct InstrumentOutput {
+    pub sequence: u64,
+    pub events: Vec<OrderEvent>,
+}
+
+// The book of one asset. It only queues and matches orders: commands reach it after the risk stage
+// has checked them, and it holds no traders, so its own settlement has no balances to move. Its fills
+// are reported from the merge, so the book keeps no trade log, market data, order records or PnL.
+pub struct InstrumentBook {
+    pub asset: AssetName,
+    pub order_book: OrderBook,
+    events: Arc<Mutex<Vec<OrderEvent>>>,
+}
+
+impl InstrumentBook {
+    pub fn new(asset: AssetName, policy: MatchingPolicy) -> Self {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut order_book = OrderBook {
+            matching_only: true,
+            ..Default::default()
+        };
+        order_book.matching_policies.insert(asset, policy);
+        order_book.add_listener(events.clone());
+        InstrumentBook {
+            asset,
+            order_book,
+            events,
+        }
+    }
+
+    pub fn process(&mut self, input: InstrumentInput) -> InstrumentOutput {
+        self.order_book.now = input.timestamp;
+        match input.command {
+            Command::NewOrder(order) => self.order_book.limit(order),
+            Command::Cancel { order } => {
+                if let Some(order_id) = self.order_book.client_order_ids.resolve(&order) {
+                    self.order_book.cancel(order_id);
+                }
+            }
+            Command::Amend { order, price, amount } => {
+                if let Some(order_id) = self.order_book.client_order_ids.resolve(&order) {
+                    self.order_book.amend(order_id, price, amount);
+                }
+            }
+            Command::Deposit { .. } => {}
+            Command::Session { asset, state } => self.order_book.set_session(asset, state),
+        }
+        InstrumentOutput {
+            sequence: input.sequence,
+            events: std::mem::take(&mut *self.events.lock().unwrap()),
+        }
+    }
+}
+
+// Runs the book on its own thread until the command queue is closed
+pub fn spawn(
+    asset: AssetName,
+    policy: MatchingPolicy,
+    capacity: usize,
+) -> (Producer<InstrumentInput>, Consumer<InstrumentOutput>, JoinHandle<()>) {
+    let (commands, mut inputs) = spsc::channel(capacity);
+    let (mut outputs, merged) = spsc::channel(capacity);
+    let worker = thread::Builder::new()
+        .name(format!("instrument-{:?}", asset))
+        .spawn(move || {
+            let mut book = InstrumentBook::new(asset, policy);
+            while let Some(input) = inputs.pop() {
+                outputs.push(book.process(input));
+            }
+        })
+        .expect("Couldn't spawn instrument thread");
+    (commands, merged, worker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn book_reports_fills_without_keeping_records_of_them() {
+        let mut book = InstrumentBook::new(AssetName::A, MatchingPolicy::Fifo);
+        let mut events = Vec::new();
+        for (sequence, serialized_command) in ["new C1 b A 8 10", "new C2 s A 8 4"].into_iter().enumerate() {
+            let mut command = Command::parse(serialized_command).unwrap();
+            if let Command::NewOrder(order) = &mut command {
+                order.id = sequence;
+            }
+            let input = InstrumentInput {
+                sequence: sequence as u64,
+                timestamp: 0,
+                command,
+            };
+            events.extend(book.process(input).events);
+        }
+
+        assert!(events.iter().any(|event| matches!(event, OrderEvent::Fill { .. })));
+        let order_book = &book.order_book;
+        assert_eq!(order_book.depth(AssetName::A, 1).bids[0].volume, 6);
+        assert!(order_book.trades.is_empty() && order_book.market_data.messages.is_empty());
+        assert!(order_book.order_store.records().is_empty() && order_book.pnl.positions.is_empty());
+    }
+}
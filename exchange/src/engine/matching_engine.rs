@@ -0,0 +1,408 @@
+use crate::asset_name::AssetName;
+use crate::clock::Clock;
+use crate::command::{Command, OrderRef};
+use crate::engine::instrument::{self, InstrumentBook, InstrumentInput, InstrumentOutput};
+use crate::engine::risk::{Admission, RiskStage};
+use crate::engine::spsc::{Consumer, Producer};
+use crate::order_matching_system::listener::OrderEvent;
+use crate::order_matching_system::matching_policy::MatchingPolicy;
+use crate::order_matching_system::session::SessionState;
+use crate::order_matching_system::settlement::Settlement;
+use crate::trade::Trade;
+use crate::trader::Trader;
+use std::collections::{BTreeMap, VecDeque};
+use std::thread::JoinHandle;
+
+pub const QUEUE_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineEvent {
+    Accepted { order_id: usize },
+    Cancelled { order_id: usize },
+    Amended { order_id: usize, price: u64, amount: u64 },
+    Deposited,
+    SessionChanged { asset: AssetName, state: SessionState },
+    Rejected { reason: &'static str },
+    Trade(Trade),
+}
+
+// Everything one command caused, outputs come out in the order the commands were submitted
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineOutput {
+    pub sequence: u64,
+    pub events: Vec<EngineEvent>,
+}
+
+enum Instrument {
+    // processed on the submitting thread as soon as the command is admitted
+    Inline {
+        book: Box<InstrumentBook>,
+        outputs: VecDeque<InstrumentOutput>,
+    },
+    Threaded {
+        commands: Option<Producer<InstrumentInput>>,
+        outputs: Consumer<InstrumentOutput>,
+        worker: Option<JoinHandle<()>>,
+    },
+}
+
+impl Instrument {
+    fn try_send(&mut self, input: InstrumentInput) -> Result<(), InstrumentInput> {
+        match self {
+            Instrument::Inline { book, outputs } => {
+                outputs.push_back(book.process(input));
+                Ok(())
+            }
+            Instrument::Threaded { commands, .. } => commands.as_mut().unwrap().try_push(input),
+        }
+    }
+
+    fn receive(&mut self, wait: bool) -> Option<InstrumentOutput> {
+        match self {
+            Instrument::Inline { outputs, .. } => outputs.pop_front(),
+            Instrument::Threaded { outputs, .. } if wait => Some(outputs.pop().expect("Instrument thread stopped")),
+            Instrument::Threaded { outputs, .. } => outputs.try_pop(),
+        }
+    }
+}
+
+impl Drop for Instrument {
+    fn drop(&mut self) {
+        if let Instrument::Threaded { commands, worker, .. } = self {
+            // closing the queue stops the thread once it has processed what is left
+            commands.take();
+            if let Some(worker) = worker.take() {
+                let _ = worker.join();
+            }
+        }
+    }
+}
+
+struct Pending {
+    sequence: u64,
+    events: Vec<EngineEvent>,
+    instrument: Option<AssetName>,
+}
+
+// Runs every instrument's book on its own thread behind the risk stage, which runs on the submitting
+// thread. A command is admitted only once the instruments it could depend on have drained (see
+// RiskStage::dependencies), and the instruments' outputs are merged back in submission order with
+// trade ids assigned there, so the outputs and balances do not depend on how the threads were scheduled.
+// The merged events settle the risk stage's accounts by the same Settlement as a single OrderBook.
+// The single-threaded engine runs the same pipeline with every book on the submitting thread.
+pub struct MatchingEngine {
+    pub risk: RiskStage,
+    threaded: bool,
+    instruments: BTreeMap<AssetName, Instrument>,
+    in_flight: BTreeMap<AssetName, usize>,
+    pending: VecDeque<Pending>,
+    sequence: u64,
+    trade_id_sequence: usize,
+    merged: Vec<EngineOutput>,
+    // commands are stamped when they are submitted, every instrument executes them as of that time
+    clock: Box<dyn Clock>,
+    // instruments without an entry match FIFO
+    matching_policies: BTreeMap<AssetName, MatchingPolicy>,
+}
+
+impl MatchingEngine {
+    pub fn threaded(accounts: BTreeMap<String, Trader>) -> Self {
+        Self::new(accounts, true)
+    }
+
+    pub fn single_threaded(accounts: BTreeMap<String, Trader>) -> Self {
+        Self::new(accounts, false)
+    }
+
+    fn new(accounts: BTreeMap<String, Trader>, threaded: bool) -> Self {
+        MatchingEngine {
+            risk: RiskStage::new(accounts),
+            threaded,
+            instruments: BTreeMap::new(),
+            in_flight: BTreeMap::new(),
+            pending: VecDeque::new(),
+            sequence: 0,
+            trade_id_sequence: 0,
+            merged: Vec::new(),
+            clock: Box::default(),
+            matching_policies: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    pub fn with_matching_policies(mut self, matching_policies: BTreeMap<AssetName, MatchingPolicy>) -> Self {
+        self.matching_policies = matching_policies;
+        self
+    }
+
+    // Returns the sequence number of the command's output
+    pub fn submit(&mut self, command: Command) -> u64 {
+        let sequence = self.sequence;
+        self.sequence += 1;
+        let timestamp = self.clock.now();
+
+        let dependencies = self.risk.dependencies(&command);
+        while dependencies.iter().any(|asset| self.in_flight.get(asset).is_some_and(|count| *count > 0)) {
+            self.merge_next(true);
+        }
+
+        let (event, route) = match self.risk.admit(command) {
+            Admission::Route(asset, command) => {
+                let event = match &command {
+                    Command::NewOrder(order) => EngineEvent::Accepted { order_id: order.id },
+                    Command::Cancel { order: OrderRef::Id(order_id) } => EngineEvent::Cancelled { order_id: *order_id },
+                    Command::Amend { order: OrderRef::Id(order_id), price, amount } => EngineEvent::Amended {
+                        order_id: *order_id,
+                        price: *price,
+                        amount: *amount,
+                    },
+                    Command::Cancel { .. } | Command::Amend { .. } => unreachable!("Risk routes orders by exchange id"),
+                    Command::Deposit { .. } => EngineEvent::Deposited,
+                    Command::Session { asset, state } => EngineEvent::SessionChanged {
+                        asset: *asset,
+                        state: *state,
+                    },
+                };
+                (event, Some((asset, command)))
+            }
+            Admission::Deposited => (EngineEvent::Deposited, None),
+            Admission::Rejected(reason) => (EngineEvent::Rejected { reason }, None),
+        };
+        let instrument = route.as_ref().map(|(asset, _)| *asset);
+        if let Some((asset, command)) = route {
+            self.dispatch(
+                asset,
+                InstrumentInput {
+                    sequence,
+                    timestamp,
+                    command,
+                },
+            );
+        }
+        self.pending.push_back(Pending {
+            sequence,
+            events: vec![event],
+            instrument,
+        });
+        self.merge_ready();
+        sequence
+    }
+
+    fn dispatch(&mut self, asset: AssetName, mut input: InstrumentInput) {
+        *self.in_flight.entry(asset).or_insert(0) += 1;
+        let threaded = self.threaded;
+        let policy = self.matching_policies.get(&asset).copied().unwrap_or_default();
+        loop {
+            let instrument = self.instruments.entry(asset).or_insert_with(|| {
+                if threaded {
+                    let (commands, outputs, worker) = instrument::spawn(asset, policy, QUEUE_CAPACITY);
+                    Instrument::Threaded {
+                        commands: Some(commands),
+                        outputs,
+                        worker: Some(worker),
+                    }
+                } else {
+                    Instrument::Inline {
+                        book: Box::new(InstrumentBook::new(asset, policy)),
+                        outputs: VecDeque::new(),
+                    }
+                }
+            });
+            match instrument.try_send(input) {
+                Ok(()) => return,
+                // the instrument is behind, taking its outputs lets it go on
+                Err(rejected) => {
+                    input = rejected;
+                    self.merge_next(true);
+                }
+            }
+        }
+    }
+
+    // Merges the oldest pending command if its output is there, or waits for it
+    fn merge_next(&mut self, wait: bool) -> bool {
+        let Some(pending) = self.pending.front() else {
+            return false;
+        };
+        let events = match pending.instrument {
+            None => Vec::new(),
+            Some(asset) => {
+                let Some(output) = self.instruments.get_mut(&asset).unwrap().receive(wait) else {
+                    return false;
+                };
+                debug_assert_eq!(output.sequence, pending.sequence);
+                *self.in_flight.get_mut(&asset).unwrap() -= 1;
+                output.events
+            }
+        };
+
+        let mut pending = self.pending.pop_front().unwrap();
+        for mut event in events {
+            if let OrderEvent::Fill { trade, .. } = &mut event {
+                trade.id = self.trade_id_sequence;
+                self.trade_id_sequence += 1;
+                pending.events.push(EngineEvent::Trade(trade.clone()));
+            }
+            event.deliver(&mut Settlement(&mut self.risk.accounts));
+            event.deliver(&mut self.risk);
+        }
+        self.merged.push(EngineOutput {
+            sequence: pending.sequence,
+            events: pending.events,
+        });
+        true
+    }
+
+    fn merge_ready(&mut self) {
+        while self.merge_next(false) {}
+    }
+
+    // Outputs merged so far, without waiting for the instruments
+    pub fn poll(&mut self) -> Vec<EngineOutput> {
+        self.merge_ready();
+        std::mem::take(&mut self.merged)
+    }
+
+    // Waits until every submitted command is merged
+    pub fn flush(&mut self) -> Vec<EngineOutput> {
+        while self.merge_next(true) {}
+        std::mem::take(&mut self.merged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use crate::deserialize::Deserialize;
+    use crate::engine::risk::{INSUFFICIENT_FUNDS, UNKNOWN_ORDER};
+    use crate::order_matching_system::order_book::OrderBook;
+    use crate::order_matching_system::testing::{command_strategy, funded_order_book, underfunded_order_book};
+    use proptest::prelude::*;
+
+    fn accounts(serialized_traders: &[&str]) -> BTreeMap<String, Trader> {
+        serialized_traders
+            .iter()
+            .map(|serialized| Trader::deserialize(serialized.to_string()))
+            .map(|trader| (trader.name.clone(), trader))
+            .collect()
+    }
+
+    fn run(mut engine: MatchingEngine, commands: &[String]) -> (Vec<EngineOutput>, MatchingEngine) {
+        for command in commands {
+            engine.submit(Command::parse(command).unwrap());
+        }
+        (engine.flush(), engine)
+    }
+
+    // Balances with the reservations, e.g. "C1 40 5 0 0 0 reserved 10 [(A, 2)]"
+    fn balances(accounts: &BTreeMap<String, Trader>) -> Vec<String> {
+        accounts
+            .values()
+            .map(|trader| {
+                let mut reserved: Vec<(AssetName, u64)> = trader
+                    .reserved_assets
+                    .iter()
+                    .filter(|(_, amount)| **amount > 0)
+                    .map(|(asset, amount)| (*asset, *amount))
+                    .collect();
+                reserved.sort();
+                format!("{} reserved {} {:?}", trader.serialize(), trader.reserved_usd, reserved)
+            })
+            .collect()
+    }
+
+    fn trades(outputs: &[EngineOutput]) -> Vec<Trade> {
+        outputs
+            .iter()
+            .flat_map(|output| &output.events)
+            .filter_map(|event| match event {
+                EngineEvent::Trade(trade) => Some(trade.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn risk_stage_admits_an_order_once_the_trade_paying_for_it_has_settled() {
+        let commands: Vec<String> = [
+            "new C1 b A 10 5",
+            "new C2 b B 10 1",
+            "new C2 s A 10 5",
+            "new C2 b B 10 1",
+            "cancel 7",
+            "deposit C9 USD 5",
+        ]
+        .map(String::from)
+        .to_vec();
+        let (outputs, engine) = run(
+            MatchingEngine::threaded(accounts(&["C1 50 0 0 0 0", "C2 0 5 0 0 0"])),
+            &commands,
+        );
+
+        let events: Vec<&EngineEvent> = outputs.iter().map(|output| &output.events[0]).collect();
+        assert_eq!(
+            events,
+            vec![
+                &EngineEvent::Accepted { order_id: 0 },
+                &EngineEvent::Rejected { reason: INSUFFICIENT_FUNDS },
+                &EngineEvent::Accepted { order_id: 1 },
+                &EngineEvent::Accepted { order_id: 2 },
+                &EngineEvent::Rejected { reason: UNKNOWN_ORDER },
+                &EngineEvent::Deposited,
+            ]
+        );
+        assert_eq!(outputs.iter().map(|output| output.sequence).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(trades(&outputs).len(), 1);
+        assert_eq!(engine.risk.accounts["C1"].serialize(), "C1 0 5 0 0 0");
+        assert_eq!(engine.risk.accounts["C2"].serialize(), "C2 40 0 0 0 0");
+        assert_eq!(engine.risk.accounts["C2"].reserved_usd, 10);
+        assert_eq!(engine.risk.accounts["C9"].usd_balance, 5);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn threaded_and_single_threaded_engines_agree(
+            commands in prop::collection::vec(command_strategy(), 1..80),
+        ) {
+            let traders = ["C1 300 20 20 0 0", "C2 300 20 20 0 0", "C3 300 20 20 0 0", "C4 300 20 20 0 0"];
+            let (threaded, threaded_engine) = run(MatchingEngine::threaded(accounts(&traders)), &commands);
+            let (single, single_engine) = run(MatchingEngine::single_threaded(accounts(&traders)), &commands);
+
+            prop_assert_eq!(threaded, single);
+            prop_assert_eq!(balances(&threaded_engine.risk.accounts), balances(&single_engine.risk.accounts));
+        }
+
+        #[test]
+        fn engine_trades_and_settles_like_one_order_book(
+            commands in prop::collection::vec(command_strategy(), 1..80),
+            underfunded in any::<bool>(),
+        ) {
+            // both run on one clock that ticks before every command, trades carry the time of theirs
+            let clock = SimulatedClock::new(1_000);
+            let traders = if underfunded { underfunded_order_book } else { funded_order_book };
+            let mut engine = MatchingEngine::threaded(traders().users).with_clock(clock.clone());
+            let mut order_book = OrderBook {
+                clock: Box::new(clock.clone()),
+                ..traders()
+            };
+            for command in &commands {
+                clock.advance(1);
+                engine.submit(Command::parse(command).unwrap());
+                order_book.execute(Command::parse(command).unwrap());
+            }
+            let outputs = engine.flush();
+
+            prop_assert_eq!(trades(&outputs), order_book.trades.clone());
+            prop_assert_eq!(balances(&engine.risk.accounts), balances(&order_book.users));
+            let rejected = outputs.iter().filter(|output| matches!(output.events[0], EngineEvent::Rejected { .. })).count();
+            prop_assert_eq!(rejected as u64, order_book.metrics.rejected);
+        }
+    }
+}
@@ -0,0 +1,4 @@
+pub mod instrument;
+pub mod matching_engine;
+pub mod risk;
+pub mod spsc;
@@ -0,0 +1,168 @@
+use crate::asset_name::AssetName;
+use crate::command::{Command, OrderRef};
+use crate::deserialize::Deserialize;
+use crate::order::Order;
+use crate::order_matching_system::client_order_ids::ClientOrderIds;
+use crate::order_matching_system::listener::OrderListener;
+use crate::order_matching_system::session::SessionState;
+use crate::order_matching_system::settlement::funds_rejection;
+use crate::trade::Trade;
+use crate::trader::Trader;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+pub use crate::order_matching_system::client_order_ids::DUPLICATE_CLIENT_ORDER_ID;
+pub use crate::order_matching_system::listener::UNKNOWN_ORDER;
+pub use crate::order_matching_system::settlement::{BALANCE_OVERFLOW, INSUFFICIENT_FUNDS, UNKNOWN_TRADER};
+
+// What the risk stage did with a command before any instrument sees it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Admission {
+    // the command goes on to the instrument's book, with the order id assigned for a new order
+    // and the order of a cancel or amend named by its exchange id
+    Route(AssetName, Command),
+    Deposited,
+    Rejected(&'static str),
+}
+
+// Owns every trader's balances for all instruments and admits a command only if the book would:
+// the same checks as OrderBook::execute_at. The balances move when the instruments' events are
+// merged, by the book's own Settlement, and the live orders follow the same events.
+#[derive(Default)]
+pub struct RiskStage {
+    pub accounts: BTreeMap<String, Trader>,
+    pub order_id_sequence: usize,
+    // live orders with their open amount, admitted new orders before their instrument has them
+    orders: HashMap<usize, Order>,
+    client_order_ids: ClientOrderIds,
+    open_orders: HashMap<(String, AssetName), usize>,
+    // session of every instrument as of the last admitted command, to accept what its book would
+    sessions: BTreeMap<AssetName, SessionState>,
+}
+
+impl RiskStage {
+    pub fn new(accounts: BTreeMap<String, Trader>) -> Self {
+        RiskStage {
+            accounts,
+            ..Default::default()
+        }
+    }
+
+    // Instruments whose queued commands may still change what the command's trader can afford:
+    // the ones where the trader has live orders. Until they drain the command cannot be admitted.
+    pub fn dependencies(&self, command: &Command) -> BTreeSet<AssetName> {
+        let trader_name = match command {
+            Command::NewOrder(order) => &order.trader_name,
+            Command::Cancel { order } | Command::Amend { order, .. } => match self.resolve(order) {
+                Some(order) => &order.trader_name,
+                None => return BTreeSet::new(),
+            },
+            Command::Deposit { .. } | Command::Session { .. } => return BTreeSet::new(),
+        };
+        self.open_orders
+            .iter()
+            .filter(|((name, _), count)| name == trader_name && **count > 0)
+            .map(|((_, asset), _)| *asset)
+            .collect()
+    }
+
+    pub fn admit(&mut self, command: Command) -> Admission {
+        let asset = match &command {
+            Command::NewOrder(order) => Some(order.asset),
+            Command::Cancel { order } | Command::Amend { order, .. } => self.resolve(order).map(|order| order.asset),
+            Command::Deposit { .. } | Command::Session { .. } => None,
+        };
+        let state = asset.and_then(|asset| self.sessions.get(&asset)).copied().unwrap_or_default();
+        if let Some(reason) = state.rejection(&command) {
+            return Admission::Rejected(reason);
+        }
+        match command {
+            Command::NewOrder(mut order) => {
+                if self.client_order_ids.is_duplicate(&order) {
+                    return Admission::Rejected(DUPLICATE_CLIENT_ORDER_ID);
+                }
+                if let Some(reason) = funds_rejection(&self.accounts, &order, None) {
+                    return Admission::Rejected(reason);
+                }
+                order.id = self.order_id_sequence;
+                self.order_id_sequence += 1;
+                self.track(order.clone());
+                Admission::Route(order.asset, Command::NewOrder(order))
+            }
+            Command::Cancel { order } => match self.resolve(&order) {
+                Some(order) => Admission::Route(order.asset, Command::Cancel { order: order.id.into() }),
+                None => Admission::Rejected(UNKNOWN_ORDER),
+            },
+            Command::Amend { order, price, amount } => {
+                let Some(old) = self.resolve(&order) else {
+                    return Admission::Rejected(UNKNOWN_ORDER);
+                };
+                // as OrderBook::amend_rejection: only an amendment that re-enters the order reserves more
+                if (amount > 0 && (price != old.price || amount > old.amount)) {
+                    let new = Order { price, amount, ..old.clone() };
+                    if let Some(reason) = funds_rejection(&self.accounts, &new, Some(old)) {
+                        return Admission::Rejected(reason);
+                    }
+                }
+                Admission::Route(old.asset, Command::Amend { order: old.id.into(), price, amount })
+            }
+            Command::Deposit { trader_name, asset, amount } => {
+                let deposited = self
+                    .accounts
+                    .entry(trader_name.clone())
+                    .or_insert_with(|| Trader::deserialize(format!("{} 0 0 0 0 0", trader_name)))
+                    .deposit(asset, amount);
+                match deposited {
+                    true => Admission::Deposited,
+                    false => Admission::Rejected(BALANCE_OVERFLOW),
+                }
+            }
+            Command::Session { asset, state } => {
+                self.sessions.insert(asset, state);
+                Admission::Route(asset, command)
+            }
+        }
+    }
+
+    // The live order a cancel or amend names
+    fn resolve(&self, order: &OrderRef) -> Option<&Order> {
+        self.orders.get(&self.client_order_ids.resolve(order)?)
+    }
+
+    fn track(&mut self, order: Order) {
+        self.client_order_ids.insert(&order);
+        *self.open_orders.entry((order.trader_name.clone(), order.asset)).or_insert(0) += 1;
+        self.orders.insert(order.id, order);
+    }
+
+    fn untrack(&mut self, order_id: usize) {
+        if let Some(order) = self.orders.remove(&order_id) {
+            self.client_order_ids.remove(&order);
+            *self.open_orders.get_mut(&(order.trader_name.clone(), order.asset)).unwrap() -= 1;
+        }
+    }
+}
+
+// Follows the live orders through the merged events, new orders are tracked when they are admitted
+impl OrderListener for RiskStage {
+    fn on_fill(&mut self, trade: &Trade, buy: &Order, sell: &Order) {
+        for order_id in [buy.id, sell.id] {
+            let order = self.orders.get_mut(&order_id).unwrap();
+            order.amount -= trade.amount;
+            if (order.amount == 0) {
+                self.untrack(order_id);
+            }
+        }
+    }
+
+    fn on_amend(&mut self, old: &Order, new: &Order) {
+        self.orders.insert(new.id, new.clone());
+    }
+
+    fn on_cancel(&mut self, order: &Order) {
+        self.untrack(order.id);
+    }
+
+    fn on_expire(&mut self, order: &Order) {
+        self.untrack(order.id);
+    }
+}
@@ -0,0 +1,161 @@
+use std::cell::UnsafeCell;
+use std::hint;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// Bounded lock-free single producer single consumer ring. head is only written by the consumer
+// and tail only by the producer, each slot between head and tail holds a value.
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    closed: AtomicBool,
+}
+
+// Slots are handed over through head and tail with acquire and release, one side touches a slot at a time
+unsafe impl<T: Send> Send for Ring<T> {}
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let (head, tail) = (*self.head.get_mut(), *self.tail.get_mut());
+        for position in head..tail {
+            unsafe { self.slots[position % self.slots.len()].get_mut().assume_init_drop() };
+        }
+    }
+}
+
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+pub fn channel<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "Queue capacity must be positive");
+    let ring = Arc::new(Ring {
+        slots: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        closed: AtomicBool::new(false),
+    });
+    (Producer { ring: ring.clone() }, Consumer { ring })
+}
+
+impl<T> Producer<T> {
+    // Gives the value back when the queue is full
+    pub fn try_push(&mut self, value: T) -> Result<(), T> {
+        let ring = &self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        if tail - ring.head.load(Ordering::Acquire) == ring.slots.len() {
+            return Err(value);
+        }
+        unsafe { (*ring.slots[tail % ring.slots.len()].get()).write(value) };
+        ring.tail.store(tail + 1, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn push(&mut self, mut value: T) {
+        let mut backoff = Backoff::default();
+        while let Err(rejected) = self.try_push(value) {
+            value = rejected;
+            backoff.wait();
+        }
+    }
+}
+
+// The consumer sees the queue closed once the producer is gone and everything pushed is popped
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::Release);
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn try_pop(&mut self) -> Option<T> {
+        let ring = &self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        if head == ring.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = unsafe { (*ring.slots[head % ring.slots.len()].get()).assume_init_read() };
+        ring.head.store(head + 1, Ordering::Release);
+        Some(value)
+    }
+
+    // Waits for the next value, None once the queue is closed and empty
+    pub fn pop(&mut self) -> Option<T> {
+        let mut backoff = Backoff::default();
+        loop {
+            // closed is read before the last look at the ring, so nothing pushed before closing is lost
+            let closed = self.ring.closed.load(Ordering::Acquire);
+            if let Some(value) = self.try_pop() {
+                return Some(value);
+            }
+            if closed {
+                return None;
+            }
+            backoff.wait();
+        }
+    }
+}
+
+// Spins first, then yields, then sleeps so that an idle side does not hold a core
+#[derive(Default)]
+struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    fn wait(&mut self) {
+        match self.step {
+            0..=63 => hint::spin_loop(),
+            64..=127 => thread::yield_now(),
+            _ => thread::sleep(Duration::from_micros(50)),
+        }
+        self.step = self.step.saturating_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_queue_rejects_until_popped() {
+        let (mut producer, mut consumer) = channel(2);
+        assert!(producer.try_push(1).is_ok() && producer.try_push(2).is_ok());
+        assert_eq!(producer.try_push(3), Err(3));
+        assert_eq!(consumer.try_pop(), Some(1));
+        assert!(producer.try_push(3).is_ok());
+        drop(producer);
+        assert_eq!((consumer.pop(), consumer.pop(), consumer.pop()), (Some(2), Some(3), None));
+    }
+
+    #[test]
+    fn values_cross_threads_in_order_and_unread_ones_are_dropped() {
+        let (mut producer, mut consumer) = channel(8);
+        let sender = thread::spawn(move || {
+            for value in 0..10_000 {
+                producer.push(value.to_string());
+            }
+        });
+        for expected in 0..10_000 {
+            assert_eq!(consumer.pop(), Some(expected.to_string()));
+        }
+        sender.join().unwrap();
+        assert_eq!(consumer.pop(), None);
+
+        let (mut producer, consumer) = channel(4);
+        let value = Arc::new(());
+        producer.push(value.clone());
+        producer.push(value.clone());
+        drop((producer, consumer));
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,901 @@
+use crate::asset_name::AssetName;
+use crate::command::Command;
+use crate::fix::message::{msg_type, tag, utc_timestamp, Message};
+use crate::order::{Direction, Order, TimeInForce};
+use crate::order_matching_system::journal::Journal;
+use crate::order_matching_system::order_book::{OrderBook, Outcome};
+use crate::order_matching_system::settlement::UNKNOWN_TRADER;
+use crate::trade::Trade;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+pub const SENDER_COMP_ID: &str = "EXCHANGE";
+const DEFAULT_HEART_BT_INT: u64 = 30;
+// Outgoing messages kept per session for ResendRequest, older ones are gap filled
+pub const DEFAULT_RESEND_BUFFER: usize = 10_000;
+
+// Sequence numbers and order ownership survive reconnects of the same counterparty
+struct Session {
+    // the trader whose API key the counterparty logged on with, every order of the session is theirs
+    account: String,
+    next_incoming: u64,
+    // the last outgoing messages, after the first `dropped` that no longer fit
+    sent: VecDeque<Message>,
+    dropped: u64,
+    pending: Vec<Message>,
+    cl_ord_ids: HashMap<String, usize>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            account: String::new(),
+            next_incoming: 1,
+            sent: VecDeque::new(),
+            dropped: 0,
+            pending: Vec::new(),
+            cl_ord_ids: HashMap::new(),
+        }
+    }
+}
+
+impl Session {
+    fn next_outgoing(&self) -> u64 {
+        self.dropped + self.sent.len() as u64 + 1
+    }
+
+    // The sent message with the sequence number, None once it has been dropped
+    fn sent(&self, seq_num: u64) -> Option<&Message> {
+        seq_num.checked_sub(self.dropped + 1).and_then(|index| self.sent.get(index as usize))
+    }
+}
+
+struct FixOrder {
+    session_id: String,
+    cl_ord_id: String,
+    account: String,
+    symbol: AssetName,
+    side: Direction,
+    order_qty: u64,
+    price: u64,
+    cum_qty: u64,
+    cum_notional: u64,
+}
+
+impl FixOrder {
+    fn leaves_qty(&self) -> u64 {
+        self.order_qty - self.cum_qty
+    }
+
+    fn ord_status(&self) -> &'static str {
+        match (self.cum_qty, self.leaves_qty()) {
+            (_, 0) => "2",
+            (0, _) => "0",
+            _ => "1",
+        }
+    }
+
+    fn avg_px(&self) -> f64 {
+        if self.cum_qty == 0 {
+            0.0
+        } else {
+            self.cum_notional as f64 / self.cum_qty as f64
+        }
+    }
+}
+
+// State of the currently connected counterparty
+struct Connection {
+    session_id: Option<String>,
+    heart_bt_int: Duration,
+    last_received: Instant,
+    last_sent: Instant,
+    test_request_pending: bool,
+    outgoing: Vec<Message>,
+    closed: bool,
+}
+
+// Serves one connection at a time, see accept_one. Sessions, with their sequence numbers and orders,
+// outlive connections, so counterparties can take turns.
+pub struct FixAcceptor {
+    pub order_book: OrderBook,
+    // outgoing messages each session keeps to resend
+    pub resend_buffer: usize,
+    // trader name by API key, as for the HTTP API
+    api_keys: HashMap<String, String>,
+    journal: Option<Journal>,
+    listener: TcpListener,
+    sessions: HashMap<String, Session>,
+    orders: HashMap<usize, FixOrder>,
+    exec_id_sequence: u64,
+}
+
+impl FixAcceptor {
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+        api_keys: HashMap<String, String>,
+        order_book: OrderBook,
+        journal: Option<Journal>,
+    ) -> io::Result<Self> {
+        Ok(FixAcceptor {
+            order_book,
+            resend_buffer: DEFAULT_RESEND_BUFFER,
+            api_keys,
+            journal,
+            listener: TcpListener::bind(addr)?,
+            sessions: HashMap::new(),
+            orders: HashMap::new(),
+            exec_id_sequence: 0,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Snapshot of the book, after which the journal starts over
+    pub fn save_checkpoint<P: AsRef<Path>>(&mut self, snapshot_path: P) -> io::Result<()> {
+        self.order_book.save_checkpoint(snapshot_path, self.journal.as_mut())
+    }
+
+    // Serves a single connection until Logout or disconnect. There is no concurrency: another counterparty
+    // connecting meanwhile waits in the listen backlog until this call returns and the next one accepts it.
+    pub fn accept_one(&mut self) -> io::Result<()> {
+        let (mut stream, _) = self.listener.accept()?;
+        let mut connection = Connection {
+            session_id: None,
+            heart_bt_int: Duration::from_secs(DEFAULT_HEART_BT_INT),
+            last_received: Instant::now(),
+            last_sent: Instant::now(),
+            test_request_pending: false,
+            outgoing: Vec::new(),
+            closed: false,
+        };
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 4096];
+
+        while !connection.closed {
+            stream.set_read_timeout(Some(connection.heart_bt_int))?;
+            match stream.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => {
+                    buffer.extend_from_slice(&chunk[..read]);
+                    connection.last_received = Instant::now();
+                    connection.test_request_pending = false;
+                }
+                Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    self.on_timeout(&mut connection);
+                }
+                Err(error) => return Err(error),
+            }
+
+            loop {
+                match Message::decode(&buffer) {
+                    Ok(Some((message, consumed))) => {
+                        buffer.drain(..consumed);
+                        self.on_message(&mut connection, message);
+                    }
+                    Ok(None) => break,
+                    Err(error) => {
+                        self.send(&mut connection, Message::new(msg_type::LOGOUT).with(tag::TEXT, format!("{:?}", error)));
+                        connection.closed = true;
+                        break;
+                    }
+                }
+                if connection.closed {
+                    break;
+                }
+            }
+            self.flush(&mut stream, &mut connection)?;
+        }
+        self.flush(&mut stream, &mut connection)
+    }
+
+    fn flush(&mut self, stream: &mut TcpStream, connection: &mut Connection) -> io::Result<()> {
+        if !connection.outgoing.is_empty() {
+            connection.last_sent = Instant::now();
+        }
+        for message in connection.outgoing.drain(..) {
+            stream.write_all(&message.encode())?;
+        }
+        stream.flush()
+    }
+
+    fn on_timeout(&mut self, connection: &mut Connection) {
+        if connection.session_id.is_none() {
+            connection.closed = true;
+            return;
+        }
+        if connection.test_request_pending {
+            self.send(connection, Message::new(msg_type::LOGOUT).with(tag::TEXT, "Heartbeat timeout"));
+            connection.closed = true;
+        } else if connection.last_received.elapsed() >= connection.heart_bt_int {
+            connection.test_request_pending = true;
+            self.send(connection, Message::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, "TEST"));
+        } else if connection.last_sent.elapsed() >= connection.heart_bt_int {
+            self.send(connection, Message::new(msg_type::HEARTBEAT));
+        }
+    }
+
+    fn on_message(&mut self, connection: &mut Connection, message: Message) {
+        if connection.session_id.is_none() {
+            if message.msg_type() != msg_type::LOGON {
+                connection.closed = true;
+                return;
+            }
+            let Some(session_id) = message.get(tag::SENDER_COMP_ID) else {
+                connection.closed = true;
+                return;
+            };
+            // the Password is an API key, a session stays bound to the trader it first logged on as
+            let account = message.get(tag::PASSWORD).and_then(|api_key| self.api_keys.get(api_key));
+            let bound = self.sessions.get(session_id).map(|session| &session.account);
+            let Some(account) = account.filter(|account| bound.is_none_or(|bound| bound == *account)).cloned() else {
+                connection.closed = true;
+                return;
+            };
+            connection.session_id = Some(session_id.to_string());
+            let session = self.sessions.entry(session_id.to_string()).or_default();
+            session.account = account;
+            if message.get(tag::RESET_SEQ_NUM_FLAG) == Some("Y") {
+                *session = Session {
+                    account: std::mem::take(&mut session.account),
+                    cl_ord_ids: std::mem::take(&mut session.cl_ord_ids),
+                    ..Default::default()
+                };
+            }
+        }
+
+        if message.msg_type() == msg_type::SEQUENCE_RESET {
+            self.on_sequence_reset(connection, &message);
+            return;
+        }
+        let expected = self.session(connection).next_incoming;
+        let seq_num = message.seq_num();
+        if seq_num < expected {
+            if message.get(tag::POSS_DUP_FLAG) != Some("Y") {
+                let text = format!("MsgSeqNum too low, expecting {} but received {}", expected, seq_num);
+                self.send(connection, Message::new(msg_type::LOGOUT).with(tag::TEXT, text));
+                connection.closed = true;
+            }
+            return;
+        }
+        if seq_num > expected {
+            if message.msg_type() == msg_type::LOGON {
+                self.on_logon(connection, &message);
+            }
+            let resend_request = Message::new(msg_type::RESEND_REQUEST)
+                .with(tag::BEGIN_SEQ_NO, expected)
+                .with(tag::END_SEQ_NO, 0);
+            self.send(connection, resend_request);
+            return;
+        }
+        self.session_mut(connection).next_incoming += 1;
+
+        match message.msg_type() {
+            msg_type::LOGON => self.on_logon(connection, &message),
+            msg_type::HEARTBEAT => {}
+            msg_type::TEST_REQUEST => {
+                let test_req_id = message.get(tag::TEST_REQ_ID).unwrap_or_default().to_string();
+                self.send(connection, Message::new(msg_type::HEARTBEAT).with(tag::TEST_REQ_ID, test_req_id));
+            }
+            msg_type::RESEND_REQUEST => self.on_resend_request(connection, &message),
+            msg_type::LOGOUT => {
+                self.send(connection, Message::new(msg_type::LOGOUT));
+                connection.closed = true;
+            }
+            msg_type::NEW_ORDER_SINGLE => self.on_new_order_single(connection, &message),
+            msg_type::ORDER_CANCEL_REQUEST => self.on_order_cancel_request(connection, &message),
+            msg_type::ORDER_CANCEL_REPLACE_REQUEST => self.on_order_cancel_replace_request(connection, &message),
+            unsupported => {
+                let reject = Message::new(msg_type::REJECT)
+                    .with(tag::REF_SEQ_NUM, seq_num)
+                    .with(tag::TEXT, format!("Unsupported MsgType {}", unsupported));
+                self.send(connection, reject);
+            }
+        }
+    }
+
+    fn on_logon(&mut self, connection: &mut Connection, message: &Message) {
+        let heart_bt_int = message.get_u64(tag::HEART_BT_INT).unwrap_or(DEFAULT_HEART_BT_INT).max(1);
+        connection.heart_bt_int = Duration::from_secs(heart_bt_int);
+        self.send(
+            connection,
+            Message::new(msg_type::LOGON).with(tag::ENCRYPT_METHOD, 0).with(tag::HEART_BT_INT, heart_bt_int),
+        );
+        // execution reports for orders that traded while the counterparty was away
+        let pending = std::mem::take(&mut self.session_mut(connection).pending);
+        for report in pending {
+            self.send(connection, report);
+        }
+    }
+
+    fn on_sequence_reset(&mut self, connection: &mut Connection, message: &Message) {
+        let Some(new_seq_no) = message.get_u64(tag::NEW_SEQ_NO) else {
+            return;
+        };
+        let session = self.session_mut(connection);
+        if message.get(tag::GAP_FILL_FLAG) != Some("Y") || new_seq_no > session.next_incoming {
+            session.next_incoming = new_seq_no;
+        }
+    }
+
+    // Application messages are resent as possible duplicates, admin ones and those past the resend buffer
+    // are replaced by a gap fill
+    fn on_resend_request(&mut self, connection: &mut Connection, message: &Message) {
+        let session_id = connection.session_id.clone().unwrap();
+        let session = self.session(connection);
+        let last = session.next_outgoing() - 1;
+        let begin = message.get_u64(tag::BEGIN_SEQ_NO).unwrap_or(1).max(1);
+        let end = match message.get_u64(tag::END_SEQ_NO) {
+            Some(end) if end != 0 && end < last => end,
+            _ => last,
+        };
+
+        let mut resent = Vec::new();
+        // messages no longer kept are gap filled along with the admin ones
+        let mut gap_start = (begin <= session.dropped).then_some(begin);
+        for seq_num in begin.max(session.dropped + 1)..=end {
+            let original = match session.sent(seq_num) {
+                Some(original) if !msg_type::is_admin(original.msg_type()) => original,
+                _ => {
+                    gap_start.get_or_insert(seq_num);
+                    continue;
+                }
+            };
+            if let Some(gap_start) = gap_start.take() {
+                resent.push(Self::gap_fill(&session_id, gap_start, seq_num));
+            }
+            let mut duplicate = original.clone();
+            let original_sending_time = original.get(tag::SENDING_TIME).unwrap_or_default().to_string();
+            duplicate.set(tag::POSS_DUP_FLAG, "Y");
+            duplicate.set(tag::ORIG_SENDING_TIME, original_sending_time);
+            duplicate.set(tag::SENDING_TIME, utc_timestamp());
+            resent.push(duplicate);
+        }
+        if let Some(gap_start) = gap_start {
+            resent.push(Self::gap_fill(&session_id, gap_start, end + 1));
+        }
+        connection.outgoing.extend(resent);
+    }
+
+    fn gap_fill(session_id: &str, seq_num: u64, new_seq_no: u64) -> Message {
+        Message::new(msg_type::SEQUENCE_RESET)
+            .with(tag::SENDER_COMP_ID, SENDER_COMP_ID)
+            .with(tag::TARGET_COMP_ID, session_id)
+            .with(tag::MSG_SEQ_NUM, seq_num)
+            .with(tag::SENDING_TIME, utc_timestamp())
+            .with(tag::POSS_DUP_FLAG, "Y")
+            .with(tag::GAP_FILL_FLAG, "Y")
+            .with(tag::NEW_SEQ_NO, new_seq_no)
+    }
+
+    fn on_new_order_single(&mut self, connection: &mut Connection, message: &Message) {
+        let session_id = connection.session_id.clone().unwrap();
+        let cl_ord_id = message.get(tag::CL_ORD_ID).unwrap_or_default().to_string();
+        let order = FixOrder {
+            session_id: session_id.clone(),
+            cl_ord_id: cl_ord_id.clone(),
+            account: self.session(connection).account.clone(),
+            symbol: message
+                .get(tag::SYMBOL)
+                .and_then(|symbol| AssetName::from_str(symbol).ok())
+                .unwrap_or(AssetName::Unknown),
+            side: match message.get(tag::SIDE) {
+                Some("2") => Direction::Sell,
+                _ => Direction::Buy,
+            },
+            order_qty: message.get_u64(tag::ORDER_QTY).unwrap_or_default(),
+            price: message.get_u64(tag::PRICE).unwrap_or_default(),
+            cum_qty: 0,
+            cum_notional: 0,
+        };
+        let new_order = Order {
+            trader_name: order.account.clone(),
+            direction: order.side,
+            asset: order.symbol,
+            price: order.price,
+            amount: order.order_qty,
+            time_in_force: match message.get(tag::TIME_IN_FORCE) {
+                Some("0") => TimeInForce::Day,
+                _ => TimeInForce::Gtc,
+            },
+            ..Default::default()
+        };
+
+        let rejection = if message.get(tag::ACCOUNT).is_some_and(|account| account != order.account) {
+            Some("Account does not belong to the session")
+        } else if cl_ord_id.is_empty() || self.session(connection).cl_ord_ids.contains_key(&cl_ord_id) {
+            Some("Duplicate or missing ClOrdID")
+        } else if !matches!(message.get(tag::SIDE), Some("1") | Some("2")) {
+            Some("Unsupported Side")
+        } else if message.get(tag::ORD_TYPE) != Some("2") {
+            Some("Only limit orders are supported")
+        } else if !matches!(message.get(tag::TIME_IN_FORCE), None | Some("0") | Some("1")) {
+            Some("Only Day and GTC orders are supported")
+        } else if order.symbol == AssetName::Unknown {
+            Some("Unknown Symbol")
+        } else if order.order_qty == 0 || order.price == 0 {
+            Some("OrderQty and Price must be positive")
+        } else {
+            None
+        };
+        // the book checks the account, its funds and the session
+        let (outcome, trades) = match rejection {
+            Some(text) => (Outcome::Rejected(text), Vec::new()),
+            None => self.submit(Command::NewOrder(new_order)),
+        };
+        let order_id = match outcome {
+            Outcome::Accepted(order_id) => order_id,
+            Outcome::Rejected(reason) => {
+                let text = if (reason == UNKNOWN_TRADER) { "Unknown Account" } else { reason };
+                let report = Self::execution_report(&order, None, self.next_exec_id(), "8", "8")
+                    .with(tag::LEAVES_QTY, 0)
+                    .with(tag::TEXT, text);
+                self.send(connection, report);
+                return;
+            }
+            Outcome::Executed => unreachable!("a new order is accepted or rejected"),
+        };
+
+        self.session_mut(connection).cl_ord_ids.insert(cl_ord_id, order_id);
+        self.orders.insert(order_id, order);
+        let exec_id = self.next_exec_id();
+        let report = Self::execution_report(&self.orders[&order_id], Some(order_id), exec_id, "0", "0");
+        self.send(connection, report);
+        self.report_fills(connection, trades);
+    }
+
+    fn on_order_cancel_request(&mut self, connection: &mut Connection, message: &Message) {
+        let Some(order_id) = self.find_order(connection, message) else {
+            self.send_cancel_reject(connection, message, "1", "Unknown order");
+            return;
+        };
+        if !self.order_book.orders.contains_key(&order_id) {
+            self.send_cancel_reject(connection, message, "1", "Order is not resting");
+            return;
+        }
+        if let (Outcome::Rejected(reason), _) = self.submit(Command::Cancel { order: order_id.into() }) {
+            self.send_cancel_reject(connection, message, "1", reason);
+            return;
+        }
+
+        let cl_ord_id = message.get(tag::CL_ORD_ID).unwrap_or_default().to_string();
+        let mut order = self.orders.remove(&order_id).unwrap();
+        let orig_cl_ord_id = std::mem::replace(&mut order.cl_ord_id, cl_ord_id);
+        let report = Self::execution_report(&order, Some(order_id), self.next_exec_id(), "4", "4")
+            .with(tag::LEAVES_QTY, 0)
+            .with(tag::ORIG_CL_ORD_ID, orig_cl_ord_id);
+        self.send(connection, report);
+    }
+
+    fn on_order_cancel_replace_request(&mut self, connection: &mut Connection, message: &Message) {
+        let Some(order_id) = self.find_order(connection, message) else {
+            self.send_cancel_reject(connection, message, "2", "Unknown order");
+            return;
+        };
+        let order = &self.orders[&order_id];
+        let order_qty = message.get_u64(tag::ORDER_QTY).unwrap_or(order.order_qty);
+        let price = message.get_u64(tag::PRICE).unwrap_or(order.price);
+        let resting = self.order_book.orders.get(&order_id).cloned();
+        let Some(resting) = resting.filter(|_| order_qty > order.cum_qty) else {
+            self.send_cancel_reject(connection, message, "2", "Order is not resting or already filled");
+            return;
+        };
+        let amend = Command::Amend {
+            order: resting.id.into(),
+            price,
+            amount: order_qty - order.cum_qty,
+        };
+        let trades = match self.submit(amend) {
+            (Outcome::Rejected(reason), _) => {
+                self.send_cancel_reject(connection, message, "2", reason);
+                return;
+            }
+            (_, trades) => trades,
+        };
+
+        let cl_ord_id = message.get(tag::CL_ORD_ID).unwrap_or_default().to_string();
+        let session = self.session_mut(connection);
+        session.cl_ord_ids.insert(cl_ord_id.clone(), order_id);
+        let order = self.orders.get_mut(&order_id).unwrap();
+        let orig_cl_ord_id = std::mem::replace(&mut order.cl_ord_id, cl_ord_id);
+        order.order_qty = order_qty;
+        order.price = price;
+        let exec_id = self.next_exec_id();
+        let order = &self.orders[&order_id];
+        let report = Self::execution_report(order, Some(order_id), exec_id, "5", order.ord_status())
+            .with(tag::ORIG_CL_ORD_ID, orig_cl_ord_id);
+        self.send(connection, report);
+        self.report_fills(connection, trades);
+    }
+
+    fn find_order(&self, connection: &Connection, message: &Message) -> Option<usize> {
+        let orig_cl_ord_id = message.get(tag::ORIG_CL_ORD_ID)?;
+        let order_id = *self.session(connection).cl_ord_ids.get(orig_cl_ord_id)?;
+        self.orders.contains_key(&order_id).then_some(order_id)
+    }
+
+    fn send_cancel_reject(&mut self, connection: &mut Connection, message: &Message, response_to: &str, text: &str) {
+        let order_id = self.find_order(connection, message);
+        let reject = Message::new(msg_type::ORDER_CANCEL_REJECT)
+            .with(tag::ORDER_ID, order_id.map_or("NONE".to_string(), |order_id| order_id.to_string()))
+            .with(tag::CL_ORD_ID, message.get(tag::CL_ORD_ID).unwrap_or_default())
+            .with(tag::ORIG_CL_ORD_ID, message.get(tag::ORIG_CL_ORD_ID).unwrap_or_default())
+            .with(tag::ORD_STATUS, order_id.map_or("8", |order_id| self.orders[&order_id].ord_status()))
+            .with(tag::CXL_REJ_RESPONSE_TO, response_to)
+            .with(tag::CXL_REJ_REASON, if order_id.is_some() { "0" } else { "1" })
+            .with(tag::TEXT, text);
+        self.send(connection, reject);
+    }
+
+    // Runs the command through the journal and the book, which decides whether it is accepted
+    fn submit(&mut self, command: Command) -> (Outcome, Vec<Trade>) {
+        let trades_before = self.order_book.trades.len();
+        let outcome = match self.journal.as_mut() {
+            Some(journal) => self.order_book.apply(journal, command),
+            None => self.order_book.execute(command),
+        };
+        (outcome, self.order_book.trades[trades_before..].to_vec())
+    }
+
+    fn report_fills(&mut self, connection: &mut Connection, trades: Vec<Trade>) {
+        for trade in trades {
+            for order_id in [trade.buy_order_id, trade.sell_order_id] {
+                let Some(order) = self.orders.get_mut(&order_id) else {
+                    continue;
+                };
+                order.cum_qty += trade.amount;
+                order.cum_notional += trade.amount * trade.price;
+                let status = order.ord_status();
+                let exec_id = self.next_exec_id();
+                let report = Self::execution_report(&self.orders[&order_id], Some(order_id), exec_id, "F", status)
+                    .with(tag::LAST_PX, trade.price)
+                    .with(tag::LAST_QTY, trade.amount);
+                self.deliver(connection, order_id, report);
+                if status == "2" {
+                    self.orders.remove(&order_id);
+                }
+            }
+        }
+    }
+
+    fn deliver(&mut self, connection: &mut Connection, order_id: usize, report: Message) {
+        let session_id = self.orders[&order_id].session_id.clone();
+        if connection.session_id.as_ref() == Some(&session_id) {
+            self.send(connection, report);
+        } else if let Some(session) = self.sessions.get_mut(&session_id) {
+            session.pending.push(report);
+        }
+    }
+
+    fn next_exec_id(&mut self) -> u64 {
+        self.exec_id_sequence += 1;
+        self.exec_id_sequence
+    }
+
+    fn execution_report(
+        order: &FixOrder,
+        order_id: Option<usize>,
+        exec_id: u64,
+        exec_type: &str,
+        ord_status: &str,
+    ) -> Message {
+        Message::new(msg_type::EXECUTION_REPORT)
+            .with(tag::ORDER_ID, order_id.map_or("NONE".to_string(), |order_id| order_id.to_string()))
+            .with(tag::CL_ORD_ID, &order.cl_ord_id)
+            .with(tag::EXEC_ID, exec_id)
+            .with(tag::EXEC_TYPE, exec_type)
+            .with(tag::ORD_STATUS, ord_status)
+            .with(tag::ACCOUNT, &order.account)
+            .with(tag::SYMBOL, format!("{:?}", order.symbol))
+            .with(tag::SIDE, if order.side == Direction::Buy { "1" } else { "2" })
+            .with(tag::ORDER_QTY, order.order_qty)
+            .with(tag::PRICE, order.price)
+            .with(tag::CUM_QTY, order.cum_qty)
+            .with(tag::LEAVES_QTY, order.leaves_qty())
+            .with(tag::AVG_PX, order.avg_px())
+    }
+
+    fn send(&mut self, connection: &mut Connection, message: Message) {
+        let session_id = connection.session_id.clone().unwrap_or_default();
+        let session = self.sessions.entry(session_id.clone()).or_default();
+        let message = message
+            .with(tag::SENDER_COMP_ID, SENDER_COMP_ID)
+            .with(tag::TARGET_COMP_ID, session_id)
+            .with(tag::MSG_SEQ_NUM, session.next_outgoing())
+            .with(tag::SENDING_TIME, utc_timestamp());
+        if (session.sent.len() >= self.resend_buffer) {
+            session.sent.pop_front();
+            session.dropped += 1;
+        }
+        session.sent.push_back(message.clone());
+        connection.outgoing.push(message);
+    }
+
+    fn session(&self, connection: &Connection) -> &Session {
+        &self.sessions[connection.session_id.as_ref().unwrap()]
+    }
+
+    fn session_mut(&mut self, connection: &Connection) -> &mut Session {
+        self.sessions.get_mut(connection.session_id.as_ref().unwrap()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deserialize::Deserialize;
+    use crate::trader::Trader;
+    use std::sync::mpsc;
+    use std::thread;
+
+    struct Initiator {
+        stream: TcpStream,
+        buffer: Vec<u8>,
+        next_outgoing: u64,
+    }
+
+    impl Initiator {
+        fn connect(addr: SocketAddr) -> Self {
+            let stream = TcpStream::connect(addr).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            Initiator {
+                stream,
+                buffer: Vec::new(),
+                next_outgoing: 1,
+            }
+        }
+
+        fn send(&mut self, message: Message) {
+            let seq_num = self.next_outgoing;
+            self.next_outgoing += 1;
+            self.send_with_seq_num(message, seq_num);
+        }
+
+        fn send_with_seq_num(&mut self, message: Message, seq_num: u64) {
+            let message = message
+                .with(tag::SENDER_COMP_ID, "CLIENT")
+                .with(tag::TARGET_COMP_ID, SENDER_COMP_ID)
+                .with(tag::MSG_SEQ_NUM, seq_num)
+                .with(tag::SENDING_TIME, utc_timestamp());
+            self.stream.write_all(&message.encode()).unwrap();
+        }
+
+        fn receive(&mut self) -> Message {
+            loop {
+                if let Some((message, consumed)) = Message::decode(&self.buffer).unwrap() {
+                    self.buffer.drain(..consumed);
+                    return message;
+                }
+                let mut chunk = [0u8; 4096];
+                let read = self.stream.read(&mut chunk).unwrap();
+                assert!(read > 0, "acceptor closed the connection");
+                self.buffer.extend_from_slice(&chunk[..read]);
+            }
+        }
+
+        fn receive_all(&mut self, count: usize) -> Vec<Message> {
+            (0..count).map(|_| self.receive()).collect()
+        }
+    }
+
+    fn new_order_single(cl_ord_id: &str, account: &str, side: &str, qty: u64, price: u64) -> Message {
+        Message::new(msg_type::NEW_ORDER_SINGLE)
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::ACCOUNT, account)
+            .with(tag::SYMBOL, "A")
+            .with(tag::SIDE, side)
+            .with(tag::ORDER_QTY, qty)
+            .with(tag::ORD_TYPE, "2")
+            .with(tag::PRICE, price)
+    }
+
+    fn logon(api_key: &str) -> Message {
+        Message::new(msg_type::LOGON)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, 30)
+            .with(tag::PASSWORD, api_key)
+    }
+
+    #[test]
+    fn resend_buffer_keeps_the_last_messages_and_gap_fills_older_ones() {
+        let mut acceptor = FixAcceptor::bind("127.0.0.1:0", HashMap::new(), OrderBook::default(), None).unwrap();
+        acceptor.resend_buffer = 3;
+        let mut connection = Connection {
+            session_id: Some("C1".to_string()),
+            heart_bt_int: Duration::from_secs(DEFAULT_HEART_BT_INT),
+            last_received: Instant::now(),
+            last_sent: Instant::now(),
+            test_request_pending: false,
+            outgoing: Vec::new(),
+            closed: false,
+        };
+        for _ in 0..5 {
+            acceptor.send(&mut connection, Message::new(msg_type::EXECUTION_REPORT));
+        }
+        assert_eq!(acceptor.sessions["C1"].sent.len(), 3);
+        connection.outgoing.clear();
+
+        let resend_request = Message::new(msg_type::RESEND_REQUEST).with(tag::BEGIN_SEQ_NO, 1).with(tag::END_SEQ_NO, 0);
+        acceptor.on_resend_request(&mut connection, &resend_request);
+        let resent: Vec<(&str, u64)> = connection
+            .outgoing
+            .iter()
+            .map(|message| (message.msg_type(), message.seq_num()))
+            .collect();
+        assert_eq!(
+            resent,
+            vec![
+                (msg_type::SEQUENCE_RESET, 1),
+                (msg_type::EXECUTION_REPORT, 3),
+                (msg_type::EXECUTION_REPORT, 4),
+                (msg_type::EXECUTION_REPORT, 5),
+            ]
+        );
+        assert_eq!(connection.outgoing[0].get(tag::NEW_SEQ_NO), Some("3"));
+    }
+
+    // Runs the acceptor for one session on its own thread
+    fn start_acceptor(preloaded: Vec<Order>) -> (SocketAddr, mpsc::Receiver<Vec<String>>) {
+        let (addr_sender, addr_receiver) = mpsc::channel();
+        let (result_sender, result_receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut order_book = OrderBook {
+                users: ["C1 2000 10 5 15 0", "C2 1000 20 35 40 10"]
+                    .into_iter()
+                    .map(|serialized| Trader::deserialize(serialized.to_string()))
+                    .map(|trader| (trader.name.clone(), trader))
+                    .collect(),
+                ..Default::default()
+            };
+            for order in preloaded {
+                order_book.execute(Command::NewOrder(order));
+            }
+            let api_keys = [("key-c1", "C1"), ("key-c2", "C2")]
+                .into_iter()
+                .map(|(api_key, trader_name)| (api_key.to_string(), trader_name.to_string()))
+                .collect();
+            let mut acceptor = FixAcceptor::bind("127.0.0.1:0", api_keys, order_book, None).unwrap();
+            addr_sender.send(acceptor.local_addr().unwrap()).unwrap();
+            acceptor.accept_one().unwrap();
+            let balances = acceptor
+                .order_book
+                .users
+                .values()
+                .map(|trader| trader.serialize())
+                .collect();
+            result_sender.send(balances).unwrap();
+        });
+        (addr_receiver.recv().unwrap(), result_receiver)
+    }
+
+    #[test]
+    fn initiator_session_trades_through_the_acceptor() {
+        // C1 bids for 10 at 8 outside the session
+        let bid = Order {
+            trader_name: "C1".to_string(),
+            direction: Direction::Buy,
+            asset: AssetName::A,
+            price: 8,
+            amount: 10,
+            ..Default::default()
+        };
+        let (addr, result) = start_acceptor(vec![bid]);
+        let mut initiator = Initiator::connect(addr);
+
+        initiator.send(logon("key-c2"));
+        let logon = initiator.receive();
+        assert_eq!(logon.msg_type(), msg_type::LOGON);
+        assert_eq!(logon.seq_num(), 1);
+
+        initiator.send(new_order_single("s1", "C2", "2", 20, 7));
+        let reports = initiator.receive_all(2);
+        assert_eq!(reports[0].get(tag::EXEC_TYPE), Some("0"));
+        assert_eq!(reports[0].get(tag::LEAVES_QTY), Some("20"));
+        assert_eq!(reports[1].get(tag::EXEC_TYPE), Some("F"));
+        assert_eq!(reports[1].get(tag::ORD_STATUS), Some("1"));
+        assert_eq!(reports[1].get(tag::LAST_QTY), Some("10"));
+        assert_eq!(reports[1].get(tag::LEAVES_QTY), Some("10"));
+
+        // the session trades for C2 only
+        initiator.send(new_order_single("b1", "C1", "1", 10, 8));
+        let rejected = initiator.receive();
+        assert_eq!(rejected.get(tag::EXEC_TYPE), Some("8"));
+        assert_eq!(rejected.get(tag::TEXT), Some("Account does not belong to the session"));
+
+        initiator.send(
+            Message::new(msg_type::ORDER_CANCEL_REPLACE_REQUEST)
+                .with(tag::ORIG_CL_ORD_ID, "s1")
+                .with(tag::CL_ORD_ID, "s2")
+                .with(tag::ORDER_QTY, 15)
+                .with(tag::PRICE, 9),
+        );
+        let replaced = initiator.receive();
+        assert_eq!(replaced.get(tag::EXEC_TYPE), Some("5"));
+        assert_eq!(replaced.get(tag::ORIG_CL_ORD_ID), Some("s1"));
+        assert_eq!(replaced.get(tag::LEAVES_QTY), Some("5"));
+
+        initiator.send(Message::new(msg_type::ORDER_CANCEL_REQUEST).with(tag::ORIG_CL_ORD_ID, "s2").with(tag::CL_ORD_ID, "s3"));
+        let cancelled = initiator.receive();
+        assert_eq!(cancelled.get(tag::EXEC_TYPE), Some("4"));
+        assert_eq!(cancelled.get(tag::CUM_QTY), Some("10"));
+
+        initiator.send(Message::new(msg_type::ORDER_CANCEL_REQUEST).with(tag::ORIG_CL_ORD_ID, "s2").with(tag::CL_ORD_ID, "s4"));
+        assert_eq!(initiator.receive().msg_type(), msg_type::ORDER_CANCEL_REJECT);
+
+        initiator.send(new_order_single("b2", "C2", "1", 1000, 8));
+        let rejected = initiator.receive();
+        assert_eq!(rejected.get(tag::EXEC_TYPE), Some("8"));
+        assert_eq!(rejected.get(tag::TEXT), Some("Insufficient funds"));
+
+        initiator.send(Message::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, "ping"));
+        let heartbeat = initiator.receive();
+        assert_eq!(heartbeat.msg_type(), msg_type::HEARTBEAT);
+        assert_eq!(heartbeat.get(tag::TEST_REQ_ID), Some("ping"));
+
+        // everything after the Logon: 7 application messages, the admin ones collapsed into gap fills
+        initiator.send(Message::new(msg_type::RESEND_REQUEST).with(tag::BEGIN_SEQ_NO, 1).with(tag::END_SEQ_NO, 0));
+        let resent = initiator.receive_all(9);
+        assert_eq!(resent[0].msg_type(), msg_type::SEQUENCE_RESET);
+        assert_eq!(resent[0].get(tag::NEW_SEQ_NO), Some("2"));
+        let duplicates: Vec<u64> = resent
+            .iter()
+            .filter(|message| !msg_type::is_admin(message.msg_type()))
+            .map(|message| {
+                assert_eq!(message.get(tag::POSS_DUP_FLAG), Some("Y"));
+                message.seq_num()
+            })
+            .collect();
+        assert_eq!(duplicates, (2..=8).collect::<Vec<u64>>());
+        assert_eq!(resent.last().unwrap().get(tag::NEW_SEQ_NO), Some("10"));
+
+        // a sequence gap makes the acceptor ask for the missing messages
+        let expected = initiator.next_outgoing;
+        initiator.send_with_seq_num(Message::new(msg_type::HEARTBEAT), expected + 3);
+        let resend_request = initiator.receive();
+        assert_eq!(resend_request.msg_type(), msg_type::RESEND_REQUEST);
+        assert_eq!(resend_request.get_u64(tag::BEGIN_SEQ_NO), Some(expected));
+        initiator.send_with_seq_num(
+            Message::new(msg_type::SEQUENCE_RESET)
+                .with(tag::GAP_FILL_FLAG, "Y")
+                .with(tag::NEW_SEQ_NO, expected + 4),
+            expected,
+        );
+        initiator.next_outgoing = expected + 4;
+
+        initiator.send(Message::new(msg_type::LOGOUT));
+        assert_eq!(initiator.receive().msg_type(), msg_type::LOGOUT);
+
+        let balances = result.recv().unwrap();
+        assert_eq!(balances, vec!["C1 1920 20 5 15 0", "C2 1080 10 35 40 10"]);
+    }
+
+    #[test]
+    fn first_message_other_than_logon_closes_the_connection() {
+        let (addr, result) = start_acceptor(Vec::new());
+        let mut initiator = Initiator::connect(addr);
+
+        initiator.send(new_order_single("b1", "C1", "1", 10, 8));
+
+        let mut chunk = [0u8; 16];
+        assert_eq!(initiator.stream.read(&mut chunk).unwrap(), 0);
+        assert_eq!(result.recv().unwrap(), vec!["C1 2000 10 5 15 0", "C2 1000 20 35 40 10"]);
+    }
+
+    #[test]
+    fn logon_without_a_known_api_key_closes_the_connection() {
+        let (addr, result) = start_acceptor(Vec::new());
+        let mut initiator = Initiator::connect(addr);
+
+        initiator.send(logon("key-c9"));
+
+        let mut chunk = [0u8; 16];
+        assert_eq!(initiator.stream.read(&mut chunk).unwrap(), 0);
+        assert_eq!(result.recv().unwrap(), vec!["C1 2000 10 5 15 0", "C2 1000 20 35 40 10"]);
+    }
+}
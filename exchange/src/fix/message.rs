@@ -0,0 +1,299 @@
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SOH: char = '\x01';
+pub const BEGIN_STRING: &str = "FIX.4.4";
+// far beyond any message the acceptor handles, a longer BodyLength can only be garbage
+pub const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+pub mod tag {
+    pub const ACCOUNT: u32 = 1;
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const BEGIN_STRING: u32 = 8;
+    pub const BODY_LENGTH: u32 = 9;
+    pub const CHECK_SUM: u32 = 10;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const ORIG_SENDING_TIME: u32 = 122;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+    pub const PASSWORD: u32 = 554;
+}
+
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+
+    pub fn is_admin(msg_type: &str) -> bool {
+        matches!(msg_type, "0" | "1" | "2" | "3" | "4" | "5" | "A")
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    Garbled(String),
+    BadCheckSum,
+}
+
+// Fields in wire order, without BeginString, BodyLength and CheckSum
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub fields: Vec<(u32, String)>,
+}
+
+impl Message {
+    pub fn new(msg_type: &str) -> Self {
+        Message {
+            fields: vec![(tag::MSG_TYPE, msg_type.to_string())],
+        }
+    }
+
+    pub fn with<T: ToString>(mut self, tag: u32, value: T) -> Self {
+        self.set(tag, value);
+        self
+    }
+
+    pub fn set<T: ToString>(&mut self, tag: u32, value: T) {
+        match self.fields.iter_mut().find(|(field_tag, _)| *field_tag == tag) {
+            Some(field) => field.1 = value.to_string(),
+            None => self.fields.push((tag, value.to_string())),
+        }
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field_tag, _)| *field_tag == tag)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_u64(&self, tag: u32) -> Option<u64> {
+        self.get(tag)?.parse().ok()
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(tag::MSG_TYPE).unwrap_or_default()
+    }
+
+    pub fn seq_num(&self) -> u64 {
+        self.get_u64(tag::MSG_SEQ_NUM).unwrap_or_default()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = String::new();
+        // MsgType must be the first field of the body, the standard header follows it
+        let header = [tag::MSG_TYPE, tag::SENDER_COMP_ID, tag::TARGET_COMP_ID, tag::MSG_SEQ_NUM, tag::SENDING_TIME];
+        for header_tag in header {
+            if let Some(value) = self.get(header_tag) {
+                write!(body, "{}={}{}", header_tag, value, SOH).unwrap();
+            }
+        }
+        for (field_tag, value) in self.fields.iter().filter(|(field_tag, _)| !header.contains(field_tag)) {
+            write!(body, "{}={}{}", field_tag, value, SOH).unwrap();
+        }
+
+        let mut message = format!("8={}{}9={}{}{}", BEGIN_STRING, SOH, body.len(), SOH, body);
+        let check_sum = message.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(message, "10={:03}{}", check_sum, SOH).unwrap();
+        message.into_bytes()
+    }
+
+    // Returns the first complete message in the buffer and the number of bytes it took,
+    // or None if more bytes are needed
+    pub fn decode(buffer: &[u8]) -> Result<Option<(Message, usize)>, DecodeError> {
+        let prefix = format!("8={}{}9=", BEGIN_STRING, SOH);
+        if buffer.len() < prefix.len() {
+            return Ok(None);
+        }
+        if !buffer.starts_with(prefix.as_bytes()) {
+            return Err(DecodeError::Garbled("message must start with BeginString".to_string()));
+        }
+        let bad_length = || DecodeError::Garbled("bad BodyLength".to_string());
+        let Some(length_end) = buffer[prefix.len()..].iter().position(|byte| *byte == SOH as u8) else {
+            // no more digits than MAX_BODY_LENGTH has can still be a BodyLength
+            if (buffer.len() - prefix.len() > MAX_BODY_LENGTH.to_string().len()) {
+                return Err(bad_length());
+            }
+            return Ok(None);
+        };
+        let body_length: usize = std::str::from_utf8(&buffer[prefix.len()..prefix.len() + length_end])
+            .ok()
+            .and_then(|length| length.parse().ok())
+            .filter(|length| *length <= MAX_BODY_LENGTH)
+            .ok_or_else(bad_length)?;
+
+        let body_start = prefix.len() + length_end + 1;
+        let trailer_start = body_start.checked_add(body_length).ok_or_else(bad_length)?;
+        let message_end = trailer_start + "10=000".len() + 1;
+        if buffer.len() < message_end {
+            return Ok(None);
+        }
+        if !buffer[trailer_start..].starts_with(b"10=") || buffer[message_end - 1] != SOH as u8 {
+            return Err(DecodeError::Garbled("CheckSum must follow the body".to_string()));
+        }
+        let check_sum = std::str::from_utf8(&buffer[trailer_start + 3..message_end - 1])
+            .ok()
+            .and_then(|check_sum| check_sum.parse::<u8>().ok());
+        let expected = buffer[..trailer_start].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if check_sum != Some(expected) {
+            return Err(DecodeError::BadCheckSum);
+        }
+
+        let body = std::str::from_utf8(&buffer[body_start..trailer_start])
+            .map_err(|_| DecodeError::Garbled("body is not utf-8".to_string()))?;
+        let mut fields = Vec::new();
+        for field in body.split(SOH).filter(|field| !field.is_empty()) {
+            let (field_tag, value) = field
+                .split_once('=')
+                .and_then(|(field_tag, value)| Some((field_tag.parse::<u32>().ok()?, value)))
+                .ok_or_else(|| DecodeError::Garbled(format!("bad field {}", field)))?;
+            fields.push((field_tag, value.to_string()));
+        }
+        if fields.first().map(|(field_tag, _)| *field_tag) != Some(tag::MSG_TYPE) {
+            return Err(DecodeError::Garbled("MsgType must be the first body field".to_string()));
+        }
+        Ok(Some((Message { fields }, message_end)))
+    }
+}
+
+// UTCTimestamp in YYYYMMDD-HH:MM:SS.sss format
+pub fn utc_timestamp() -> String {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (hours, minutes, secs) = ((seconds / 3600) % 24, (seconds / 60) % 60, seconds % 60);
+
+    // days since epoch to civil date, http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (seconds / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        hours,
+        minutes,
+        secs,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_message_has_length_and_check_sum() {
+        let message = Message::new(msg_type::HEARTBEAT)
+            .with(tag::SENDER_COMP_ID, "EXCHANGE")
+            .with(tag::TARGET_COMP_ID, "CLIENT")
+            .with(tag::MSG_SEQ_NUM, 1)
+            .with(tag::SENDING_TIME, "20240101-00:00:00.000");
+
+        let encoded = String::from_utf8(message.encode()).unwrap().replace(SOH, "|");
+
+        assert_eq!(
+            encoded,
+            "8=FIX.4.4|9=57|35=0|49=EXCHANGE|56=CLIENT|34=1|52=20240101-00:00:00.000|10=180|"
+        );
+    }
+
+    #[test]
+    fn decoded_message_equals_encoded() {
+        let message = Message::new(msg_type::NEW_ORDER_SINGLE)
+            .with(tag::MSG_SEQ_NUM, 2)
+            .with(tag::CL_ORD_ID, "order-1")
+            .with(tag::PRICE, 10);
+        let mut buffer = message.encode();
+        buffer.extend_from_slice(b"8=FIX.4.4");
+
+        let (decoded, consumed) = Message::decode(&buffer).unwrap().unwrap();
+
+        assert_eq!(decoded, message);
+        assert_eq!(consumed, buffer.len() - "8=FIX.4.4".len());
+    }
+
+    #[test]
+    fn incomplete_message_needs_more_bytes() {
+        let encoded = Message::new(msg_type::HEARTBEAT).encode();
+
+        assert_eq!(Message::decode(&encoded[..encoded.len() - 2]), Ok(None));
+    }
+
+    #[test]
+    fn corrupted_message_fails_check_sum() {
+        let mut encoded = Message::new(msg_type::HEARTBEAT).with(tag::TEST_REQ_ID, "a").encode();
+        let position = encoded.len() - 9;
+        encoded[position] = b'b';
+
+        assert_eq!(Message::decode(&encoded), Err(DecodeError::BadCheckSum));
+    }
+
+    #[test]
+    fn body_length_beyond_the_maximum_is_garbled() {
+        let garbled = Err(DecodeError::Garbled("bad BodyLength".to_string()));
+        for buffer in [
+            "8=FIX.4.4\x019=18446744073709551615\x0135=0\x0110=000\x01".to_string(),
+            format!("8=FIX.4.4\x019={}\x0135=0\x01", MAX_BODY_LENGTH + 1),
+            "8=FIX.4.4\x019=99999999999999999999999".to_string(),
+        ] {
+            assert_eq!(Message::decode(buffer.as_bytes()), garbled, "{:?}", buffer);
+        }
+    }
+
+    #[test]
+    fn utc_timestamp_has_fix_format() {
+        let timestamp = utc_timestamp();
+
+        assert_eq!(timestamp.len(), 21);
+        assert_eq!(&timestamp[8..9], "-");
+        assert!(timestamp.starts_with("20"));
+    }
+}
@@ -2,11 +2,23 @@
 extern crate strum;
 extern crate strum_macros;
 
-mod asset_name;
+pub mod asset_name;
+pub mod api;
+pub mod batch;
+pub mod binary_protocol;
+pub mod candles;
+pub mod clock;
 pub mod trader;
 pub mod deserialize;
+pub mod engine;
+pub mod fix;
+pub mod command;
 pub mod order;
+pub mod order_flow;
 pub mod order_matching_system;
+pub mod scenario;
+pub mod trade;
+pub mod udp_feed;
 
 
 #[cfg(test)]
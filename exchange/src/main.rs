@@ -1,15 +1,213 @@
-use exchange::{deserialize::Deserialize, order::Order, order_matching_system::order_book::*, trader::Trader};
+use exchange::{
+    asset_name::AssetName,
+    api::server::{load_api_keys, ApiServer, API_ADDR, API_KEYS_PATH},
+    batch,
+    binary_protocol::gateway::BinaryGateway,
+    candles::CandleAggregator,
+    clock::{Clock, SystemClock},
+    command::Command,
+    deserialize::Deserialize,
+    engine::matching_engine::{EngineEvent, MatchingEngine},
+    fix::acceptor::FixAcceptor,
+    order::Order,
+    order_flow::{self, OrderFlowConfig},
+    scenario::{scenario_paths, Scenario, SCENARIOS_DIR},
+    order_matching_system::journal::{Journal, JOURNAL_PATH},
+    order_matching_system::matching_policy::{load_matching_policies, MATCHING_POLICIES_PATH},
+    order_matching_system::order_book::*,
+    order_matching_system::pnl::{PnlConfig, PNL_CONFIG_PATH, PNL_REPORT_PATH},
+    order_matching_system::session::{Schedule, SessionScheduler, SessionState, SCHEDULE_PATH},
+    order_matching_system::snapshot::SNAPSHOT_PATH,
+    trader::Trader,
+    udp_feed::publisher::MarketDataPublisher,
+};
+use std::path::Path;
+use std::str::FromStr;
 
-fn main() {
-    let mut order_book = OrderBook {
+const FIX_ADDR: &str = "127.0.0.1:9878";
+const BINARY_ADDR: &str = "127.0.0.1:9879";
+const MARKET_DATA_ADDR: &str = "127.0.0.1:9880";
+const MARKET_DATA_RETRANSMIT_ADDR: &str = "127.0.0.1:9881";
+const CANDLE_BARS: [&str; 5] = ["1s", "1m", "1h", "100t", "1000v"];
+const GENERATED_DIR: &str = "resources/generated";
+
+fn recover_order_book() -> OrderBook {
+    let mut order_book = OrderBook::restore_snapshot(SNAPSHOT_PATH).unwrap_or_else(|| OrderBook {
         users: Trader::deserialize_all(),
         ..Default::default()
-    };
-    let orders = Order::deserialize_all();
+    });
+    // before the journal replays, its commands have to match the way they did the first time
+    order_book.matching_policies = load_matching_policies(MATCHING_POLICIES_PATH);
+    order_book.pnl.config = PnlConfig::load(PNL_CONFIG_PATH);
+    order_book.replay(JOURNAL_PATH).expect("Unable to replay journal");
+    order_book.clock = Box::new(SystemClock);
+    order_book
+}
+
+// Session transitions by resources/schedule.txt, none without the file
+fn load_scheduler(clock: impl Clock + 'static) -> Option<SessionScheduler> {
+    Schedule::load(SCHEDULE_PATH).map(|schedule| SessionScheduler::new(schedule, clock))
+}
+
+// Journals and executes the transitions the schedule is due for
+fn run_schedule(scheduler: &mut Option<SessionScheduler>, order_book: &mut OrderBook, journal: &mut Journal) {
+    if let Some(scheduler) = scheduler.as_mut() {
+        for command in scheduler.due(order_book) {
+            order_book.apply(journal, command);
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    // synthetic orders.txt and clients.txt for them, nothing else is read or written
+    if args.get(1).map(String::as_str) == Some("generate") {
+        let count = args.get(2).and_then(|count| count.parse().ok()).expect("Usage: generate <count> [directory]");
+        let dir = Path::new(args.get(3).map_or(GENERATED_DIR, String::as_str));
+        std::fs::create_dir_all(dir).expect("Couldn't create directory");
+        order_flow::write_files(OrderFlowConfig::default(), count, &dir.join("orders.txt"), &dir.join("clients.txt"))
+            .expect("Couldn't write generated order flow");
+        println!("{} orders written to {}", count, dir.display());
+        return;
+    }
+    // run every scenario and diff it against its golden outputs, or rewrite them with --regenerate
+    if args.get(1).map(String::as_str) == Some("scenarios") {
+        let regenerate = args.iter().any(|arg| arg == "--regenerate");
+        let dir = args.iter().skip(2).find(|arg| *arg != "--regenerate").map_or(SCENARIOS_DIR, String::as_str);
+        let mut failed = 0;
+        for path in scenario_paths(dir) {
+            let scenario = Scenario::load(&path);
+            if regenerate {
+                scenario.regenerate(&path);
+                println!("regenerated {}", scenario.name);
+            } else if let Some(diff) = scenario.check() {
+                println!("FAILED {}\n{}", scenario.name, diff);
+                failed += 1;
+            } else {
+                println!("ok {}", scenario.name);
+            }
+        }
+        if failed > 0 {
+            std::process::exit(1);
+        }
+        return;
+    }
+    // orders.txt from clients.txt through the engine with a thread per instrument, without the snapshot
+    // and the journal: only clients_updated.txt is written
+    if args.get(1).map(String::as_str) == Some("engine") {
+        let mut engine = MatchingEngine::threaded(Trader::deserialize_all())
+            .with_matching_policies(load_matching_policies(MATCHING_POLICIES_PATH));
+        for order in Order::deserialize_all().into_values() {
+            engine.submit(Command::NewOrder(order));
+        }
+        let outputs = engine.flush();
+        let events = outputs.iter().flat_map(|output| &output.events);
+        let trades = events.clone().filter(|event| matches!(event, EngineEvent::Trade(_))).count();
+        let rejected = events.filter(|event| matches!(event, EngineEvent::Rejected { .. })).count();
+        println!("commands {} trades {} rejected {}", outputs.len(), trades, rejected);
+        Trader::serialize_all(&engine.risk.accounts);
+        return;
+    }
+    // opening the journal cuts off a torn last record before anything replays it
+    let mut journal = Journal::open(JOURNAL_PATH).expect("Unable to open journal");
+
+    if args.get(1).map(String::as_str) == Some("api") {
+        let addr = args.get(2).map_or(API_ADDR, String::as_str);
+        let candles = CANDLE_BARS
+            .iter()
+            .map(|bar| {
+                CandleAggregator::new(bar.parse().unwrap())
+                    .with_csv(format!("resources/candles_{}.csv", bar))
+                    .expect("Couldn't open candles file")
+            })
+            .collect();
+        let market_data = MarketDataPublisher::bind(MARKET_DATA_ADDR, MARKET_DATA_RETRANSMIT_ADDR)
+            .expect("Couldn't bind market data publisher");
+        println!(
+            "Market data published to {}, retransmission on {}",
+            MARKET_DATA_ADDR,
+            market_data.retransmit_addr()
+        );
+        let server = ApiServer::bind(
+            addr,
+            load_api_keys(API_KEYS_PATH),
+            Some(journal),
+            candles,
+            Some(market_data),
+            load_scheduler(SystemClock),
+            recover_order_book,
+        )
+        .expect("Couldn't bind API server");
+        println!("API server listening on {}", server.local_addr().unwrap());
+        server.run();
+        return;
+    }
+
+    let mut order_book = recover_order_book();
+    let mut scheduler = load_scheduler(SystemClock);
+    match args.get(1).map(String::as_str) {
+        // only recover the book from the snapshot and the journal
+        Some("replay") => {}
+        // admin: force an instrument into a session state, e.g. "session A halted"
+        Some("session") => {
+            let asset = args.get(2).and_then(|asset| AssetName::from_str(asset).ok());
+            let state = args.get(3).and_then(|state| state.parse::<SessionState>().ok());
+            let (Some(asset), Some(state)) = (asset, state) else {
+                panic!("Usage: session <asset> <pre_open|opening_auction|continuous|halted|closing_auction|closed>");
+            };
+            order_book.apply(&mut journal, Command::Session { asset, state });
+        }
+        // print every resting order of an asset in queue priority, optionally of one trader only
+        Some("book") => {
+            let asset = args.get(2).and_then(|asset| AssetName::from_str(asset).ok()).expect("Usage: book <asset> [trader]");
+            print!("{}", order_book.level3(asset, args.get(3).map(String::as_str), false));
+            return;
+        }
+        Some("fix") => {
+            let addr = args.get(2).map_or(FIX_ADDR, String::as_str);
+            run_schedule(&mut scheduler, &mut order_book, &mut journal);
+            let mut acceptor = FixAcceptor::bind(addr, load_api_keys(API_KEYS_PATH), order_book, Some(journal))
+                .expect("Couldn't bind FIX acceptor");
+            println!("FIX acceptor listening on {}", acceptor.local_addr().unwrap());
+            loop {
+                if let Err(error) = acceptor.accept_one() {
+                    println!("FIX session ended with error: {}", error);
+                }
+                Trader::serialize_all(&acceptor.order_book.users);
+                acceptor.order_book.save_pnl_report(PNL_REPORT_PATH);
+                if let Err(error) = acceptor.save_checkpoint(SNAPSHOT_PATH) {
+                    println!("Couldn't save snapshot: {}", error);
+                }
+            }
+        }
+        Some("binary") => {
+            let addr = args.get(2).map_or(BINARY_ADDR, String::as_str);
+            run_schedule(&mut scheduler, &mut order_book, &mut journal);
+            let mut gateway = BinaryGateway::bind(addr, load_api_keys(API_KEYS_PATH), order_book, Some(journal))
+                .expect("Couldn't bind binary gateway");
+            println!("Binary gateway listening on {}", gateway.local_addr().unwrap());
+            loop {
+                if let Err(error) = gateway.accept_one() {
+                    println!("Binary session ended with error: {}", error);
+                }
+                Trader::serialize_all(&gateway.order_book.users);
+                gateway.order_book.save_pnl_report(PNL_REPORT_PATH);
+                if let Err(error) = gateway.save_checkpoint(SNAPSHOT_PATH) {
+                    println!("Couldn't save snapshot: {}", error);
+                }
+            }
+        }
+        _ => {
+            let commands = Order::deserialize_all().into_values().map(Command::NewOrder).collect();
+            batch::run(&mut order_book, commands, Schedule::load(SCHEDULE_PATH), SystemClock, Some(&mut journal));
+        }
+    }
 
-    for (_key, order) in orders {
-        order_book.limit(&order.clone());
+    for ticker in order_book.tickers.values() {
+        println!("{}", ticker);
     }
-    
+    println!("{}", order_book.metrics);
     Trader::serialize_all(&order_book.users);
+    order_book.save_pnl_report(PNL_REPORT_PATH);
+    order_book.save_checkpoint(SNAPSHOT_PATH, Some(&mut journal)).expect("Couldn't save snapshot");
 }
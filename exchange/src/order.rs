@@ -6,10 +6,8 @@ use std::default;
 use std::path::Path;
 use std::str::FromStr;
 use strum_macros::EnumString;
-use std::cell::RefCell;
-use std::rc::Rc;
 
-#[derive(Debug, Default, Copy, Clone, EnumString, PartialEq)]
+#[derive(Debug, Default, Copy, Clone, EnumString, PartialEq, Eq)]
 pub enum Direction {
     #[strum(serialize = "b")]
     #[default]
@@ -18,7 +16,19 @@ pub enum Direction {
     Sell
 }
 
-#[derive(Default)]
+// How long an order may rest: until cancelled or until its instrument closes for the day
+#[derive(Debug, Default, Copy, Clone, EnumString, PartialEq, Eq)]
+pub enum TimeInForce {
+    #[strum(serialize = "gtc")]
+    #[default]
+    Gtc,
+    #[strum(serialize = "day")]
+    Day,
+}
+
+const CLIENT_ORDER_ID_PREFIX: &str = "cl=";
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Order {
     pub id: usize,
     pub trader_name: String,
@@ -26,41 +36,98 @@ pub struct Order {
     pub asset: AssetName,
     pub price: u64,
     pub amount: u64,
+    // position of the order's entry into the book among all entries, amend to a new price re-enters it
+    pub entry_sequence: u64,
+    pub time_in_force: TimeInForce,
+    // nanoseconds since the epoch by the book's clock: when the order entered the book, together with
+    // entry_sequence, and when it last changed by the entry, a fill or an amend
+    pub entry_timestamp: u64,
+    pub update_timestamp: u64,
+    // the trader's own name for the order, unique among their live orders
+    pub client_order_id: Option<String>,
 }
 
-impl Deserialize<usize, Rc<RefCell<Order>>> for Order {
+impl Order {
+    // "<trader> <b|s> <asset> <price> <amount> [day] [timestamp] [cl=<client order id>]". The timestamp of
+    // a historical order is read into entry_timestamp and not serialized back: the book stamps orders by its own clock.
+    pub fn parse(serialized_str: &str) -> Order {
+        Self::try_parse(serialized_str).expect("Can't parse order")
+    }
 
-    fn deserialize(serialized_str: String) -> Rc<RefCell<Order>> {
+    // None for anything parse would panic on, and for orders on the Unknown asset
+    pub fn try_parse(serialized_str: &str) -> Option<Order> {
         let parts: Vec<&str> = serialized_str.split(' ').collect();
+        if (parts.len() < 5) {
+            return None;
+        }
 
         let trader_name: String = parts[0].to_string();
-        let direction = Direction::from_str(parts[1]).unwrap();
-        let asset = AssetName::from_str(parts[2]).unwrap();
-        let price = parts[3].parse::<u64>().expect("Can't parse to u64");
-        let amount = parts[4].parse::<u64>().expect("Can't parse to u64");
+        let direction = Direction::from_str(parts[1]).ok()?;
+        let asset = AssetName::from_str(parts[2]).ok().filter(|asset| *asset != AssetName::Unknown)?;
+        let price = parts[3].parse::<u64>().ok()?;
+        let amount = parts[4].parse::<u64>().ok()?;
+        let mut time_in_force = TimeInForce::Gtc;
+        let mut entry_timestamp = 0;
+        let mut client_order_id = None;
+        for part in &parts[5..] {
+            if let Some(id) = part.strip_prefix(CLIENT_ORDER_ID_PREFIX) {
+                if (id.is_empty()) {
+                    return None;
+                }
+                client_order_id = Some(id.to_string());
+                continue;
+            }
+            match part.parse::<u64>() {
+                Ok(timestamp) => entry_timestamp = timestamp,
+                Err(_) => time_in_force = TimeInForce::from_str(part).ok()?,
+            }
+        }
 
-        let order = Order {
+        Some(Order {
             id: usize::MAX,
-            trader_name: trader_name,
-            direction: direction,
-            asset: asset,
-            price: price,
-            amount: amount,
+            trader_name,
+            direction,
+            asset,
+            price,
+            amount,
+            entry_sequence: 0,
+            time_in_force,
+            entry_timestamp,
+            update_timestamp: entry_timestamp,
+            client_order_id,
+        })
+    }
+
+    pub fn serialize(&self) -> String {
+        let direction = match self.direction {
+            Direction::Buy => "b",
+            Direction::Sell => "s",
+        };
+        let serialized = format!(
+            "{} {} {:?} {} {}",
+            self.trader_name, direction, self.asset, self.price, self.amount
+        );
+        let serialized = match self.time_in_force {
+            TimeInForce::Gtc => serialized,
+            TimeInForce::Day => serialized + " day",
         };
-        Rc::new(RefCell::new(order))
+        match &self.client_order_id {
+            Some(id) => format!("{} {}{}", serialized, CLIENT_ORDER_ID_PREFIX, id),
+            None => serialized,
+        }
     }
+}
 
-    fn deserialize_all() -> BTreeMap<usize, Rc<RefCell<Order>>> {
-        let mut orders = BTreeMap::new();
+impl Deserialize<usize, Order> for Order {
+
+    fn deserialize(serialized_str: String) -> Order {
+        Self::parse(&serialized_str)
+    }
+
+    // Orders by their line in the file. They have no id yet, the book gives them the next of its sequence.
+    fn deserialize_all() -> BTreeMap<usize, Order> {
         let lines = Self::read_lines(Path::new("./resources/orders.txt"));
-        for line in lines {
-            if let Ok(serialized_order) = line {
-                let mut order = Self::deserialize(serialized_order);
-                order.borrow_mut().id = orders.len();
-                orders.insert(order.borrow().id, order.clone());
-            }
-        }
-        orders
+        lines.map_while(Result::ok).map(Self::deserialize).enumerate().collect()
     }
 }
 
@@ -73,4 +140,43 @@ mod tests {
         assert_eq!(orders.len(), 3);
     }
 
+    #[test]
+    fn serialized_order_equals_deserialize_input() {
+        let serialized_str = "C2 s A 7 20".to_string();
+
+        let order = Order::deserialize(serialized_str.clone());
+
+        assert_eq!(order.serialize(), serialized_str);
+    }
+
+    #[test]
+    fn day_orders_carry_their_time_in_force() {
+        let order = Order::parse("C1 b A 8 10 day");
+        assert_eq!(order.time_in_force, TimeInForce::Day);
+        assert_eq!(order.serialize(), "C1 b A 8 10 day");
+        assert_eq!(Order::parse("C1 b A 8 10").time_in_force, TimeInForce::Gtc);
+    }
+
+    #[test]
+    fn historical_orders_carry_their_timestamp() {
+        let order = Order::parse("C1 b A 8 10 day 1700000000000000000");
+        assert_eq!((order.time_in_force, order.entry_timestamp), (TimeInForce::Day, 1_700_000_000_000_000_000));
+        assert_eq!(Order::parse("C1 b A 8 10 42").entry_timestamp, 42);
+        assert_eq!(order.serialize(), "C1 b A 8 10 day");
+    }
+
+    #[test]
+    fn client_order_id_is_read_and_serialized_back() {
+        let order = Order::parse("C1 b A 8 10 cl=hedge-1 day");
+        assert_eq!((order.client_order_id.as_deref(), order.time_in_force), (Some("hedge-1"), TimeInForce::Day));
+        assert_eq!(order.serialize(), "C1 b A 8 10 day cl=hedge-1");
+        assert_eq!(Order::parse("C1 b A 8 10").client_order_id, None);
+    }
+
+    #[test]
+    fn malformed_order_is_not_parsed() {
+        for serialized_str in ["", "C1 b A 8", "C1 x A 8 10", "C1 b Unknown 8 10", "C1 b A -8 10", "C1 b A 8 10 ioc", "C1 b A 8 10 cl="] {
+            assert_eq!(Order::try_parse(serialized_str), None, "{}", serialized_str);
+        }
+    }
 }
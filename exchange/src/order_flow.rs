@@ -0,0 +1,319 @@
+use crate::asset_name::AssetName;
+use crate::command::Command;
+use crate::deserialize::Deserialize;
+use crate::order::{Direction, Order};
+use crate::trader::Trader;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+
+// Enough for every generated order, funding is not what the flow is meant to exercise
+const USD_FUNDING: u64 = 1_000_000_000_000;
+const ASSET_FUNDING: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SizeDistribution {
+    Fixed(u64),
+    // both bounds included
+    Uniform { min: u64, max: u64 },
+    // many small orders and a long tail of large ones, never below 1
+    Exponential { mean: f64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderFlowConfig {
+    pub seed: u64,
+    // traders C1..Cn, each one buys and sells
+    pub traders: usize,
+    pub assets: Vec<AssetName>,
+    pub start_price: u64,
+    // the mid price of each asset moves by up to this much, up or down, before every new order
+    pub max_price_step: u64,
+    // limit orders are placed up to this far from the mid price, on their own side of it
+    pub book_width: u64,
+    // share of commands that cancel a previously generated order
+    pub cancel_ratio: f64,
+    // share of new orders that are market orders
+    pub market_ratio: f64,
+    pub sizes: SizeDistribution,
+}
+
+impl Default for OrderFlowConfig {
+    fn default() -> Self {
+        OrderFlowConfig {
+            seed: 42,
+            traders: 4,
+            assets: vec![AssetName::A, AssetName::B],
+            start_price: 100,
+            max_price_step: 1,
+            book_width: 5,
+            cancel_ratio: 0.2,
+            market_ratio: 0.1,
+            sizes: SizeDistribution::Uniform { min: 1, max: 50 },
+        }
+    }
+}
+
+// Linear congruential generator, the same seed gives the same flow on every platform
+struct Lcg(u64);
+
+impl Lcg {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        self.0 >> 11
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    // In [0, 1)
+    fn unit(&mut self) -> f64 {
+        self.next_u64() as f64 / (1u64 << 53) as f64
+    }
+}
+
+// Endless synthetic order flow for a book that starts empty: ids of the generated orders are
+// counted from 0, in the order the book assigns them. The book has no market order type, so a
+// market order is a limit order priced twice the book width through the mid, which takes whatever
+// rests within reach and leaves the rest of its amount in the book.
+pub struct OrderFlow {
+    config: OrderFlowConfig,
+    random: Lcg,
+    mid_prices: HashMap<AssetName, u64>,
+    next_order_id: usize,
+    // generated limit orders that have not been cancelled yet, some may have been filled since
+    cancellable: Vec<usize>,
+}
+
+impl OrderFlow {
+    pub fn new(config: OrderFlowConfig) -> Self {
+        assert!(config.traders > 0 && !config.assets.is_empty(), "Order flow needs traders and assets");
+        OrderFlow {
+            random: Lcg(config.seed),
+            mid_prices: config.assets.iter().map(|asset| (*asset, config.start_price.max(1))).collect(),
+            next_order_id: 0,
+            cancellable: Vec::new(),
+            config,
+        }
+    }
+
+    pub fn trader_names(&self) -> Vec<String> {
+        (1..=self.config.traders).map(|trader| format!("C{}", trader)).collect()
+    }
+
+    // Deposits that fund every trader of the flow, to run before it
+    pub fn deposits(&self) -> Vec<Command> {
+        let mut deposits = Vec::new();
+        for trader_name in self.trader_names() {
+            deposits.push(Command::Deposit {
+                trader_name: trader_name.clone(),
+                asset: None,
+                amount: USD_FUNDING,
+            });
+            for asset in &self.config.assets {
+                deposits.push(Command::Deposit {
+                    trader_name: trader_name.clone(),
+                    asset: Some(*asset),
+                    amount: ASSET_FUNDING,
+                });
+            }
+        }
+        deposits
+    }
+
+    // The same funding as traders in clients.txt format
+    pub fn traders(&self) -> BTreeMap<String, Trader> {
+        let mut traders = BTreeMap::new();
+        for command in self.deposits() {
+            if let Command::Deposit { trader_name, asset, amount } = command {
+                traders
+                    .entry(trader_name.clone())
+                    .or_insert_with(|| Trader::deserialize(format!("{} 0 0 0 0 0", trader_name)))
+                    .deposit(asset, amount);
+            }
+        }
+        traders
+    }
+
+    fn size(&mut self) -> u64 {
+        match self.config.sizes {
+            SizeDistribution::Fixed(amount) => amount.max(1),
+            SizeDistribution::Uniform { min, max } => min.max(1) + self.random.below(max.saturating_sub(min.max(1)) + 1),
+            SizeDistribution::Exponential { mean } => (1.0 - (1.0 - self.random.unit()).ln() * (mean - 1.0).max(0.0)) as u64,
+        }
+    }
+
+    fn new_order(&mut self) -> Order {
+        let asset = self.config.assets[self.random.below(self.config.assets.len() as u64) as usize];
+        let step = self.config.max_price_step;
+        let mid = self.mid_prices[&asset] + self.random.below(2 * step + 1);
+        let mid = mid.saturating_sub(step).max(1);
+        self.mid_prices.insert(asset, mid);
+
+        let direction = if self.random.below(2) == 0 { Direction::Buy } else { Direction::Sell };
+        let market = self.random.unit() < self.config.market_ratio;
+        let offset = if market { 2 * self.config.book_width } else { self.random.below(self.config.book_width + 1) };
+        // a market order crosses the mid, a limit order stays on its own side of it
+        let price = match (direction, market) {
+            (Direction::Buy, true) | (Direction::Sell, false) => mid + offset,
+            (Direction::Buy, false) | (Direction::Sell, true) => mid.saturating_sub(offset).max(1),
+        };
+        let trader = 1 + self.random.below(self.config.traders as u64);
+
+        let id = self.next_order_id;
+        self.next_order_id += 1;
+        if !market {
+            self.cancellable.push(id);
+        }
+        Order {
+            id,
+            trader_name: format!("C{}", trader),
+            direction,
+            asset,
+            price,
+            amount: self.size(),
+            entry_sequence: 0,
+            time_in_force: Default::default(),
+            entry_timestamp: 0,
+            update_timestamp: 0,
+            client_order_id: None,
+        }
+    }
+}
+
+impl Iterator for OrderFlow {
+    type Item = Command;
+
+    fn next(&mut self) -> Option<Command> {
+        if (!self.cancellable.is_empty() && self.random.unit() < self.config.cancel_ratio) {
+            let index = self.random.below(self.cancellable.len() as u64) as usize;
+            let order_id = self.cancellable.swap_remove(index);
+            return Some(Command::Cancel { order: order_id.into() });
+        }
+        Some(Command::NewOrder(self.new_order()))
+    }
+}
+
+// Writes `count` new orders in orders.txt format and their funded traders in clients.txt format.
+// orders.txt has no cancels, so the flow is generated without them whatever the config says.
+pub fn write_files(mut config: OrderFlowConfig, count: usize, orders_path: &Path, clients_path: &Path) -> io::Result<()> {
+    config.cancel_ratio = 0.0;
+    let mut flow = OrderFlow::new(config);
+
+    let mut clients = LineWriter::new(File::create(clients_path)?);
+    for trader in flow.traders().values() {
+        writeln!(clients, "{}", trader.serialize())?;
+    }
+    clients.flush()?;
+
+    let mut orders = LineWriter::new(File::create(orders_path)?);
+    for command in flow.by_ref().take(count) {
+        if let Command::NewOrder(order) = command {
+            writeln!(orders, "{}", order.serialize())?;
+        }
+    }
+    orders.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_matching_system::order_book::OrderBook;
+
+    fn generate(config: OrderFlowConfig, count: usize) -> Vec<Command> {
+        OrderFlow::new(config).take(count).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_flow() {
+        let config = OrderFlowConfig::default();
+        assert_eq!(generate(config.clone(), 1000), generate(config.clone(), 1000));
+        assert_ne!(generate(config.clone(), 1000), generate(OrderFlowConfig { seed: 7, ..config }, 1000));
+    }
+
+    #[test]
+    fn flow_follows_the_configured_mix() {
+        let config = OrderFlowConfig {
+            traders: 3,
+            assets: vec![AssetName::C],
+            max_price_step: 0,
+            book_width: 2,
+            cancel_ratio: 0.25,
+            market_ratio: 0.5,
+            sizes: SizeDistribution::Uniform { min: 3, max: 6 },
+            ..Default::default()
+        };
+        let commands = generate(config, 10_000);
+        let orders: Vec<&Order> = commands
+            .iter()
+            .filter_map(|command| match command {
+                Command::NewOrder(order) => Some(order),
+                _ => None,
+            })
+            .collect();
+        let cancels = commands.len() - orders.len();
+        // market orders are the only ones priced 4 away from the fixed mid of 100
+        let markets = orders.iter().filter(|order| order.price.abs_diff(100) == 4).count();
+
+        assert!((2_000..3_000).contains(&cancels), "{} cancels", cancels);
+        assert!((3_000..4_500).contains(&markets), "{} market orders", markets);
+        assert!(orders.iter().all(|order| order.asset == AssetName::C && (3..=6).contains(&order.amount)));
+        assert!(orders.iter().all(|order| ["C1", "C2", "C3"].contains(&order.trader_name.as_str())));
+        assert!(orders.iter().enumerate().all(|(index, order)| order.id == index));
+    }
+
+    #[test]
+    fn prices_stay_positive_and_sizes_follow_the_distribution() {
+        let config = OrderFlowConfig {
+            start_price: 3,
+            max_price_step: 2,
+            cancel_ratio: 0.0,
+            sizes: SizeDistribution::Exponential { mean: 10.0 },
+            ..Default::default()
+        };
+        let amounts: Vec<u64> = generate(config, 10_000)
+            .into_iter()
+            .map(|command| match command {
+                Command::NewOrder(order) => {
+                    assert!(order.price >= 1);
+                    order.amount
+                }
+                command => panic!("Unexpected {:?}", command),
+            })
+            .collect();
+        let mean = amounts.iter().sum::<u64>() as f64 / amounts.len() as f64;
+
+        assert!(amounts.iter().all(|amount| *amount >= 1));
+        assert!((8.0..12.0).contains(&mean), "mean size {}", mean);
+        assert!(amounts.iter().any(|amount| *amount > 40));
+    }
+
+    #[test]
+    fn funded_traders_run_the_flow_through_a_book() {
+        let flow = OrderFlow::new(OrderFlowConfig::default());
+        let mut order_book = OrderBook {
+            users: flow.traders(),
+            ..Default::default()
+        };
+        for command in flow.take(5_000) {
+            order_book.execute(command);
+        }
+        assert!(!order_book.trades.is_empty() && !order_book.orders.is_empty());
+
+        let written = std::env::temp_dir().join(format!("order_flow_{}", std::process::id()));
+        std::fs::create_dir_all(&written).unwrap();
+        write_files(OrderFlowConfig::default(), 100, &written.join("orders.txt"), &written.join("clients.txt")).unwrap();
+        let orders = std::fs::read_to_string(written.join("orders.txt")).unwrap();
+        let clients = std::fs::read_to_string(written.join("clients.txt")).unwrap();
+        std::fs::remove_dir_all(&written).unwrap();
+
+        assert_eq!(orders.lines().count(), 100);
+        assert!(orders.lines().all(|line| Order::parse(line).amount > 0));
+        assert_eq!(
+            clients.lines().map(|line| Trader::deserialize(line.to_string()).usd_balance).collect::<Vec<_>>(),
+            vec![USD_FUNDING; 4]
+        );
+    }
+}
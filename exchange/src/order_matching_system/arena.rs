@@ -0,0 +1,292 @@
+use crate::order::Order;
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut};
+
+// Slot of an order in the arena. The generation changes every time the slot is freed,
+// so a handle kept past the removal of its order is caught instead of reading another order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OrderHandle {
+    index: u32,
+    generation: u32,
+}
+
+// prev and next link the order into the queue of its limit, both are None while it is not queued
+struct Slot {
+    generation: u32,
+    order: Option<Order>,
+    prev: Option<OrderHandle>,
+    next: Option<OrderHandle>,
+}
+
+// Owns every resting order: a slab of slots reused through a free list, addressed by handles
+// from the limit queues and by order id through the index. The handle is the order's queue node,
+// and the order itself holds its side, asset and price, so the index locates it in the book.
+#[derive(Default)]
+pub struct OrderArena {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    ids: HashMap<usize, OrderHandle>,
+}
+
+impl OrderArena {
+    // Ids are unique: a stored order may still be linked into a queue, so it is never replaced here
+    pub fn insert(&mut self, order: Order) -> OrderHandle {
+        assert!(!self.ids.contains_key(&order.id), "Order {} is already stored", order.id);
+        let id = order.id;
+        let handle = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.order = Some(order);
+                slot.prev = None;
+                slot.next = None;
+                OrderHandle {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    order: Some(order),
+                    prev: None,
+                    next: None,
+                });
+                OrderHandle {
+                    index: (self.slots.len() - 1) as u32,
+                    generation: 0,
+                }
+            }
+        };
+        self.ids.insert(id, handle);
+        handle
+    }
+
+    // A queued order has to leave its queue first
+    pub fn remove(&mut self, order_id: &usize) -> Option<Order> {
+        let handle = self.ids.remove(order_id)?;
+        let slot = &mut self.slots[handle.index as usize];
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        slot.order.take()
+    }
+
+    pub fn handle(&self, order_id: &usize) -> Option<OrderHandle> {
+        self.ids.get(order_id).copied()
+    }
+
+    pub fn get(&self, order_id: &usize) -> Option<&Order> {
+        self.ids.get(order_id).map(|handle| &self[*handle])
+    }
+
+    pub fn get_mut(&mut self, order_id: &usize) -> Option<&mut Order> {
+        let handle = *self.ids.get(order_id)?;
+        Some(&mut self[handle])
+    }
+
+    pub fn contains_key(&self, order_id: &usize) -> bool {
+        self.ids.contains_key(order_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    // Orders in id order
+    pub fn values(&self) -> impl Iterator<Item = &Order> + '_ {
+        let mut ids: Vec<(&usize, &OrderHandle)> = self.ids.iter().collect();
+        ids.sort_unstable_by_key(|(id, _)| **id);
+        ids.into_iter().map(|(_, handle)| &self[*handle])
+    }
+
+    fn slot(&self, handle: OrderHandle) -> &Slot {
+        let slot = &self.slots[handle.index as usize];
+        assert!(slot.generation == handle.generation && slot.order.is_some(), "Stale order handle");
+        slot
+    }
+
+    fn slot_mut(&mut self, handle: OrderHandle) -> &mut Slot {
+        let slot = &mut self.slots[handle.index as usize];
+        assert!(slot.generation == handle.generation && slot.order.is_some(), "Stale order handle");
+        slot
+    }
+}
+
+// Intrusive doubly linked list of orders through their arena slots, so an order
+// leaves the queue in O(1) wherever it is
+#[derive(Debug, Default)]
+pub struct OrderQueue {
+    head: Option<OrderHandle>,
+    tail: Option<OrderHandle>,
+    len: usize,
+}
+
+impl OrderQueue {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn front(&self) -> Option<OrderHandle> {
+        self.head
+    }
+
+    pub fn push_back(&mut self, handle: OrderHandle, orders: &mut OrderArena) {
+        {
+            let slot = orders.slot_mut(handle);
+            slot.prev = self.tail;
+            slot.next = None;
+        }
+        match self.tail {
+            Some(tail) => orders.slot_mut(tail).next = Some(handle),
+            None => self.head = Some(handle),
+        }
+        self.tail = Some(handle);
+        self.len += 1;
+    }
+
+    pub fn pop_front(&mut self, orders: &mut OrderArena) -> Option<OrderHandle> {
+        let head = self.head?;
+        self.remove(head, orders);
+        Some(head)
+    }
+
+    // False when the order is not queued here, the queue is left as it was
+    pub fn remove(&mut self, handle: OrderHandle, orders: &mut OrderArena) -> bool {
+        let (prev, next) = {
+            let slot = orders.slot(handle);
+            (slot.prev, slot.next)
+        };
+        if (prev.is_none() && self.head != Some(handle)) {
+            return false;
+        }
+        match prev {
+            Some(prev) => orders.slot_mut(prev).next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => orders.slot_mut(next).prev = prev,
+            None => self.tail = prev,
+        }
+        let slot = orders.slot_mut(handle);
+        slot.prev = None;
+        slot.next = None;
+        self.len -= 1;
+        true
+    }
+
+    // Orders from the front of the queue
+    pub fn iter<'a>(&self, orders: &'a OrderArena) -> impl Iterator<Item = OrderHandle> + 'a {
+        std::iter::successors(self.head, move |handle| orders.slot(*handle).next)
+    }
+}
+
+impl Index<OrderHandle> for OrderArena {
+    type Output = Order;
+
+    fn index(&self, handle: OrderHandle) -> &Order {
+        self.slot(handle).order.as_ref().unwrap()
+    }
+}
+
+impl IndexMut<OrderHandle> for OrderArena {
+    fn index_mut(&mut self, handle: OrderHandle) -> &mut Order {
+        self.slot_mut(handle).order.as_mut().unwrap()
+    }
+}
+
+impl Index<&usize> for OrderArena {
+    type Output = Order;
+
+    fn index(&self, order_id: &usize) -> &Order {
+        self.get(order_id).expect("No order with this id")
+    }
+}
+
+impl FromIterator<Order> for OrderArena {
+    fn from_iter<I: IntoIterator<Item = Order>>(orders: I) -> Self {
+        let mut arena = OrderArena::default();
+        for order in orders {
+            arena.insert(order);
+        }
+        arena
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(id: usize, amount: u64) -> Order {
+        Order {
+            id,
+            amount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn freed_slots_are_reused_and_stale_handles_rejected() {
+        let mut arena = OrderArena::default();
+        let first = arena.insert(order(0, 10));
+        arena.insert(order(1, 20));
+        assert_eq!(arena.remove(&0).map(|order| order.amount), Some(10));
+        let reused = arena.insert(order(2, 30));
+
+        assert_eq!(reused.index, first.index);
+        assert_ne!(reused, first);
+        assert_eq!(arena[reused].amount, 30);
+        assert_eq!(arena.values().map(|order| order.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(std::panic::catch_unwind(|| arena[first].amount).is_err());
+    }
+
+    #[test]
+    fn queue_unlinks_orders_from_any_position() {
+        let mut arena = OrderArena::default();
+        let handles: Vec<OrderHandle> = (0..4).map(|id| arena.insert(order(id, 1))).collect();
+        let mut queue = OrderQueue::default();
+        for handle in &handles {
+            queue.push_back(*handle, &mut arena);
+        }
+        let ids = |queue: &OrderQueue, arena: &OrderArena| queue.iter(arena).map(|handle| arena[handle].id).collect::<Vec<_>>();
+
+        assert!(queue.remove(handles[2], &mut arena));
+        assert_eq!(ids(&queue, &arena), vec![0, 1, 3]);
+        assert!(!queue.remove(handles[2], &mut arena));
+        assert_eq!(queue.pop_front(&mut arena), Some(handles[0]));
+        assert!(queue.remove(handles[3], &mut arena));
+        queue.push_back(handles[2], &mut arena);
+        assert_eq!(ids(&queue, &arena), vec![1, 2]);
+        assert_eq!((queue.len(), queue.front()), (2, Some(handles[1])));
+        assert!(queue.remove(handles[1], &mut arena) && queue.remove(handles[2], &mut arena));
+        assert!(queue.is_empty() && queue.front().is_none());
+    }
+
+    #[test]
+    fn orders_are_found_by_id() {
+        let mut arena: OrderArena = [order(0, 10), order(1, 20)].into_iter().collect();
+
+        assert_eq!(arena.len(), 2);
+        assert_eq!(arena[&0].amount, 10);
+        arena.get_mut(&1).unwrap().amount = 7;
+        assert_eq!(arena.handle(&1).map(|handle| arena[handle].amount), Some(7));
+        assert!(arena.remove(&3).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Order 0 is already stored")]
+    fn inserting_an_existing_id_is_rejected() {
+        let mut arena = OrderArena::default();
+        let handle = arena.insert(order(0, 10));
+        let mut queue = OrderQueue::default();
+        queue.push_back(handle, &mut arena);
+        // replacing it would free a slot the queue still links to
+        arena.insert(order(0, 5));
+    }
+}
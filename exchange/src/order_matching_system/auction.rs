@@ -0,0 +1,258 @@
+use crate::asset_name::AssetName;
+use crate::order::{Direction, Order};
+use crate::order_matching_system::arena::OrderHandle;
+use crate::order_matching_system::limit_tree::Level;
+use crate::order_matching_system::listener::OrderEvent;
+use crate::order_matching_system::order_book::OrderBook;
+use crate::trade::Trade;
+use std::collections::BTreeSet;
+
+// Price the book would uncross at with the volume that would execute there. imbalance is
+// what is left on the larger side at that price: positive for buys, negative for sells.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Uncross {
+    pub price: u64,
+    pub volume: u64,
+    pub imbalance: i64,
+}
+
+// Single clearing price of the levels, None when nothing crosses. Among the limit prices the one
+// that executes the most volume wins, then the one with the smallest imbalance, then market pressure:
+// the highest price if every candidate left has more to buy, the lowest if every one has more to sell.
+// Otherwise the price closest to the reference price, or to the middle of the candidates without one,
+// the lower price on a tie.
+pub fn clearing_price(bids: &[Level], asks: &[Level], reference: Option<u64>) -> Option<Uncross> {
+    let prices: BTreeSet<u64> = bids.iter().chain(asks).map(|level| level.price).collect();
+    let candidates: Vec<Uncross> = prices
+        .into_iter()
+        .map(|price| {
+            let buy: u64 = bids.iter().filter(|level| level.price >= price).map(|level| level.volume).sum();
+            let sell: u64 = asks.iter().filter(|level| level.price <= price).map(|level| level.volume).sum();
+            Uncross {
+                price,
+                volume: buy.min(sell),
+                imbalance: buy as i64 - sell as i64,
+            }
+        })
+        .collect();
+
+    let volume = candidates.iter().map(|uncross| uncross.volume).max().filter(|volume| *volume > 0)?;
+    let candidates: Vec<Uncross> = candidates.into_iter().filter(|uncross| uncross.volume == volume).collect();
+    let imbalance = candidates.iter().map(|uncross| uncross.imbalance.unsigned_abs()).min().unwrap();
+    let candidates: Vec<Uncross> = candidates
+        .into_iter()
+        .filter(|uncross| uncross.imbalance.unsigned_abs() == imbalance)
+        .collect();
+
+    let (lowest, highest) = (candidates[0], candidates[candidates.len() - 1]);
+    if candidates.iter().all(|uncross| uncross.imbalance > 0) {
+        return Some(highest);
+    }
+    if candidates.iter().all(|uncross| uncross.imbalance < 0) {
+        return Some(lowest);
+    }
+    let reference = reference.unwrap_or((lowest.price + highest.price) / 2);
+    candidates.into_iter().min_by_key(|uncross| uncross.price.abs_diff(reference))
+}
+
+impl OrderBook {
+    // Orders of the asset rest without matching and the indicative uncross is published
+    pub fn in_auction(&self, asset: AssetName) -> bool {
+        self.session(asset).is_call()
+    }
+
+    // Where the asset would uncross now, the last trade price is the reference
+    pub fn indicative(&self, asset: AssetName) -> Option<Uncross> {
+        let depth = self.depth(asset, usize::MAX);
+        let reference = self.tickers.get(&asset).and_then(|ticker| ticker.last_price);
+        clearing_price(&depth.bids, &depth.asks, reference)
+    }
+
+    // Every crossing order executes at the clearing price, buys and sells each in price-time priority.
+    // Called when a call ends or trading starts on orders that rested without matching.
+    pub fn uncross(&mut self, asset: AssetName) -> Option<Uncross> {
+        let uncross = self.indicative(asset);
+        if let Some(uncross) = uncross {
+            let buys = self.executable(asset, Direction::Buy, uncross);
+            let sells = self.executable(asset, Direction::Sell, uncross);
+            let (mut buys, mut sells) = (buys.into_iter().peekable(), sells.into_iter().peekable());
+            while let (Some((buy, buy_left)), Some((sell, sell_left))) = (buys.peek_mut(), sells.peek_mut()) {
+                let amount = (*buy_left).min(*sell_left);
+                self.fill_at(*buy, *sell, uncross.price, amount);
+                *buy_left -= amount;
+                *sell_left -= amount;
+                if (*buy_left == 0) {
+                    buys.next();
+                }
+                if (*sell_left == 0) {
+                    sells.next();
+                }
+            }
+        }
+        uncross
+    }
+
+    // Orders of one side that trade at the uncross with how much of each, best price and oldest first
+    fn executable(&self, asset: AssetName, direction: Direction, uncross: Uncross) -> Vec<(OrderHandle, u64)> {
+        let limits = match direction {
+            Direction::Buy => &self.buy_limits,
+            Direction::Sell => &self.sell_limits,
+        };
+        let mut left = uncross.volume;
+        let mut executable = Vec::new();
+        for (_, queue) in limits[&asset].queues(&self.orders) {
+            for handle in queue {
+                let amount = self.orders[handle].amount.min(left);
+                if (amount == 0) {
+                    return executable;
+                }
+                executable.push((handle, amount));
+                left -= amount;
+            }
+        }
+        executable
+    }
+
+    // Fills amount of a resting buy and sell at price, the listeners settle it
+    fn fill_at(&mut self, buy: OrderHandle, sell: OrderHandle, price: u64, amount: u64) {
+        let (buy_order, sell_order) = (self.orders[buy].clone(), self.orders[sell].clone());
+        for (handle, order) in [(buy, &buy_order), (sell, &sell_order)] {
+            self.take_from_resting(handle, order, amount);
+        }
+        let trade = Trade {
+            asset: buy_order.asset,
+            price,
+            amount,
+            buy_order_id: buy_order.id,
+            sell_order_id: sell_order.id,
+            buyer: buy_order.trader_name.clone(),
+            seller: sell_order.trader_name.clone(),
+            ..Default::default()
+        };
+        self.notify(OrderEvent::Fill {
+            trade,
+            buy: buy_order,
+            sell: sell_order,
+        });
+    }
+
+    fn take_from_resting(&mut self, handle: OrderHandle, order: &Order, amount: u64) {
+        let limits = match order.direction {
+            Direction::Buy => self.buy_limits.get_mut(&order.asset).unwrap(),
+            Direction::Sell => self.sell_limits.get_mut(&order.asset).unwrap(),
+        };
+        if (amount == order.amount) {
+            limits.remove(handle, &mut self.orders);
+            self.orders.remove(&order.id);
+        } else {
+            limits.reduce(order.price, amount);
+            self.orders[handle].amount -= amount;
+            self.orders[handle].update_timestamp = self.now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_matching_system::market_data::{InstrumentStatus, MarketDataEvent};
+    use crate::order_matching_system::testing::{execute_all, funded_order_book};
+
+    fn levels(levels: &[(u64, u64)]) -> Vec<Level> {
+        levels
+            .iter()
+            .map(|(price, volume)| Level {
+                price: *price,
+                volume: *volume,
+                order_count: 1,
+            })
+            .collect()
+    }
+
+    fn price(bids: &[(u64, u64)], asks: &[(u64, u64)], reference: Option<u64>) -> Option<(u64, u64, i64)> {
+        clearing_price(&levels(bids), &levels(asks), reference).map(|uncross| (uncross.price, uncross.volume, uncross.imbalance))
+    }
+
+    #[test]
+    fn clearing_price_maximizes_executed_volume() {
+        // 10 executes at 10 and 12, 15 at 11
+        assert_eq!(price(&[(12, 10), (11, 5)], &[(10, 10), (11, 10)], None), Some((11, 15, -5)));
+        assert_eq!(price(&[(9, 10)], &[(10, 10)], Some(10)), None);
+        assert_eq!(price(&[], &[(10, 10)], None), None);
+    }
+
+    #[test]
+    fn equal_volumes_are_decided_by_imbalance_then_market_pressure() {
+        // 10 executes at both, 3 more to buy at 10 against 5 more to sell at 12
+        assert_eq!(price(&[(12, 10), (10, 3)], &[(10, 10), (12, 5)], None), Some((10, 10, 3)));
+        // buyers left over at 11 and 12 push the price up, sellers left over at 10 and 11 push it down
+        assert_eq!(price(&[(12, 20)], &[(10, 5), (11, 5)], None), Some((12, 10, 10)));
+        assert_eq!(price(&[(12, 5), (11, 5)], &[(10, 20)], None), Some((10, 10, -10)));
+    }
+
+    #[test]
+    fn balanced_candidates_take_the_price_closest_to_the_reference() {
+        let (bids, asks) = ([(12, 10)], [(10, 10)]);
+        assert_eq!(price(&bids, &asks, Some(13)), Some((12, 10, 0)));
+        assert_eq!(price(&bids, &asks, Some(11)), Some((10, 10, 0)));
+        assert_eq!(price(&bids, &asks, Some(9)), Some((10, 10, 0)));
+        assert_eq!(price(&bids, &asks, None), Some((10, 10, 0)));
+    }
+
+    #[test]
+    fn orders_collect_during_the_call_and_execute_at_one_price() {
+        let mut order_book = funded_order_book();
+        execute_all(
+            &mut order_book,
+            &["session A opening_auction", "new C1 b A 12 10", "new C3 b A 11 5", "new C2 s A 10 10", "new C4 s A 11 10"],
+        );
+        assert!(order_book.trades.is_empty() && order_book.in_auction(AssetName::A));
+        assert_eq!(order_book.indicative(AssetName::A), Some(Uncross { price: 11, volume: 15, imbalance: -5 }));
+
+        execute_all(&mut order_book, &["session A continuous"]);
+        let trades: Vec<_> = order_book
+            .trades
+            .iter()
+            .map(|trade| (trade.buyer.as_str(), trade.seller.as_str(), trade.price, trade.amount))
+            .collect();
+        assert_eq!(trades, vec![("C1", "C2", 11, 10), ("C3", "C4", 11, 5)]);
+        assert!(!order_book.in_auction(AssetName::A));
+        assert_eq!(order_book.depth(AssetName::A, 5).asks, levels(&[(11, 5)]));
+        assert!(order_book.depth(AssetName::A, 5).bids.is_empty());
+        // C1 reserved at 12 and paid 11
+        let (c1, c2, c4) = (&order_book.users["C1"], &order_book.users["C2"], &order_book.users["C4"]);
+        assert_eq!((c1.usd_balance, c1.reserved_usd, c1.assets_count[&AssetName::A]), (1_000_000 - 110, 0, 10_010));
+        assert_eq!((c2.usd_balance, c2.reserved_assets[&AssetName::A]), (1_000_000 + 110, 0));
+        assert_eq!((c4.usd_balance, c4.reserved_assets[&AssetName::A]), (1_000_000 + 55, 5));
+
+        // continuous trading resumes
+        execute_all(&mut order_book, &["new C1 b A 11 5"]);
+        assert_eq!(order_book.trades.len(), 3);
+    }
+
+    #[test]
+    fn indicative_uncross_is_published_while_it_changes() {
+        let mut order_book = funded_order_book();
+        execute_all(
+            &mut order_book,
+            &["session B opening_auction", "new C1 b B 12 10", "new C2 s B 10 4", "new C4 s B 13 1", "session B continuous"],
+        );
+        let events: Vec<MarketDataEvent> = order_book
+            .market_data
+            .messages
+            .iter()
+            .map(|message| message.event.clone())
+            .filter(|event| matches!(event, MarketDataEvent::Status(_) | MarketDataEvent::Indicative { .. }))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                MarketDataEvent::Status(InstrumentStatus::Auction),
+                // nothing crosses when the call opens
+                MarketDataEvent::Indicative { price: 0, volume: 0, imbalance: 0 },
+                MarketDataEvent::Indicative { price: 12, volume: 4, imbalance: 6 },
+                MarketDataEvent::Status(InstrumentStatus::Trading),
+            ]
+        );
+    }
+}
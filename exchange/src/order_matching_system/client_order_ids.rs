@@ -0,0 +1,131 @@
+use crate::command::OrderRef;
+use crate::order::Order;
+use crate::order_matching_system::listener::OrderListener;
+use crate::trade::Trade;
+use std::collections::HashMap;
+
+pub const DUPLICATE_CLIENT_ORDER_ID: &str = "Duplicate client order id";
+
+// Exchange ids of the live orders by trader and client order id. A client id is free again
+// once its order is filled, cancelled or expired.
+#[derive(Debug, Default)]
+pub struct ClientOrderIds(HashMap<(String, String), usize>);
+
+impl ClientOrderIds {
+    pub fn get(&self, trader_name: &str, client_order_id: &str) -> Option<usize> {
+        self.0.get(&(trader_name.to_string(), client_order_id.to_string())).copied()
+    }
+
+    // Whether the order's client id already names a live order of its trader
+    pub fn is_duplicate(&self, order: &Order) -> bool {
+        match &order.client_order_id {
+            Some(client_order_id) => self.get(&order.trader_name, client_order_id).is_some(),
+            None => false,
+        }
+    }
+
+    pub fn resolve(&self, order: &OrderRef) -> Option<usize> {
+        match order {
+            OrderRef::Id(order_id) => Some(*order_id),
+            OrderRef::Client { trader_name, client_order_id } => self.get(trader_name, client_order_id),
+        }
+    }
+
+    pub fn insert(&mut self, order: &Order) {
+        if let Some(client_order_id) = &order.client_order_id {
+            self.0.insert((order.trader_name.clone(), client_order_id.clone()), order.id);
+        }
+    }
+
+    pub fn remove(&mut self, order: &Order) {
+        if let Some(client_order_id) = &order.client_order_id {
+            self.0.remove(&(order.trader_name.clone(), client_order_id.clone()));
+        }
+    }
+}
+
+impl OrderListener for ClientOrderIds {
+    fn on_accept(&mut self, order: &Order) {
+        self.insert(order);
+    }
+
+    fn on_fill(&mut self, trade: &Trade, buy: &Order, sell: &Order) {
+        for order in [buy, sell] {
+            if (order.amount == trade.amount) {
+                self.remove(order);
+            }
+        }
+    }
+
+    fn on_cancel(&mut self, order: &Order) {
+        self.remove(order);
+    }
+
+    fn on_expire(&mut self, order: &Order) {
+        self.remove(order);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_matching_system::order_book::OrderBook;
+    use crate::order_matching_system::order_store::OrderStatus;
+    use crate::order_matching_system::testing::{execute_all, funded_order_book};
+
+    #[test]
+    fn duplicate_of_a_live_client_order_id_is_rejected_without_an_id() {
+        let mut order_book = funded_order_book();
+        execute_all(&mut order_book, &["new C1 b A 8 10 cl=o1", "new C1 b A 7 5 cl=o1", "new C3 b A 7 5 cl=o1"]);
+
+        assert_eq!(order_book.order_id_sequence, 2);
+        assert_eq!(order_book.client_order_ids.get("C1", "o1"), Some(0));
+        assert_eq!(order_book.client_order_ids.get("C3", "o1"), Some(1));
+        assert_eq!(order_book.users["C1"].reserved_usd, 80);
+        let rejected = order_book.order_store.by_status(OrderStatus::Rejected);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].order.price, 7);
+    }
+
+    #[test]
+    fn cancel_and_amend_find_the_order_by_either_id() {
+        let mut order_book = funded_order_book();
+        execute_all(
+            &mut order_book,
+            &["new C1 b A 8 10 cl=o1", "new C1 b A 7 5 cl=o2", "amend C1 o1 9 4", "cancel 1", "cancel C1 o2", "amend C3 o1 9 4"],
+        );
+
+        assert_eq!(order_book.orders.get(&0).map(|order| (order.price, order.amount)), Some((9, 4)));
+        assert!(!order_book.orders.contains_key(&1));
+        assert_eq!(order_book.metrics.rejected, 2);
+    }
+
+    #[test]
+    fn client_order_id_is_free_again_once_its_order_is_gone() {
+        let mut order_book = funded_order_book();
+        execute_all(
+            &mut order_book,
+            &["new C1 b A 8 10 cl=o1", "new C2 s A 8 10", "new C1 b A 8 3 cl=o1", "cancel C1 o1", "new C1 b A 8 2 cl=o1"],
+        );
+
+        assert_eq!(order_book.metrics.rejected, 0);
+        assert_eq!(order_book.client_order_ids.get("C1", "o1"), Some(3));
+        assert_eq!(order_book.order_store.get(0).unwrap().status, OrderStatus::Filled);
+        assert_eq!(order_book.order_store.get(2).unwrap().status, OrderStatus::Cancelled);
+    }
+
+    #[test]
+    fn ids_carry_on_after_a_snapshot() {
+        let path = std::env::temp_dir().join("exchange_client_order_ids_snapshot.txt");
+        let mut order_book = funded_order_book();
+        execute_all(&mut order_book, &["new C1 b A 8 10 cl=o1", "new C1 b A 7 5"]);
+        order_book.save_snapshot(&path).unwrap();
+
+        let mut restored = OrderBook::restore_snapshot(&path).unwrap();
+        execute_all(&mut restored, &["new C1 b A 6 1 cl=o1", "new C1 b A 6 1 cl=o2", "cancel C1 o1"]);
+
+        assert_eq!(restored.client_order_ids.get("C1", "o2"), Some(2));
+        assert!(!restored.orders.contains_key(&0));
+        assert_eq!(restored.metrics.rejected, 1);
+    }
+}
@@ -0,0 +1,400 @@
+use crate::command::Command;
+use crate::order_matching_system::order_book::{OrderBook, Outcome};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+pub const JOURNAL_PATH: &str = "resources/journal.txt";
+
+// Every command is appended as one line before it is executed:
+//   <sequence> <crc32 of "<sequence> <timestamp> <command>" in hex> <timestamp> <command>
+// with the time the book executes it at. Records written before timestamps have none and replay at 0.
+// A record without a trailing newline or with a bad checksum can only be the last one written before
+// a crash, so it is cut off when the journal is opened. Anywhere else it is corruption: the journal
+// is not opened and does not replay, since the committed records after it would be lost.
+// Once a snapshot covers every record the journal is truncated, and the records after it continue
+// the sequence of the snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalRecord {
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub command: Command,
+}
+
+pub struct Journal {
+    file: File,
+    last_sequence: u64,
+}
+
+impl Journal {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+
+        let (records, valid_len) = Self::parse_records(&content)?;
+        if valid_len < content.len() {
+            file.set_len(valid_len as u64)?;
+        }
+        Ok(Journal {
+            file,
+            last_sequence: records.last().map_or(0, |record| record.sequence),
+        })
+    }
+
+    pub fn append(&mut self, timestamp: u64, command: &Command) -> u64 {
+        self.last_sequence += 1;
+        let record = format!("{} {} {}", self.last_sequence, timestamp, command.serialize());
+        let (sequence, payload) = record.split_once(' ').unwrap();
+        let line = format!("{} {:08x} {}\n", sequence, crc32(record.as_bytes()), payload);
+        self.file.write_all(line.as_bytes()).expect("Unable to write journal");
+        self.file.sync_data().expect("Unable to sync journal");
+        self.last_sequence
+    }
+
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    // Drops every record, the sequence goes on from the last one
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()
+    }
+
+    // Every intact record, none for a missing journal
+    pub fn records<P: AsRef<Path>>(path: P) -> io::Result<Vec<JournalRecord>> {
+        let mut content = Vec::new();
+        if let Ok(mut file) = File::open(path) {
+            file.read_to_end(&mut content)?;
+        }
+        Ok(Self::parse_records(&content)?.0)
+    }
+
+    // The intact records and the length they take, which leaves out a torn last record
+    fn parse_records(content: &[u8]) -> io::Result<(Vec<JournalRecord>, usize)> {
+        let mut records: Vec<JournalRecord> = Vec::new();
+        let mut valid_len = 0;
+        while let Some(line_len) = content[valid_len..].iter().position(|byte| *byte == b'\n') {
+            let line = &content[valid_len..valid_len + line_len];
+            let sequence = records.last().map(|record| record.sequence + 1);
+            match Self::parse_record(line, sequence) {
+                Some(record) => records.push(record),
+                None if valid_len + line_len + 1 == content.len() => break,
+                None => {
+                    let position = records.len() + 1;
+                    let message = format!("Corrupt journal record {} followed by more records", position);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+                }
+            }
+            valid_len += line_len + 1;
+        }
+        Ok((records, valid_len))
+    }
+
+    // The first record may have any sequence, the journal may have been truncated before it
+    fn parse_record(line: &[u8], expected_sequence: Option<u64>) -> Option<JournalRecord> {
+        let line = std::str::from_utf8(line).ok()?;
+        let mut parts = line.splitn(3, ' ');
+        let sequence = parts.next()?;
+        let checksum = u32::from_str_radix(parts.next()?, 16).ok()?;
+        let payload = parts.next()?;
+
+        let record = format!("{} {}", sequence, payload);
+        let sequence = sequence.parse::<u64>().ok()?;
+        if expected_sequence.is_some_and(|expected| sequence != expected) || crc32(record.as_bytes()) != checksum {
+            return None;
+        }
+        // a command starts with its kind, so a number in front of it can only be the timestamp
+        let (timestamp, command) = match payload.split_once(' ') {
+            Some((timestamp, command)) if timestamp.parse::<u64>().is_ok() => (timestamp.parse().unwrap(), command),
+            _ => (0, payload),
+        };
+        Some(JournalRecord {
+            sequence,
+            timestamp,
+            command: Command::parse(command)?,
+        })
+    }
+}
+
+impl OrderBook {
+    pub fn apply(&mut self, journal: &mut Journal, command: Command) -> Outcome {
+        // a journal truncated after the snapshot the book was restored from is empty
+        journal.last_sequence = journal.last_sequence.max(self.journal_sequence);
+        let timestamp = self.clock.now();
+        self.journal_sequence = journal.append(timestamp, &command);
+        self.execute_at(command, timestamp)
+    }
+
+    // Executes the journal tail that is not yet part of the book (e.g. after restoring a snapshot),
+    // nothing of a corrupt journal
+    pub fn replay<P: AsRef<Path>>(&mut self, journal_path: P) -> io::Result<()> {
+        let records = Journal::records(journal_path)?;
+        if let Some(first) = records.first().filter(|first| first.sequence > self.journal_sequence + 1) {
+            let message = format!("Journal starts at {} past the book at {}", first.sequence, self.journal_sequence);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        for record in records {
+            if record.sequence > self.journal_sequence {
+                self.journal_sequence = record.sequence;
+                self.execute_at(record.command, record.timestamp);
+            }
+        }
+        Ok(())
+    }
+
+    // The snapshot covers every journaled command, so the journal starts over once it is saved
+    pub fn save_checkpoint<P: AsRef<Path>>(&self, snapshot_path: P, journal: Option<&mut Journal>) -> io::Result<()> {
+        self.save_snapshot(snapshot_path)?;
+        match journal {
+            Some(journal) => journal.truncate(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use crate::deserialize::Deserialize;
+    use crate::trader::Trader;
+    use std::path::PathBuf;
+
+    const COMMANDS: [&str; 8] = [
+        "new C1 b A 8 10",
+        "new C3 b A 7 10",
+        "deposit C2 A 5",
+        "new C2 s A 7 25",
+        "new C3 b A 9 2",
+        "amend 2 8 2",
+        "new C1 b B 4 3",
+        "cancel 4",
+    ];
+
+    fn fresh_order_book() -> OrderBook {
+        OrderBook {
+            users: ["C1 2000 10 5 15 0", "C2 1000 20 35 40 10", "C3 2000 0 15 10 0"]
+                .into_iter()
+                .map(|serialized| Trader::deserialize(serialized.to_string()))
+                .map(|trader| (trader.name.clone(), trader))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn run_with_journal(journal_path: &Path, commands: &[&str]) -> OrderBook {
+        let mut order_book = fresh_order_book();
+        let mut journal = Journal::open(journal_path).unwrap();
+        for serialized_command in commands {
+            order_book.apply(&mut journal, Command::parse(serialized_command).unwrap());
+        }
+        order_book
+    }
+
+    fn state(order_book: &OrderBook, name: &str) -> (String, Vec<String>) {
+        let path = std::env::temp_dir().join(name);
+        order_book.save_snapshot(&path).unwrap();
+        let trades = order_book.trades.iter().map(|trade| trade.serialize()).collect();
+        (std::fs::read_to_string(path).unwrap(), trades)
+    }
+
+    #[test]
+    fn crc32_matches_reference_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn replay_produces_output_identical_to_original_run() {
+        let journal_path = temp_path("exchange_journal_replay.txt");
+        let original = run_with_journal(&journal_path, &COMMANDS);
+
+        let mut replayed = fresh_order_book();
+        replayed.replay(&journal_path).unwrap();
+
+        assert!(!original.trades.is_empty());
+        assert_eq!(replayed.journal_sequence, COMMANDS.len() as u64);
+        assert_eq!(
+            state(&original, "exchange_journal_original_state.txt"),
+            state(&replayed, "exchange_journal_replayed_state.txt")
+        );
+    }
+
+    #[test]
+    fn snapshot_plus_journal_tail_equals_original_run() {
+        let journal_path = temp_path("exchange_journal_tail.txt");
+        let snapshot_path = std::env::temp_dir().join("exchange_journal_tail_snapshot.txt");
+        run_with_journal(&journal_path, &COMMANDS[..4]).save_snapshot(&snapshot_path).unwrap();
+
+        let mut order_book = OrderBook::restore_snapshot(&snapshot_path).unwrap();
+        let mut journal = Journal::open(&journal_path).unwrap();
+        for serialized_command in &COMMANDS[4..] {
+            order_book.apply(&mut journal, Command::parse(serialized_command).unwrap());
+        }
+        let mut recovered = OrderBook::restore_snapshot(&snapshot_path).unwrap();
+        recovered.replay(&journal_path).unwrap();
+
+        assert_eq!(
+            state(&order_book, "exchange_journal_tail_expected.txt").0,
+            state(&recovered, "exchange_journal_tail_recovered.txt").0
+        );
+    }
+
+    #[test]
+    fn checkpoint_truncates_the_journal_and_its_sequence_goes_on() {
+        let journal_path = temp_path("exchange_journal_checkpoint.txt");
+        let snapshot_path = std::env::temp_dir().join("exchange_journal_checkpoint_snapshot.txt");
+        let mut order_book = fresh_order_book();
+        let mut journal = Journal::open(&journal_path).unwrap();
+        for serialized_command in &COMMANDS[..4] {
+            order_book.apply(&mut journal, Command::parse(serialized_command).unwrap());
+        }
+        order_book.save_checkpoint(&snapshot_path, Some(&mut journal)).unwrap();
+        assert!(Journal::records(&journal_path).unwrap().is_empty());
+
+        // restarted from the checkpoint with the journal empty
+        let mut order_book = OrderBook::restore_snapshot(&snapshot_path).unwrap();
+        let mut journal = Journal::open(&journal_path).unwrap();
+        for serialized_command in &COMMANDS[4..] {
+            order_book.apply(&mut journal, Command::parse(serialized_command).unwrap());
+        }
+        let sequences: Vec<u64> = Journal::records(&journal_path).unwrap().iter().map(|record| record.sequence).collect();
+        assert_eq!(sequences, (5..=COMMANDS.len() as u64).collect::<Vec<_>>());
+
+        let mut recovered = OrderBook::restore_snapshot(&snapshot_path).unwrap();
+        recovered.replay(&journal_path).unwrap();
+        let original = run_with_journal(&temp_path("exchange_journal_checkpoint_full.txt"), &COMMANDS);
+        assert_eq!(
+            state(&original, "exchange_journal_checkpoint_expected.txt").0,
+            state(&recovered, "exchange_journal_checkpoint_recovered.txt").0
+        );
+        // records the snapshot does not reach cannot replay onto it
+        assert!(fresh_order_book().replay(&journal_path).is_err());
+    }
+
+    #[test]
+    fn commands_the_traders_cannot_pay_for_are_journaled_and_replay_as_rejections() {
+        let journal_path = temp_path("exchange_journal_unaffordable.txt");
+        let commands = [
+            "new C1 b A 100 100",
+            "new C9 b A 1 1",
+            "new C3 s A 7 1",
+            "deposit C1 USD 18446744073709551615",
+            "new C1 b A 8 10",
+            "amend 0 100 100",
+        ];
+        let original = run_with_journal(&journal_path, &commands);
+
+        let mut replayed = fresh_order_book();
+        replayed.replay(&journal_path).unwrap();
+
+        assert_eq!(Journal::records(&journal_path).unwrap().len(), commands.len());
+        assert_eq!(replayed.metrics.rejected, 5);
+        assert_eq!(replayed.users["C1"].serialize(), "C1 1920 10 5 15 0");
+        assert!(!replayed.users.contains_key("C9"));
+        assert_eq!(
+            state(&original, "exchange_journal_unaffordable_original.txt"),
+            state(&replayed, "exchange_journal_unaffordable_replayed.txt")
+        );
+    }
+
+    #[test]
+    fn torn_final_record_is_truncated_on_open() {
+        let journal_path = temp_path("exchange_journal_torn.txt");
+        run_with_journal(&journal_path, &COMMANDS[..3]);
+        let intact = std::fs::read(&journal_path).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&journal_path).unwrap();
+        file.write_all(b"4 1a2b3c4d new C2 s A").unwrap();
+
+        let mut journal = Journal::open(&journal_path).unwrap();
+
+        assert_eq!(std::fs::read(&journal_path).unwrap(), intact);
+        assert_eq!(journal.last_sequence(), 3);
+        assert_eq!(journal.append(0, &Command::Cancel { order: 0.into() }), 4);
+        assert_eq!(Journal::records(&journal_path).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn record_with_bad_checksum_is_cut_off_last_and_corruption_anywhere_else() {
+        let journal_path = temp_path("exchange_journal_corrupt.txt");
+        run_with_journal(&journal_path, &COMMANDS[..3]);
+        let content = std::fs::read_to_string(&journal_path).unwrap();
+
+        std::fs::write(&journal_path, content.replacen("deposit C2 A 5", "deposit C2 A 9", 1)).unwrap();
+        assert_eq!(Journal::records(&journal_path).unwrap().len(), 2);
+        assert_eq!(Journal::open(&journal_path).unwrap().last_sequence(), 2);
+        assert_eq!(std::fs::read_to_string(&journal_path).unwrap().lines().count(), 2);
+
+        let corrupt = content.replacen("new C3 b A 7 10", "new C3 b A 7 99", 1);
+        std::fs::write(&journal_path, &corrupt).unwrap();
+        assert_eq!(Journal::records(&journal_path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(Journal::open(&journal_path).is_err());
+        assert!(fresh_order_book().replay(&journal_path).is_err());
+        assert_eq!(std::fs::read_to_string(&journal_path).unwrap(), corrupt);
+    }
+
+    #[test]
+    fn replay_executes_at_the_journaled_time() {
+        let journal_path = temp_path("exchange_journal_time.txt");
+        let clock = SimulatedClock::new(1_000);
+        let mut original = OrderBook {
+            clock: Box::new(clock.clone()),
+            ..fresh_order_book()
+        };
+        let mut journal = Journal::open(&journal_path).unwrap();
+        for serialized_command in &COMMANDS {
+            clock.advance(10);
+            original.apply(&mut journal, Command::parse(serialized_command).unwrap());
+        }
+
+        let mut replayed = OrderBook {
+            clock: Box::new(SimulatedClock::new(99_999)),
+            ..fresh_order_book()
+        };
+        replayed.replay(&journal_path).unwrap();
+
+        let timestamps = |order_book: &OrderBook| -> Vec<u64> { order_book.trades.iter().map(|trade| trade.timestamp).collect() };
+        assert_eq!(timestamps(&original), vec![1_040, 1_040, 1_050]);
+        assert_eq!(timestamps(&replayed), timestamps(&original));
+        let resting = |order_book: &OrderBook| -> Vec<(usize, u64, u64)> {
+            order_book.orders.values().map(|order| (order.id, order.entry_timestamp, order.update_timestamp)).collect()
+        };
+        assert_eq!(resting(&replayed), resting(&original));
+    }
+
+    #[test]
+    fn records_written_before_timestamps_still_replay() {
+        let journal_path = temp_path("exchange_journal_untimed.txt");
+        let record = "1 new C1 b A 8 10";
+        let (sequence, command) = record.split_once(' ').unwrap();
+        std::fs::write(&journal_path, format!("{} {:08x} {}\n", sequence, crc32(record.as_bytes()), command)).unwrap();
+
+        let records = Journal::records(&journal_path).unwrap();
+        assert_eq!(
+            records,
+            vec![JournalRecord {
+                sequence: 1,
+                timestamp: 0,
+                command: Command::parse("new C1 b A 8 10").unwrap(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use crate::asset_name::AssetName;
+use crate::order_matching_system::limit_tree::LimitTree;
+use crate::order_matching_system::order_book::OrderBook;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// One resting order, position is its place in the limit's queue with 0 filling first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueEntry {
+    pub position: usize,
+    pub order_id: usize,
+    pub trader: String,
+    pub amount: u64,
+    pub entry_sequence: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueLevel {
+    pub price: u64,
+    pub entries: Vec<QueueEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Level3 {
+    pub asset: AssetName,
+    pub bids: Vec<QueueLevel>,
+    pub asks: Vec<QueueLevel>,
+}
+
+impl OrderBook {
+    // Order by order view of one asset, best prices first.
+    // With a trader only their orders are kept, positions still count everyone ahead of them.
+    // Anonymised views name traders by their id instead of the name.
+    pub fn level3(&self, asset: AssetName, trader: Option<&str>, anonymise: bool) -> Level3 {
+        let side = |limits: &BTreeMap<AssetName, LimitTree>| {
+            let queues = limits.get(&asset).map(|limits| limits.queues(&self.orders)).unwrap_or_default();
+            queues
+                .into_iter()
+                .map(|(price, orders)| QueueLevel {
+                    price,
+                    entries: orders
+                        .iter()
+                        .map(|order| &self.orders[*order])
+                        .enumerate()
+                        .filter(|(_, order)| trader.is_none_or(|trader| order.trader_name == trader))
+                        .map(|(position, order)| QueueEntry {
+                            position,
+                            order_id: order.id,
+                            trader: self.trader_label(&order.trader_name, anonymise),
+                            amount: order.amount,
+                            entry_sequence: order.entry_sequence,
+                        })
+                        .collect(),
+                })
+                .filter(|level| !level.entries.is_empty())
+                .collect()
+        };
+        Level3 {
+            asset,
+            bids: side(&self.buy_limits),
+            asks: side(&self.sell_limits),
+        }
+    }
+
+    fn trader_label(&self, trader_name: &str, anonymise: bool) -> String {
+        match self.users.get(trader_name) {
+            Some(trader) if anonymise => format!("T{}", trader.id),
+            _ => trader_name.to_string(),
+        }
+    }
+}
+
+// One line per resting order: side, price, queue position, order id, trader, amount, entry sequence
+impl fmt::Display for Level3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (side, levels) in [("b", &self.bids), ("s", &self.asks)] {
+            for level in levels {
+                for entry in &level.entries {
+                    writeln!(
+                        f,
+                        "{:?} {} {} #{} order {} {} {} seq {}",
+                        self.asset, side, level.price, entry.position, entry.order_id, entry.trader, entry.amount, entry.entry_sequence
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+    use crate::deserialize::Deserialize;
+    use crate::order_matching_system::testing::clients;
+
+    fn order_book(serialized_commands: &[&str]) -> OrderBook {
+        let mut order_book = OrderBook {
+            users: clients(),
+            ..Default::default()
+        };
+        for serialized_command in serialized_commands {
+            order_book.execute(Command::parse(serialized_command).unwrap());
+        }
+        order_book
+    }
+
+    fn ids(levels: &[QueueLevel]) -> Vec<(u64, Vec<(usize, usize)>)> {
+        levels
+            .iter()
+            .map(|level| (level.price, level.entries.iter().map(|entry| (entry.position, entry.order_id)).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn level3_lists_queues_in_priority_order() {
+        let order_book = order_book(&[
+            "new C1 b A 7 10",
+            "new C2 b A 8 5",
+            "new C3 b A 7 3",
+            "new C2 s A 9 4",
+            "amend 0 7 12",
+        ]);
+
+        let level3 = order_book.level3(AssetName::A, None, false);
+
+        assert_eq!(ids(&level3.bids), vec![(8, vec![(0, 1)]), (7, vec![(0, 2), (1, 0)])]);
+        assert_eq!(ids(&level3.asks), vec![(9, vec![(0, 3)])]);
+        let amended = &level3.bids[1].entries[1];
+        assert_eq!((amended.trader.as_str(), amended.amount, amended.entry_sequence), ("C1", 12, 4));
+    }
+
+    #[test]
+    fn level3_filters_by_trader_and_keeps_queue_position() {
+        let order_book = order_book(&["new C1 b A 7 10", "new C3 b A 7 3", "new C3 b A 6 1", "new C1 b A 5 1"]);
+
+        let level3 = order_book.level3(AssetName::A, Some("C3"), false);
+
+        assert_eq!(ids(&level3.bids), vec![(7, vec![(1, 1)]), (6, vec![(0, 2)])]);
+        assert!(level3.asks.is_empty());
+    }
+
+    #[test]
+    fn anonymised_level3_hides_trader_names() {
+        let order_book = order_book(&["new C1 b A 7 10", "new C2 s A 9 3"]);
+
+        let level3 = order_book.level3(AssetName::A, None, true);
+
+        assert_eq!(level3.bids[0].entries[0].trader, format!("T{}", order_book.users["C1"].id));
+        assert_eq!(level3.asks[0].entries[0].trader, format!("T{}", order_book.users["C2"].id));
+        assert_eq!(
+            level3.to_string(),
+            format!(
+                "A b 7 #0 order 0 {} 10 seq 0\nA s 9 #0 order 1 {} 3 seq 1\n",
+                level3.bids[0].entries[0].trader, level3.asks[0].entries[0].trader
+            )
+        );
+    }
+}
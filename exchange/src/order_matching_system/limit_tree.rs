@@ -1,19 +1,37 @@
+use crate::asset_name::AssetName;
 use crate::order::*;
-use crate::trader::Trader;
-use std::cell::RefCell;
+use crate::order_matching_system::arena::{OrderArena, OrderHandle, OrderQueue};
+use crate::order_matching_system::matching_policy::MatchingPolicy;
+use crate::order_matching_system::listener::OrderListener;
+use crate::trade::Trade;
 use std::collections::BTreeMap;
-use std::collections::HashMap;
-use std::collections::VecDeque;
-use std::rc::Rc;
 
 pub struct Limit {
     price: u64,
     volume: u64,
-    orders: VecDeque<Rc<RefCell<Order>>>,
+    orders: OrderQueue,
+}
+
+// Aggregated view of one price level, volume is the open quantity of all orders in the queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Level {
+    pub price: u64,
+    pub volume: u64,
+    pub order_count: usize,
+}
+
+impl Limit {
+    pub fn level(&self) -> Level {
+        Level {
+            price: self.price,
+            volume: self.volume,
+            order_count: self.orders.len(),
+        }
+    }
 }
 
 pub struct LimitTree {
-    pub limits: BTreeMap<u64, Rc<RefCell<Limit>>>,
+    pub limits: BTreeMap<u64, Limit>,
     direction: Direction,
 }
 
@@ -22,145 +40,162 @@ impl LimitTree {
         self.limits.is_empty()
     }
 
+    pub fn orders(&self, orders: &OrderArena) -> Vec<OrderHandle> {
+        self.limits
+            .values()
+            .flat_map(|limit| limit.orders.iter(orders))
+            .collect()
+    }
+
+    // Limits starting from the best price: highest bid or lowest ask
+    fn best_first(&self) -> Box<dyn Iterator<Item = &Limit> + '_> {
+        match self.direction {
+            Direction::Buy => Box::new(self.limits.values().rev()),
+            Direction::Sell => Box::new(self.limits.values()),
+        }
+    }
+
+    // Highest bid or lowest ask
+    pub fn best_price(&self) -> Option<u64> {
+        match self.direction {
+            Direction::Buy => self.limits.last_key_value().map(|(price, _)| *price),
+            Direction::Sell => self.limits.first_key_value().map(|(price, _)| *price),
+        }
+    }
+
+    pub fn level(&self, price: u64) -> Option<Level> {
+        self.limits.get(&price).map(Limit::level)
+    }
+
+    pub fn levels(&self, n: usize) -> Vec<Level> {
+        self.best_first().take(n).map(Limit::level).collect()
+    }
+
+    // Every resting order per price, best price first and in queue priority inside a limit
+    pub fn queues(&self, orders: &OrderArena) -> Vec<(u64, Vec<OrderHandle>)> {
+        self.best_first()
+            .map(|limit| (limit.price, limit.orders.iter(orders).collect()))
+            .collect()
+    }
+
     pub fn new(direction: Direction) -> Self {
         Self {
             limits: BTreeMap::new(),
-            direction: direction,
+            direction,
         }
     }
-    pub fn new_limit(&mut self, mut order: Rc<RefCell<Order>>) {
-        if (self.limits.contains_key(&order.borrow().price)) {
-            if let Some(limit) = self.limits.get_mut(&order.borrow().price) {
-                limit.borrow_mut().volume += order.borrow().amount;
-                limit.borrow_mut().orders.push_back(order.clone());
-            }
-        } else {
-            let price = order.borrow().price;
-            let mut limit = Limit {
-                price: price,
-                volume: order.borrow().amount,
-                orders: VecDeque::new(),
-            };
-            limit.orders.push_back(order.clone());
-            self.limits.insert(price, Rc::new(RefCell::new(limit)));
-        }
+
+    pub fn new_limit(&mut self, handle: OrderHandle, orders: &mut OrderArena) {
+        let (price, amount) = (orders[handle].price, orders[handle].amount);
+        let limit = self.limits.entry(price).or_insert_with(|| Limit {
+            price,
+            volume: 0,
+            orders: OrderQueue::default(),
+        });
+        limit.volume += amount;
+        limit.orders.push_back(handle, orders);
     }
+
+    // Matches the incoming order, which is already in the arena, against the resting ones, splitting
+    // it across each level's queue by the policy, and reports every fill to the listener. Filled orders
+    // are removed from the arena, the incoming one stays there with what is left of it.
     pub fn market(
         &mut self,
-        mut order: Rc<RefCell<Order>>,
-        users: &mut BTreeMap<String, Rc<RefCell<Trader>>>,
-        orders: &mut BTreeMap<usize, Rc<RefCell<Order>>>,
+        order: OrderHandle,
+        policy: MatchingPolicy,
+        orders: &mut OrderArena,
+        listener: &mut dyn OrderListener,
     ) {
-        let limit_order_id: usize;
-        let market_order_id: usize;
-
-        while (!self.limits.is_empty()
-            && Self::matched(
-                order.borrow().price,
-                self.limits.iter().next().unwrap().0.clone(),
-                order.borrow().direction,
-            ))
-        {
-            let mut order_ref = order.borrow_mut();
-
-            let matched_limit = self.limits.iter().next().as_ref().unwrap().1.clone();
-            let matched_limit_ref = matched_limit.borrow_mut();
-            let mut matched_order = matched_limit_ref.orders.front().unwrap().clone();
-            let mut matched_order_ref = matched_order.borrow_mut();
-
-            if (matched_order_ref.amount >= order_ref.amount) {
-                market_order_id = matched_order_ref.id;
-                limit_order_id = order_ref.id;
-
-                if (matched_order_ref.amount == order_ref.amount) {
-                    drop(matched_limit_ref);
-                    drop(order_ref);
-                    drop(matched_order_ref);
-
-                    self.finish(matched_limit);
-                    self.on_fill(market_order_id, limit_order_id, users, orders);
-                } else {
-                    matched_order_ref.amount -= order_ref.amount;
-                    drop(order_ref);
-                    drop(matched_order_ref);
-                    self.on_fill(limit_order_id, market_order_id, users, orders);
+        let order_id = orders[order].id;
+
+        while let Some(price) = self.best_price() {
+            if (!Self::matched(orders[order].price, price, orders[order].direction)) {
+                break;
+            }
+            let limit = self.limits.get_mut(&price).unwrap();
+            let queue = limit.orders.iter(orders).map(|handle| (handle, orders[handle].amount));
+            let allocations = policy.allocate(orders[order].amount, limit.volume, queue);
+
+            for (matched_order, amount) in allocations {
+                let matched_order_id = orders[matched_order].id;
+                let matched_filled = orders[matched_order].amount == amount;
+                let (buy, sell) = match orders[order].direction {
+                    Direction::Buy => (&orders[order], &orders[matched_order]),
+                    Direction::Sell => (&orders[matched_order], &orders[order]),
+                };
+                let trade = Trade {
+                    asset: buy.asset,
+                    // every fill of the level is at its price, however the incoming quantity is split
+                    price,
+                    amount,
+                    buy_order_id: buy.id,
+                    sell_order_id: sell.id,
+                    buyer: buy.trader_name.clone(),
+                    seller: sell.trader_name.clone(),
+                    ..Default::default()
+                };
+                listener.on_fill(&trade, buy, sell);
+                limit.volume -= amount;
+                orders[order].amount -= amount;
+                orders[matched_order].amount -= amount;
+                // the incoming order was stamped with the time of the command as it entered
+                orders[matched_order].update_timestamp = orders[order].update_timestamp;
+                if (matched_filled) {
+                    limit.orders.remove(matched_order, orders);
+                    orders.remove(&matched_order_id);
                 }
-                return;
             }
-            drop(order_ref);
-            drop(matched_order_ref);
-            drop(matched_limit_ref);
-            self.finish(matched_limit);
-            self.on_fill(matched_order.borrow().id, order.borrow().id, users, orders);
-            order.borrow_mut().amount -= matched_order.borrow().amount;
+            if limit.orders.is_empty() {
+                self.limits.remove(&price);
+            }
+            if (orders[order].amount == 0) {
+                orders.remove(&order_id);
+                break;
+            }
         }
     }
 
-    fn matched(limit: u64, market: u64, direction: Direction) -> bool {
-        match direction {
-            Direction::Sell => limit <= market,
-            Direction::Buy => limit >= market,
+    pub fn remove(&mut self, handle: OrderHandle, orders: &mut OrderArena) -> Option<OrderHandle> {
+        let price = orders[handle].price;
+        let limit = self.limits.get_mut(&price)?;
+        if (!limit.orders.remove(handle, orders)) {
+            return None;
         }
+        limit.volume -= orders[handle].amount;
+        if limit.orders.is_empty() {
+            self.limits.remove(&price);
+        }
+        Some(handle)
     }
 
-    fn finish(&mut self, limit: Rc<RefCell<Limit>>) {
-        if (limit.borrow().orders.len() == 1) {
-            self.limits.remove(&limit.borrow().price);
-        } else {
-            limit.borrow_mut().orders.pop_front();
+    // The order at price was reduced in place by amount
+    pub fn reduce(&mut self, price: u64, amount: u64) {
+        if let Some(limit) = self.limits.get_mut(&price) {
+            limit.volume -= amount;
         }
     }
 
-    fn on_fill(
-        &mut self,
-        filled_order_id: usize,
-        donor_order_id: usize,
-        traders: &mut BTreeMap<String, Rc<RefCell<Trader>>>,
-        orders: &mut BTreeMap<usize, Rc<RefCell<Order>>>,
-    ) {
-        if (filled_order_id != donor_order_id) {
-            let filled_order = orders[&filled_order_id].clone();
-            let filled_order_ref = filled_order.borrow();
-            let donor_order = orders[&donor_order_id].clone();
-            let donor_order_ref = donor_order.borrow();
-
-            let mut filled_trader = traders[&filled_order_ref.trader_name].borrow_mut();
-            let mut donor_trader = traders[&donor_order_ref.trader_name].borrow_mut();
-
-            if (filled_order_ref.direction == Direction::Buy) {
-                *filled_trader
-                    .assets_count
-                    .get_mut(&filled_order_ref.asset)
-                    .unwrap() += filled_order_ref.amount;
-                donor_trader.usd_balance += filled_order_ref.price * filled_order_ref.amount;
-            } else {
-                filled_trader.usd_balance += filled_order_ref.amount * filled_order_ref.price;
-                *donor_trader
-                    .assets_count
-                    .get_mut(&filled_order_ref.asset)
-                    .unwrap() += filled_order_ref.amount;
-                donor_trader.usd_balance +=
-                    (donor_order_ref.price - filled_order_ref.price) * filled_order_ref.amount;
-            }
+    fn matched(limit: u64, market: u64, direction: Direction) -> bool {
+        match direction {
+            Direction::Sell => limit <= market,
+            Direction::Buy => limit >= market,
         }
-        orders.remove(&filled_order_id);
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::borrow::Borrow;
-
     use super::*;
 
     #[test]
     fn new_order_inserter_to_empty_limit_tree() {
-        let order = Order::default();
+        let mut orders = OrderArena::default();
+        let order = orders.insert(Order::default());
         let mut limit_tree = LimitTree {
             limits: BTreeMap::new(),
             direction: Direction::Buy,
         };
-        limit_tree.new_limit(Rc::new(RefCell::new(order)));
+        limit_tree.new_limit(order, &mut orders);
         assert_eq!(limit_tree.limits.len(), 1);
     }
 
@@ -182,13 +217,17 @@ mod tests {
             limits: BTreeMap::new(),
             direction: Direction::Buy,
         };
-        limit_tree.new_limit(Rc::new(RefCell::new(order)));
-        limit_tree.new_limit(Rc::new(RefCell::new(order1)));
+        let mut orders = OrderArena::default();
+        for order in [order, order1] {
+            let handle = orders.insert(order);
+            limit_tree.new_limit(handle, &mut orders);
+        }
 
         assert_eq!(limit_tree.limits.len(), 1);
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn matched_fn_returns_correct_vlues() {
         let market = 10;
         let limit = 20;
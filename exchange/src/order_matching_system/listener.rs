@@ -0,0 +1,213 @@
+use crate::command::Command;
+use crate::order::Order;
+use crate::trade::Trade;
+use std::sync::{Arc, Mutex};
+
+pub const UNKNOWN_ORDER: &str = "Unknown order";
+
+// Callbacks from the book as orders go through it, in the order things happen. Every callback
+// does nothing by default, so a listener implements only the ones it needs.
+pub trait OrderListener {
+    // the order entered the book and is about to match
+    fn on_accept(&mut self, order: &Order) {}
+    // the command changed nothing, reason says why
+    fn on_reject(&mut self, command: &Command, reason: &str) {}
+    // buy and sell as they were before the fill, the trade is already numbered and stamped
+    fn on_fill(&mut self, trade: &Trade, buy: &Order, sell: &Order) {}
+    // old is replaced by new: a smaller amount at the same price keeps queue priority, anything else re-enters
+    fn on_amend(&mut self, old: &Order, new: &Order) {}
+    fn on_cancel(&mut self, order: &Order) {}
+    // the book cancelled the order itself, a day order at the close
+    fn on_expire(&mut self, order: &Order) {}
+    // what is left of an incoming order rests at its limit
+    fn on_rest(&mut self, order: &Order) {}
+}
+
+// One callback with its arguments, for code that reports while the book is borrowed and for tests
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderEvent {
+    Accept(Order),
+    Reject(Command, String),
+    Fill { trade: Trade, buy: Order, sell: Order },
+    Amend { old: Order, new: Order },
+    Cancel(Order),
+    Expire(Order),
+    Rest(Order),
+}
+
+impl OrderEvent {
+    pub fn deliver(&self, listener: &mut dyn OrderListener) {
+        match self {
+            OrderEvent::Accept(order) => listener.on_accept(order),
+            OrderEvent::Reject(command, reason) => listener.on_reject(command, reason),
+            OrderEvent::Fill { trade, buy, sell } => listener.on_fill(trade, buy, sell),
+            OrderEvent::Amend { old, new } => listener.on_amend(old, new),
+            OrderEvent::Cancel(order) => listener.on_cancel(order),
+            OrderEvent::Expire(order) => listener.on_expire(order),
+            OrderEvent::Rest(order) => listener.on_rest(order),
+        }
+    }
+}
+
+// Records the events to deliver them later
+impl OrderListener for Vec<OrderEvent> {
+    fn on_accept(&mut self, order: &Order) {
+        self.push(OrderEvent::Accept(order.clone()));
+    }
+
+    fn on_reject(&mut self, command: &Command, reason: &str) {
+        self.push(OrderEvent::Reject(command.clone(), reason.to_string()));
+    }
+
+    fn on_fill(&mut self, trade: &Trade, buy: &Order, sell: &Order) {
+        self.push(OrderEvent::Fill {
+            trade: trade.clone(),
+            buy: buy.clone(),
+            sell: sell.clone(),
+        });
+    }
+
+    fn on_amend(&mut self, old: &Order, new: &Order) {
+        self.push(OrderEvent::Amend {
+            old: old.clone(),
+            new: new.clone(),
+        });
+    }
+
+    fn on_cancel(&mut self, order: &Order) {
+        self.push(OrderEvent::Cancel(order.clone()));
+    }
+
+    fn on_expire(&mut self, order: &Order) {
+        self.push(OrderEvent::Expire(order.clone()));
+    }
+
+    fn on_rest(&mut self, order: &Order) {
+        self.push(OrderEvent::Rest(order.clone()));
+    }
+}
+
+// A listener the book owns and someone else reads, e.g. the events an instrument of the matching engine records
+impl<L: OrderListener> OrderListener for Arc<Mutex<L>> {
+    fn on_accept(&mut self, order: &Order) {
+        self.lock().unwrap().on_accept(order);
+    }
+
+    fn on_reject(&mut self, command: &Command, reason: &str) {
+        self.lock().unwrap().on_reject(command, reason);
+    }
+
+    fn on_fill(&mut self, trade: &Trade, buy: &Order, sell: &Order) {
+        self.lock().unwrap().on_fill(trade, buy, sell);
+    }
+
+    fn on_amend(&mut self, old: &Order, new: &Order) {
+        self.lock().unwrap().on_amend(old, new);
+    }
+
+    fn on_cancel(&mut self, order: &Order) {
+        self.lock().unwrap().on_cancel(order);
+    }
+
+    fn on_expire(&mut self, order: &Order) {
+        self.lock().unwrap().on_expire(order);
+    }
+
+    fn on_rest(&mut self, order: &Order) {
+        self.lock().unwrap().on_rest(order);
+    }
+}
+
+// The trade log of the book
+impl OrderListener for Vec<Trade> {
+    fn on_fill(&mut self, trade: &Trade, buy: &Order, sell: &Order) {
+        self.push(trade.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_matching_system::order_book::OrderBook;
+    use crate::order_matching_system::session::INSTRUMENT_HALTED;
+    use crate::order_matching_system::testing::{execute_all, funded_order_book};
+
+    fn recorded_book() -> (OrderBook, Arc<Mutex<Vec<OrderEvent>>>) {
+        let mut order_book = funded_order_book();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        order_book.add_listener(events.clone());
+        (order_book, events)
+    }
+
+    // Kind and order id of every event, the id of the incoming order for a fill
+    fn summary(events: &[OrderEvent]) -> Vec<(&'static str, usize)> {
+        events
+            .iter()
+            .map(|event| match event {
+                OrderEvent::Accept(order) => ("accept", order.id),
+                OrderEvent::Reject(..) => ("reject", usize::MAX),
+                OrderEvent::Fill { trade, .. } => ("fill", trade.buy_order_id.max(trade.sell_order_id)),
+                OrderEvent::Amend { new, .. } => ("amend", new.id),
+                OrderEvent::Cancel(order) => ("cancel", order.id),
+                OrderEvent::Expire(order) => ("expire", order.id),
+                OrderEvent::Rest(order) => ("rest", order.id),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn listeners_hear_every_step_of_an_order_in_order() {
+        let (mut order_book, events) = recorded_book();
+        execute_all(
+            &mut order_book,
+            &["new C1 b A 8 10", "new C2 s A 7 4", "new C4 s A 8 10", "amend 2 9 6", "cancel 2", "cancel 2"],
+        );
+        let events = events.lock().unwrap();
+        assert_eq!(
+            summary(&events),
+            vec![
+                ("accept", 0),
+                ("rest", 0),
+                ("accept", 1),
+                ("fill", 1),
+                ("accept", 2),
+                ("fill", 2),
+                ("rest", 2),
+                ("amend", 2),
+                ("rest", 2),
+                ("cancel", 2),
+                ("reject", usize::MAX),
+            ]
+        );
+
+        // the book numbered and stamped the trade before anyone saw it
+        let OrderEvent::Fill { trade, buy, sell } = &events[3] else {
+            panic!("Expected a fill, got {:?}", events[3]);
+        };
+        assert_eq!(trade, &order_book.trades[0]);
+        assert_eq!((buy.amount, sell.amount), (10, 4));
+        assert_eq!(events[10], OrderEvent::Reject(Command::Cancel { order: 2.into() }, UNKNOWN_ORDER.to_string()));
+    }
+
+    #[test]
+    fn session_rejections_and_expiries_reach_listeners() {
+        let (mut order_book, events) = recorded_book();
+        execute_all(
+            &mut order_book,
+            &["new C1 b B 8 10 day", "new C3 b B 7 10", "session B halted", "new C2 s B 7 1", "session B closed"],
+        );
+        let events = events.lock().unwrap();
+        assert_eq!(
+            summary(&events),
+            vec![("accept", 0), ("rest", 0), ("accept", 1), ("rest", 1), ("reject", usize::MAX), ("expire", 0)]
+        );
+        assert!(matches!(&events[4], OrderEvent::Reject(_, reason) if reason == INSTRUMENT_HALTED));
+        assert_eq!(order_book.users["C1"].reserved_usd, 0);
+        assert_eq!(order_book.users["C3"].reserved_usd, 70);
+
+        assert_eq!(
+            order_book.metrics.to_string(),
+            "accepted 2 rejected 1 fills 0 volume 0 amended 0 cancelled 0 expired 1 rested 2"
+        );
+    }
+}
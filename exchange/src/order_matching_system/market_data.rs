@@ -0,0 +1,458 @@
+use crate::asset_name::AssetName;
+use crate::command::Command;
+use crate::order::{Direction, Order};
+use crate::order_matching_system::auction::Uncross;
+use crate::order_matching_system::limit_tree::Level;
+use crate::order_matching_system::listener::OrderListener;
+use crate::order_matching_system::order_book::{Depth, OrderBook};
+use crate::trade::Trade;
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 100;
+// Messages the feed holds until a consumer drains them. Past that the oldest are dropped, a consumer
+// that falls that far behind sees a sequence gap and resyncs on the next snapshot.
+pub const MAX_BUFFERED_MESSAGES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstrumentStatus {
+    Trading,
+    Halted,
+    Closed,
+    // orders rest without matching until the call uncrosses
+    Auction,
+    // orders rest without matching until the opening auction
+    PreOpen,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarketDataEvent {
+    LevelAdd { side: Direction, level: Level },
+    LevelChange { side: Direction, level: Level },
+    LevelDelete { side: Direction, price: u64 },
+    Trade { trade_id: usize, price: u64, amount: u64 },
+    Status(InstrumentStatus),
+    // where the call would uncross now, volume 0 while nothing crosses
+    Indicative { price: u64, volume: u64, imbalance: i64 },
+    // full L2 state, a consumer can start from any snapshot
+    Snapshot(Depth),
+}
+
+// sequence counts every event of one asset starting from 1, with no gaps.
+// timestamp is the time of the command that caused the event, in nanoseconds since the epoch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarketDataMessage {
+    pub asset: AssetName,
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub event: MarketDataEvent,
+}
+
+// Levels of one asset as last published, and the prices on each side whose level an order event
+// may have changed since
+#[derive(Debug, Default)]
+struct PublishedBook {
+    bids: BTreeMap<u64, Level>,
+    asks: BTreeMap<u64, Level>,
+    touched_bids: BTreeSet<u64>,
+    touched_asks: BTreeSet<u64>,
+}
+
+impl PublishedBook {
+    fn side_mut(&mut self, side: Direction) -> (&mut BTreeMap<u64, Level>, &mut BTreeSet<u64>) {
+        match side {
+            Direction::Buy => (&mut self.bids, &mut self.touched_bids),
+            Direction::Sell => (&mut self.asks, &mut self.touched_asks),
+        }
+    }
+
+    fn depth(&self) -> Depth {
+        Depth {
+            bids: self.bids.values().rev().copied().collect(),
+            asks: self.asks.values().copied().collect(),
+        }
+    }
+}
+
+// Published L2 state per asset. Only the levels order events touched are compared with it, so
+// publishing after a command costs what the command changed, not the size of the book.
+#[derive(Debug)]
+pub struct MarketDataFeed {
+    // not yet drained by a consumer, at most MAX_BUFFERED_MESSAGES
+    pub messages: Vec<MarketDataMessage>,
+    pub snapshot_interval: u64,
+    sequences: BTreeMap<AssetName, u64>,
+    since_snapshot: BTreeMap<AssetName, u64>,
+    published: BTreeMap<AssetName, PublishedBook>,
+    statuses: BTreeMap<AssetName, InstrumentStatus>,
+    indicative: BTreeMap<AssetName, Option<Uncross>>,
+    // time the events being published are stamped with
+    timestamp: u64,
+}
+
+impl Default for MarketDataFeed {
+    fn default() -> Self {
+        MarketDataFeed {
+            messages: Vec::new(),
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+            sequences: BTreeMap::new(),
+            since_snapshot: BTreeMap::new(),
+            published: BTreeMap::new(),
+            statuses: BTreeMap::new(),
+            indicative: BTreeMap::new(),
+            timestamp: 0,
+        }
+    }
+}
+
+impl MarketDataFeed {
+    // Hands the buffered messages to a consumer, e.g. the UDP publisher
+    pub fn drain(&mut self) -> Vec<MarketDataMessage> {
+        std::mem::take(&mut self.messages)
+    }
+
+    fn touch(&mut self, asset: AssetName, side: Direction, price: u64) {
+        self.published.entry(asset).or_default().side_mut(side).1.insert(price);
+    }
+
+    fn publish(&mut self, asset: AssetName, event: MarketDataEvent) {
+        let sequence = self.sequences.entry(asset).or_insert(0);
+        *sequence += 1;
+        *self.since_snapshot.entry(asset).or_insert(0) += 1;
+        if let MarketDataEvent::Status(status) = event {
+            self.statuses.insert(asset, status);
+        }
+        if (self.messages.len() >= MAX_BUFFERED_MESSAGES) {
+            self.messages.drain(..MAX_BUFFERED_MESSAGES / 2);
+        }
+        self.messages.push(MarketDataMessage {
+            asset,
+            sequence: *sequence,
+            timestamp: self.timestamp,
+            event,
+        });
+    }
+
+    // Publishes what became of the touched levels of one side, given by the current level at a price:
+    // deletes first, then additions and changes, by price
+    fn publish_levels(&mut self, asset: AssetName, side: Direction, current: impl Fn(u64) -> Option<Level>) {
+        let (levels, touched) = self.published.entry(asset).or_default().side_mut(side);
+        let changes: Vec<(u64, Option<Level>, Option<Level>)> = std::mem::take(touched)
+            .into_iter()
+            .map(|price| (price, levels.get(&price).copied(), current(price)))
+            .filter(|(_, before, after)| before != after)
+            .collect();
+        for (price, _, after) in &changes {
+            match after {
+                Some(level) => levels.insert(*price, *level),
+                None => levels.remove(price),
+            };
+        }
+        for (price, before, after) in &changes {
+            if (before.is_some() && after.is_none()) {
+                self.publish(asset, MarketDataEvent::LevelDelete { side, price: *price });
+            }
+        }
+        for (_, before, after) in changes {
+            match (before, after) {
+                (None, Some(level)) => self.publish(asset, MarketDataEvent::LevelAdd { side, level }),
+                (Some(_), Some(level)) => self.publish(asset, MarketDataEvent::LevelChange { side, level }),
+                _ => {}
+            }
+        }
+    }
+}
+
+// Every level an order joins or leaves is touched: the resting side of a fill at its price, what an
+// amendment moves from and to, and what is cancelled or expires
+impl OrderListener for MarketDataFeed {
+    fn on_rest(&mut self, order: &Order) {
+        self.touch(order.asset, order.direction, order.price);
+    }
+
+    fn on_amend(&mut self, old: &Order, new: &Order) {
+        self.touch(old.asset, old.direction, old.price);
+        self.touch(new.asset, new.direction, new.price);
+    }
+
+    fn on_cancel(&mut self, order: &Order) {
+        self.touch(order.asset, order.direction, order.price);
+    }
+
+    fn on_expire(&mut self, order: &Order) {
+        self.on_cancel(order);
+    }
+
+    fn on_fill(&mut self, trade: &Trade, buy: &Order, sell: &Order) {
+        // the incoming order of the two is not resting, its level is touched to no effect
+        self.touch(buy.asset, Direction::Buy, buy.price);
+        self.touch(sell.asset, Direction::Sell, sell.price);
+        self.timestamp = trade.timestamp;
+        self.publish(
+            trade.asset,
+            MarketDataEvent::Trade {
+                trade_id: trade.id,
+                price: trade.price,
+                amount: trade.amount,
+            },
+        );
+    }
+}
+
+impl OrderBook {
+    // Asset whose book the command may change, before the command runs
+    pub(crate) fn command_asset(&self, command: &Command) -> Option<AssetName> {
+        match command {
+            Command::NewOrder(order) => Some(order.asset),
+            Command::Cancel { order } | Command::Amend { order, .. } => {
+                let order_id = self.client_order_ids.resolve(order)?;
+                self.orders.get(&order_id).map(|order| order.asset)
+            }
+            Command::Deposit { .. } => None,
+            Command::Session { asset, .. } => Some(*asset),
+        }
+    }
+
+    // Publishes a change of the asset's status, the level changes of its book and during an auction
+    // a change of the indicative uncross. Trades are published as they happen, by the feed's on_fill.
+    pub(crate) fn publish_market_data(&mut self, asset: AssetName) {
+        let status = self.session(asset).status();
+        let feed = &mut self.market_data;
+        feed.timestamp = self.now;
+        if !feed.sequences.contains_key(&asset) {
+            feed.publish(asset, MarketDataEvent::Status(status));
+            // the book may have been restored from a snapshot without any order events
+            let depth = self.depth(asset, usize::MAX);
+            for (side, levels) in [(Direction::Buy, depth.bids), (Direction::Sell, depth.asks)] {
+                for level in levels {
+                    self.market_data.touch(asset, side, level.price);
+                }
+            }
+        }
+        let feed = &mut self.market_data;
+        if feed.statuses.get(&asset) != Some(&status) {
+            feed.publish(asset, MarketDataEvent::Status(status));
+        }
+
+        let indicative = (status == InstrumentStatus::Auction).then(|| self.indicative(asset));
+        let (bids, asks) = (self.buy_limits.get(&asset), self.sell_limits.get(&asset));
+        let feed = &mut self.market_data;
+        feed.publish_levels(asset, Direction::Buy, |price| bids.and_then(|limits| limits.level(price)));
+        feed.publish_levels(asset, Direction::Sell, |price| asks.and_then(|limits| limits.level(price)));
+        if feed.since_snapshot[&asset] >= feed.snapshot_interval {
+            let depth = feed.published.entry(asset).or_default().depth();
+            feed.publish(asset, MarketDataEvent::Snapshot(depth));
+            feed.since_snapshot.insert(asset, 0);
+        }
+
+        match indicative {
+            Some(indicative) if feed.indicative.get(&asset) != Some(&indicative) => {
+                let Uncross { price, volume, imbalance } = indicative.unwrap_or_default();
+                feed.publish(asset, MarketDataEvent::Indicative { price, volume, imbalance });
+                feed.indicative.insert(asset, indicative);
+            }
+            Some(_) => {}
+            None => {
+                feed.indicative.remove(&asset);
+            }
+        }
+    }
+
+    pub fn publish_status(&mut self, asset: AssetName, status: InstrumentStatus) {
+        self.market_data.timestamp = self.now;
+        self.market_data.publish(asset, MarketDataEvent::Status(status));
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FeedError {
+    // the consumer has to wait for the next snapshot
+    SequenceGap { expected: u64, received: u64 },
+}
+
+// L2 book of one asset rebuilt from the feed
+#[derive(Debug)]
+pub struct LocalBook {
+    pub asset: AssetName,
+    pub status: Option<InstrumentStatus>,
+    bids: BTreeMap<u64, Level>,
+    asks: BTreeMap<u64, Level>,
+    next_sequence: Option<u64>,
+}
+
+impl LocalBook {
+    // Starts from the first message of the feed
+    pub fn new(asset: AssetName) -> Self {
+        LocalBook {
+            asset,
+            status: None,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            next_sequence: Some(1),
+        }
+    }
+
+    // Ignores incremental messages until the next snapshot
+    pub fn late_joiner(asset: AssetName) -> Self {
+        LocalBook {
+            next_sequence: None,
+            ..Self::new(asset)
+        }
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.next_sequence.is_some()
+    }
+
+    pub fn apply(&mut self, message: &MarketDataMessage) -> Result<(), FeedError> {
+        if message.asset != self.asset {
+            return Ok(());
+        }
+        if let MarketDataEvent::Snapshot(depth) = &message.event {
+            self.bids = depth.bids.iter().map(|level| (level.price, *level)).collect();
+            self.asks = depth.asks.iter().map(|level| (level.price, *level)).collect();
+            self.next_sequence = Some(message.sequence + 1);
+            return Ok(());
+        }
+        match self.next_sequence {
+            None => return Ok(()),
+            Some(expected) if expected != message.sequence => {
+                self.next_sequence = None;
+                return Err(FeedError::SequenceGap {
+                    expected,
+                    received: message.sequence,
+                });
+            }
+            Some(expected) => self.next_sequence = Some(expected + 1),
+        }
+
+        match &message.event {
+            MarketDataEvent::LevelAdd { side, level } | MarketDataEvent::LevelChange { side, level } => {
+                self.side_mut(*side).insert(level.price, *level);
+            }
+            MarketDataEvent::LevelDelete { side, price } => {
+                self.side_mut(*side).remove(price);
+            }
+            MarketDataEvent::Status(status) => self.status = Some(*status),
+            MarketDataEvent::Trade { .. } | MarketDataEvent::Indicative { .. } | MarketDataEvent::Snapshot(_) => {}
+        }
+        Ok(())
+    }
+
+    pub fn depth(&self, n: usize) -> Depth {
+        Depth {
+            bids: self.bids.values().rev().take(n).copied().collect(),
+            asks: self.asks.values().take(n).copied().collect(),
+        }
+    }
+
+    fn side_mut(&mut self, side: Direction) -> &mut BTreeMap<u64, Level> {
+        match side {
+            Direction::Buy => &mut self.bids,
+            Direction::Sell => &mut self.asks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_matching_system::testing::{command_strategy, execute_all, funded_order_book};
+    use proptest::prelude::*;
+
+    #[test]
+    fn feed_publishes_sequenced_level_and_trade_events() {
+        let mut order_book = funded_order_book();
+        execute_all(&mut order_book, &["new C1 b A 7 10", "new C2 s A 7 4", "new C3 b B 5 1", "cancel 0"]);
+
+        let events: Vec<(AssetName, u64, MarketDataEvent)> = order_book
+            .market_data
+            .messages
+            .iter()
+            .map(|message| (message.asset, message.sequence, message.event.clone()))
+            .collect();
+        let level = |price, volume| Level {
+            price,
+            volume,
+            order_count: 1,
+        };
+        assert_eq!(
+            events,
+            vec![
+                (AssetName::A, 1, MarketDataEvent::Status(InstrumentStatus::Trading)),
+                (AssetName::A, 2, MarketDataEvent::LevelAdd { side: Direction::Buy, level: level(7, 10) }),
+                (AssetName::A, 3, MarketDataEvent::Trade { trade_id: 0, price: 7, amount: 4 }),
+                (AssetName::A, 4, MarketDataEvent::LevelChange { side: Direction::Buy, level: level(7, 6) }),
+                (AssetName::B, 1, MarketDataEvent::Status(InstrumentStatus::Trading)),
+                (AssetName::B, 2, MarketDataEvent::LevelAdd { side: Direction::Buy, level: level(5, 1) }),
+                (AssetName::A, 5, MarketDataEvent::LevelDelete { side: Direction::Buy, price: 7 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn late_joiner_syncs_on_the_next_snapshot() {
+        let mut order_book = funded_order_book();
+        order_book.market_data.snapshot_interval = 3;
+        execute_all(&mut order_book, &["new C1 b A 7 10", "new C1 b A 6 10", "new C2 s A 9 1", "new C2 s A 8 2"]);
+
+        let mut local_book = LocalBook::late_joiner(AssetName::A);
+        for message in &order_book.market_data.messages[1..] {
+            local_book.apply(message).unwrap();
+        }
+
+        assert!(local_book.is_synced());
+        assert_eq!(local_book.depth(usize::MAX), order_book.depth(AssetName::A, usize::MAX));
+    }
+
+    #[test]
+    fn gap_in_sequence_unsyncs_the_local_book() {
+        let mut order_book = funded_order_book();
+        execute_all(&mut order_book, &["new C1 b A 7 10", "new C1 b A 6 10"]);
+
+        let mut local_book = LocalBook::new(AssetName::A);
+        local_book.apply(&order_book.market_data.messages[0]).unwrap();
+
+        assert_eq!(
+            local_book.apply(&order_book.market_data.messages[2]),
+            Err(FeedError::SequenceGap { expected: 2, received: 3 })
+        );
+        assert!(!local_book.is_synced());
+    }
+
+    #[test]
+    fn undrained_messages_are_capped_and_dropped_oldest_first() {
+        let mut order_book = funded_order_book();
+        // every order changes the level
+        for _ in 0..=MAX_BUFFERED_MESSAGES {
+            execute_all(&mut order_book, &["new C1 b A 7 1"]);
+        }
+        let messages = order_book.market_data.drain();
+        assert!(messages.len() <= MAX_BUFFERED_MESSAGES);
+        assert!(messages[0].sequence > 1);
+        assert_eq!(messages.last().unwrap().sequence, order_book.market_data.sequences[&AssetName::A]);
+        assert!(order_book.market_data.messages.is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn local_book_matches_order_book_depth(
+            commands in prop::collection::vec(command_strategy(), 1..60),
+            snapshot_interval in 1..20u64,
+        ) {
+            let mut order_book = funded_order_book();
+            order_book.market_data.snapshot_interval = snapshot_interval;
+            let mut local_books = [LocalBook::new(AssetName::A), LocalBook::new(AssetName::B)];
+
+            for command in &commands {
+                let published = order_book.market_data.messages.len();
+                order_book.execute(Command::parse(command).unwrap());
+                for local_book in &mut local_books {
+                    for message in &order_book.market_data.messages[published..] {
+                        prop_assert_eq!(local_book.apply(message), Ok(()));
+                    }
+                    prop_assert_eq!(local_book.depth(usize::MAX), order_book.depth(local_book.asset, usize::MAX));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use crate::asset_name::AssetName;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+pub const MATCHING_POLICIES_PATH: &str = "resources/matching_policies.txt";
+
+// How an incoming quantity is split across the queue of one price level
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MatchingPolicy {
+    // price-time priority: the queue is filled from the front
+    #[default]
+    Fifo,
+    // every order gets the share of its amount in the level's volume, rounded down to whole lots.
+    // What rounding leaves over is filled from the front of the queue.
+    ProRata { lot: u64 },
+    // the order at the front of the queue first gets priority_slice percent of the incoming quantity,
+    // the rest is split pro-rata over what every order still has open
+    Hybrid { priority_slice: u64, lot: u64 },
+}
+
+impl MatchingPolicy {
+    // Fills of the queued orders, given with their open amounts in queue order, by an incoming
+    // quantity against a level of `volume`. Orders that get nothing are left out, a FIFO
+    // allocation only reads the queue as far as it fills.
+    pub fn allocate<T>(&self, incoming: u64, volume: u64, queue: impl Iterator<Item = (T, u64)>) -> Vec<(T, u64)> {
+        match *self {
+            MatchingPolicy::Fifo => {
+                let mut left = incoming;
+                queue
+                    .map_while(|(order, amount)| {
+                        let fill = amount.min(left);
+                        left -= fill;
+                        (fill > 0).then_some((order, fill))
+                    })
+                    .collect()
+            }
+            MatchingPolicy::ProRata { lot } => {
+                let queue: Vec<(T, u64)> = queue.collect();
+                let fills = pro_rata(incoming, volume, &queue.iter().map(|(_, amount)| *amount).collect::<Vec<_>>(), lot);
+                Self::allocated(queue, fills)
+            }
+            MatchingPolicy::Hybrid { priority_slice, lot } => {
+                let queue: Vec<(T, u64)> = queue.collect();
+                let mut amounts: Vec<u64> = queue.iter().map(|(_, amount)| *amount).collect();
+                let top = amounts.first().map_or(0, |amount| (incoming * priority_slice.min(100) / 100).min(*amount));
+                if let Some(amount) = amounts.first_mut() {
+                    *amount -= top;
+                }
+                let mut fills = pro_rata(incoming - top, volume - top, &amounts, lot);
+                if let Some(fill) = fills.first_mut() {
+                    *fill += top;
+                }
+                Self::allocated(queue, fills)
+            }
+        }
+    }
+
+    fn allocated<T>(queue: Vec<(T, u64)>, fills: Vec<u64>) -> Vec<(T, u64)> {
+        queue
+            .into_iter()
+            .zip(fills)
+            .filter(|(_, fill)| *fill > 0)
+            .map(|((order, _), fill)| (order, fill))
+            .collect()
+    }
+}
+
+// Pro-rata fills of the amounts by incoming, the rounding remainder filled in queue order
+fn pro_rata(incoming: u64, volume: u64, amounts: &[u64], lot: u64) -> Vec<u64> {
+    if (incoming >= volume) {
+        return amounts.to_vec();
+    }
+    let lot = lot.max(1);
+    let mut fills: Vec<u64> = amounts
+        .iter()
+        .map(|amount| (incoming as u128 * *amount as u128 / volume as u128) as u64 / lot * lot)
+        .collect();
+    let mut left = incoming - fills.iter().sum::<u64>();
+    for (fill, amount) in fills.iter_mut().zip(amounts) {
+        let extra = (amount - *fill).min(left);
+        *fill += extra;
+        left -= extra;
+    }
+    fills
+}
+
+// "fifo", "pro_rata <lot>" or "hybrid <priority slice percent> <lot>"
+impl FromStr for MatchingPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let number = |part: &str| part.parse::<u64>().map_err(|_| format!("Bad matching policy: {}", s));
+        match parts.as_slice() {
+            ["fifo"] => Ok(MatchingPolicy::Fifo),
+            ["pro_rata", lot] => Ok(MatchingPolicy::ProRata { lot: number(lot)? }),
+            ["hybrid", priority_slice, lot] => Ok(MatchingPolicy::Hybrid {
+                priority_slice: number(priority_slice)?,
+                lot: number(lot)?,
+            }),
+            _ => Err(format!("Bad matching policy: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for MatchingPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchingPolicy::Fifo => write!(f, "fifo"),
+            MatchingPolicy::ProRata { lot } => write!(f, "pro_rata {}", lot),
+            MatchingPolicy::Hybrid { priority_slice, lot } => write!(f, "hybrid {} {}", priority_slice, lot),
+        }
+    }
+}
+
+// Lines of "<asset> <policy>", instruments that are not listed match FIFO. No file means no overrides.
+pub fn load_matching_policies<P: AsRef<Path>>(path: P) -> BTreeMap<AssetName, MatchingPolicy> {
+    let Ok(file) = File::open(path) else {
+        return BTreeMap::new();
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (asset, policy) = line.split_once(' ').expect("Matching policy line is <asset> <policy>");
+            (
+                AssetName::from_str(asset).expect("Unknown asset"),
+                policy.parse().expect("Couldn't parse matching policy"),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocate(policy: MatchingPolicy, incoming: u64, amounts: &[u64]) -> Vec<(usize, u64)> {
+        policy.allocate(incoming, amounts.iter().sum(), amounts.iter().copied().enumerate())
+    }
+
+    #[test]
+    fn fifo_fills_from_the_front_and_stops_reading_there() {
+        assert_eq!(allocate(MatchingPolicy::Fifo, 25, &[10, 10, 10]), vec![(0, 10), (1, 10), (2, 5)]);
+        assert_eq!(allocate(MatchingPolicy::Fifo, 40, &[10, 10]), vec![(0, 10), (1, 10)]);
+
+        let mut read = 0;
+        let queue = [5, 5, 5, 5].into_iter().enumerate().inspect(|_| read += 1);
+        assert_eq!(MatchingPolicy::Fifo.allocate(7, 20, queue), vec![(0, 5), (1, 2)]);
+        assert_eq!(read, 3);
+    }
+
+    #[test]
+    fn pro_rata_splits_by_share_in_whole_lots() {
+        // shares of 50 are 25, 15 and 10
+        assert_eq!(
+            allocate(MatchingPolicy::ProRata { lot: 1 }, 50, &[50, 30, 20]),
+            vec![(0, 25), (1, 15), (2, 10)]
+        );
+        // 12.5, 7.5 and 5 round down to lots of 5, the 5 left over goes to the front of the queue
+        assert_eq!(
+            allocate(MatchingPolicy::ProRata { lot: 5 }, 25, &[50, 30, 20]),
+            vec![(0, 15), (1, 5), (2, 5)]
+        );
+        // shares below one lot get nothing but what is left over, in queue order
+        assert_eq!(
+            allocate(MatchingPolicy::ProRata { lot: 10 }, 12, &[4, 40, 4]),
+            vec![(0, 2), (1, 10)]
+        );
+        assert_eq!(allocate(MatchingPolicy::ProRata { lot: 5 }, 100, &[7, 3]), vec![(0, 7), (1, 3)]);
+    }
+
+    #[test]
+    fn hybrid_gives_the_top_order_its_slice_before_pro_rata() {
+        let hybrid = MatchingPolicy::Hybrid { priority_slice: 40, lot: 1 };
+        // 40 of 100 to the top order, then 60 over the open 60, 60 and 80
+        assert_eq!(allocate(hybrid, 100, &[100, 60, 80]), vec![(0, 58), (1, 18), (2, 24)]);
+        // the slice is capped by the top order's amount
+        assert_eq!(allocate(hybrid, 50, &[10, 100, 100]), vec![(0, 10), (1, 20), (2, 20)]);
+
+        let everything_to_the_top = MatchingPolicy::Hybrid { priority_slice: 100, lot: 1 };
+        assert_eq!(allocate(everything_to_the_top, 30, &[50, 50]), vec![(0, 30)]);
+        let pure_pro_rata = MatchingPolicy::Hybrid { priority_slice: 0, lot: 5 };
+        assert_eq!(allocate(pure_pro_rata, 25, &[50, 30, 20]), allocate(MatchingPolicy::ProRata { lot: 5 }, 25, &[50, 30, 20]));
+    }
+
+    #[test]
+    fn allocations_add_up_to_the_incoming_quantity() {
+        let policies = [
+            MatchingPolicy::Fifo,
+            MatchingPolicy::ProRata { lot: 3 },
+            MatchingPolicy::Hybrid { priority_slice: 25, lot: 2 },
+        ];
+        let amounts = [7, 1, 12, 5, 30, 2];
+        let volume: u64 = amounts.iter().sum();
+        for policy in policies {
+            for incoming in 1..=volume + 5 {
+                let fills = allocate(policy, incoming, &amounts);
+                assert_eq!(fills.iter().map(|(_, fill)| fill).sum::<u64>(), incoming.min(volume), "{} {}", policy, incoming);
+                assert!(fills.iter().all(|(index, fill)| *fill <= amounts[*index]));
+            }
+        }
+    }
+
+    #[test]
+    fn policies_parse_back_from_their_display() {
+        for serialized in ["fifo", "pro_rata 5", "hybrid 40 1"] {
+            assert_eq!(serialized.parse::<MatchingPolicy>().unwrap().to_string(), serialized);
+        }
+        assert!("pro_rata".parse::<MatchingPolicy>().is_err());
+        assert!("hybrid x 1".parse::<MatchingPolicy>().is_err());
+    }
+}
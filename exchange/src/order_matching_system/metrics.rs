@@ -0,0 +1,67 @@
+use crate::command::Command;
+use crate::order::Order;
+use crate::order_matching_system::listener::OrderListener;
+use crate::trade::Trade;
+use std::fmt;
+
+// Counts of what the book did since it started, one per callback
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metrics {
+    pub accepted: u64,
+    pub rejected: u64,
+    pub fills: u64,
+    pub filled_volume: u64,
+    pub amended: u64,
+    pub cancelled: u64,
+    pub expired: u64,
+    pub rested: u64,
+}
+
+impl OrderListener for Metrics {
+    fn on_accept(&mut self, order: &Order) {
+        self.accepted += 1;
+    }
+
+    fn on_reject(&mut self, command: &Command, reason: &str) {
+        self.rejected += 1;
+    }
+
+    fn on_fill(&mut self, trade: &Trade, buy: &Order, sell: &Order) {
+        self.fills += 1;
+        self.filled_volume += trade.amount;
+    }
+
+    fn on_amend(&mut self, old: &Order, new: &Order) {
+        self.amended += 1;
+    }
+
+    fn on_cancel(&mut self, order: &Order) {
+        self.cancelled += 1;
+    }
+
+    fn on_expire(&mut self, order: &Order) {
+        self.expired += 1;
+    }
+
+    fn on_rest(&mut self, order: &Order) {
+        self.rested += 1;
+    }
+}
+
+// accepted 3 rejected 0 fills 2 volume 20 amended 0 cancelled 0 expired 0 rested 2
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "accepted {} rejected {} fills {} volume {} amended {} cancelled {} expired {} rested {}",
+            self.accepted,
+            self.rejected,
+            self.fills,
+            self.filled_volume,
+            self.amended,
+            self.cancelled,
+            self.expired,
+            self.rested
+        )
+    }
+}
@@ -1,4 +1,21 @@
+pub mod arena;
+pub mod auction;
+pub mod client_order_ids;
+pub mod journal;
+pub mod level3;
 pub mod limit_tree;
+pub mod listener;
+pub mod market_data;
+pub mod matching_policy;
+pub mod metrics;
 pub mod order_book;
+pub mod order_store;
+pub mod pnl;
+pub mod session;
+pub mod settlement;
+pub mod snapshot;
+pub mod ticker;
+#[cfg(test)]
+pub(crate) mod testing;
 pub use self::limit_tree::*;
 pub use self::order_book::*;
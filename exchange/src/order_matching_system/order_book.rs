@@ -1,115 +1,613 @@
 use crate::order::*;
+use crate::order_matching_system::arena::{OrderArena, OrderHandle};
+use crate::order_matching_system::client_order_ids::{ClientOrderIds, DUPLICATE_CLIENT_ORDER_ID};
 use crate::order_matching_system::limit_tree::*;
-use std::borrow::BorrowMut;
-use std::cell::RefCell;
 use std::collections::BTreeMap;
-use std::rc::Rc;
 
+use crate::asset_name::AssetName;
+use crate::clock::Clock;
+use crate::command::Command;
 use crate::deserialize::Deserialize;
+use crate::order_matching_system::listener::{OrderEvent, OrderListener, UNKNOWN_ORDER};
+use crate::order_matching_system::market_data::MarketDataFeed;
+use crate::order_matching_system::matching_policy::MatchingPolicy;
+use crate::order_matching_system::metrics::Metrics;
+use crate::order_matching_system::order_store::OrderStore;
+use crate::order_matching_system::pnl::PnlTracker;
+use crate::order_matching_system::session::SessionState;
+use crate::order_matching_system::settlement::{funds_rejection, Settlement, BALANCE_OVERFLOW};
+use crate::order_matching_system::ticker::Ticker;
+use crate::trade::Trade;
 use crate::trader::Trader;
 
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Depth {
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+// What became of a command: a new order gets the exchange id it was accepted under, a rejected command
+// the reason it changed nothing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Executed,
+    Accepted(usize),
+    Rejected(&'static str),
+}
+
+#[derive(Default)]
 pub struct OrderBook {
-    pub buy_limits: LimitTree,
-    pub sell_limits: LimitTree,
-    pub orders: BTreeMap<usize, Rc<RefCell<Order>>>,
-    pub users: BTreeMap<String, Rc<RefCell<Trader>>>,
+    pub buy_limits: BTreeMap<AssetName, LimitTree>,
+    pub sell_limits: BTreeMap<AssetName, LimitTree>,
+    pub orders: OrderArena,
+    pub users: BTreeMap<String, Trader>,
+    // exchange id of the next new order, saved with the snapshot so ids never repeat across runs
+    pub order_id_sequence: usize,
+    pub client_order_ids: ClientOrderIds,
+    pub trades: Vec<Trade>,
+    pub trade_id_sequence: usize,
+    pub journal_sequence: u64,
+    pub entry_sequence: u64,
+    pub market_data: MarketDataFeed,
+    pub tickers: BTreeMap<AssetName, Ticker>,
+    // instruments without an entry match FIFO
+    pub matching_policies: BTreeMap<AssetName, MatchingPolicy>,
+    // instruments without an entry trade continuously
+    pub sessions: BTreeMap<AssetName, SessionState>,
+    pub clock: Box<dyn Clock>,
+    // time of the command being executed, every order, trade and event it causes is stamped with it
+    pub now: u64,
+    pub metrics: Metrics,
+    // every order ever entered, with what happened to it, after it left orders
+    pub order_store: OrderStore,
+    // positions and PnL of every trader from their fills
+    pub pnl: PnlTracker,
+    // told about everything after the book's own listeners: settlement, client order ids, the trade log,
+    // tickers, market data, metrics, the order store and PnL
+    pub listeners: Vec<Box<dyn OrderListener + Send>>,
+    // a book that only matches for someone else keeping the records, e.g. an engine instrument book:
+    // the trade log, market data, the order store and PnL, which grow with every order, hear nothing
+    pub matching_only: bool,
 }
 
 impl OrderBook {
-    pub fn from_files() -> Self {
-        Self {
-            buy_limits: LimitTree::new(Direction::Buy),
-            sell_limits: LimitTree::new(Direction::Sell),
-            orders: Order::deserialize_all(),
-            users: Trader::deserialize_all(),
-        }
-    }
-
-    pub fn limit(&mut self, order: &Rc<RefCell<Order>>) {
-        self.orders.insert(order.borrow().id, order.clone());
-        self.users[&order.borrow().trader_name]
-            .as_ref()
-            .borrow_mut()
-            .block_funds(order.clone());
-        if (order.borrow().direction == Direction::Buy) {
-            self.limit_buy(order.clone());
-        } else {
-            self.limit_sell(order.clone());
-        }
-    }
-
-    fn limit_sell(&mut self, order: Rc<RefCell<Order>>) {
-        if (!self.buy_limits.empty()) {
-            let buys_best_price = *self.buy_limits.borrow_mut().limits.iter().next().unwrap().0;
-
-            if (order.borrow().price <= buys_best_price) {
-                //todo make closure or smth to not pass users&orders but pass function on_fill with scope captured
-                self.buy_limits
-                    .market(order.clone(), &mut self.users, &mut self.orders);
-                if (self.orders.contains_key(&order.borrow().id) && order.borrow().amount > 0) {
-                    self.sell_limits.new_limit(order.clone());
-                }
-            }
-        } else {
-            self.sell_limits.new_limit(order.clone());
+    pub fn limits_mut(&mut self, asset: AssetName, direction: Direction) -> &mut LimitTree {
+        let limits = match direction {
+            Direction::Buy => &mut self.buy_limits,
+            Direction::Sell => &mut self.sell_limits,
+        };
+        limits.entry(asset).or_insert_with(|| LimitTree::new(direction))
+    }
+
+    // Top n bid and ask levels of one asset, best prices first
+    pub fn depth(&self, asset: AssetName, n: usize) -> Depth {
+        let levels = |limits: &BTreeMap<AssetName, LimitTree>| {
+            limits.get(&asset).map(|limits| limits.levels(n)).unwrap_or_default()
+        };
+        Depth {
+            bids: levels(&self.buy_limits),
+            asks: levels(&self.sell_limits),
         }
     }
 
-    fn limit_buy(&mut self, order: Rc<RefCell<Order>>) {
-        if (!self.sell_limits.empty()) {
-            let sell_best_price = *self.sell_limits.borrow_mut().limits.iter().next().unwrap().0;
+    pub fn matching_policy(&self, asset: AssetName) -> MatchingPolicy {
+        self.matching_policies.get(&asset).copied().unwrap_or_default()
+    }
+
+    pub fn next_order_id(&mut self) -> usize {
+        let id = self.order_id_sequence;
+        self.order_id_sequence += 1;
+        id
+    }
+
+    pub fn execute(&mut self, command: Command) -> Outcome {
+        let timestamp = self.clock.now();
+        self.execute_at(command, timestamp)
+    }
 
-            if (order.borrow().price >= sell_best_price) {
-                self.sell_limits
-                    .market(order.clone(), &mut self.users, &mut self.orders);
-                if (self.orders.contains_key(&order.borrow().id) && order.borrow().amount > 0) {
-                    self.buy_limits.new_limit(order.clone());
+    // Executes the command as of timestamp, as journaled or replayed.
+    // A command the instrument's session does not accept changes nothing, nor does a new order
+    // whose client order id names a live order of its trader: it gets no id. Neither does an order
+    // of an unknown trader or one its trader cannot afford, so any command replays without a panic.
+    // Gateways answer their clients from the outcome rather than checking the command themselves.
+    pub fn execute_at(&mut self, command: Command, timestamp: u64) -> Outcome {
+        self.now = timestamp;
+        if let Some(reason) = self.session_rejection(&command) {
+            self.notify(OrderEvent::Reject(command, reason.to_string()));
+            return Outcome::Rejected(reason);
+        }
+        let asset = self.command_asset(&command);
+        let outcome = match &command {
+            Command::NewOrder(order) if self.client_order_ids.is_duplicate(order) => {
+                Outcome::Rejected(DUPLICATE_CLIENT_ORDER_ID)
+            }
+            Command::NewOrder(order) => match funds_rejection(&self.users, order, None) {
+                Some(reason) => Outcome::Rejected(reason),
+                None => {
+                    let mut order = order.clone();
+                    order.id = self.next_order_id();
+                    let order_id = order.id;
+                    self.limit(order);
+                    Outcome::Accepted(order_id)
                 }
+            },
+            Command::Cancel { order } => {
+                match self.client_order_ids.resolve(order).and_then(|order_id| self.cancel(order_id)) {
+                    Some(_) => Outcome::Executed,
+                    None => Outcome::Rejected(UNKNOWN_ORDER),
+                }
+            }
+            Command::Amend { order, price, amount } => match self.client_order_ids.resolve(order) {
+                Some(order_id) => match self.amend_rejection(order_id, *price, *amount) {
+                    Some(reason) => Outcome::Rejected(reason),
+                    None if self.amend(order_id, *price, *amount) => Outcome::Executed,
+                    None => Outcome::Rejected(UNKNOWN_ORDER),
+                },
+                None => Outcome::Rejected(UNKNOWN_ORDER),
+            },
+            Command::Deposit { trader_name, asset, amount } => match self.deposit(trader_name, *asset, *amount) {
+                true => Outcome::Executed,
+                false => Outcome::Rejected(BALANCE_OVERFLOW),
+            },
+            Command::Session { asset, state } => {
+                self.set_session(*asset, *state);
+                Outcome::Executed
             }
-        } else {
-            self.buy_limits.new_limit(order.clone());
+        };
+        if let Outcome::Rejected(reason) = outcome {
+            self.notify(OrderEvent::Reject(command, reason.to_string()));
+        }
+        if let Some(asset) = asset {
+            self.publish_market_data(asset);
+            self.update_ticker(asset);
         }
+        outcome
+    }
+
+    pub fn add_listener(&mut self, listener: impl OrderListener + Send + 'static) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    // Numbers and stamps the trade of a fill, then tells the book's own listeners and the registered ones
+    pub(crate) fn notify(&mut self, mut event: OrderEvent) {
+        if let OrderEvent::Fill { trade, .. } = &mut event {
+            trade.id = self.trade_id_sequence;
+            trade.timestamp = self.now;
+            self.trade_id_sequence += 1;
+        }
+        event.deliver(&mut Settlement(&mut self.users));
+        event.deliver(&mut self.client_order_ids);
+        if (!self.matching_only) {
+            event.deliver(&mut self.trades);
+        }
+        event.deliver(&mut self.tickers);
+        if (!self.matching_only) {
+            event.deliver(&mut self.market_data);
+        }
+        event.deliver(&mut self.metrics);
+        if (!self.matching_only) {
+            event.deliver(&mut self.order_store);
+            event.deliver(&mut self.pnl);
+        }
+        for listener in &mut self.listeners {
+            event.deliver(listener.as_mut());
+        }
+    }
+
+    pub fn cancel(&mut self, order_id: usize) -> Option<Order> {
+        let order = self.remove_order(order_id)?;
+        self.notify(OrderEvent::Cancel(order.clone()));
+        Some(order)
+    }
+
+    // Takes a resting order off the book without telling anyone
+    pub(crate) fn remove_order(&mut self, order_id: usize) -> Option<Order> {
+        let handle = self.orders.handle(&order_id)?;
+        let limits = match self.orders[handle].direction {
+            Direction::Buy => &mut self.buy_limits,
+            Direction::Sell => &mut self.sell_limits,
+        };
+        limits.get_mut(&self.orders[handle].asset)?.remove(handle, &mut self.orders)?;
+        self.orders.remove(&order_id)
+    }
+
+    // Reducing the amount at the same price keeps queue priority, anything else re-enters the order,
+    // an amount of 0 cancels it
+    pub fn amend(&mut self, order_id: usize, price: u64, amount: u64) -> bool {
+        let Some(old) = self.orders.get(&order_id).cloned() else {
+            return false;
+        };
+        if (amount == 0) {
+            return self.cancel(order_id).is_some();
+        }
+        if (price == old.price && amount <= old.amount) {
+            self.limits_mut(old.asset, old.direction).reduce(price, old.amount - amount);
+            let order = self.orders.get_mut(&order_id).unwrap();
+            order.amount = amount;
+            order.update_timestamp = self.now;
+            let new = order.clone();
+            self.notify(OrderEvent::Amend { old, new });
+            return true;
+        }
+        let Some(mut replacement) = self.remove_order(order_id) else {
+            return false;
+        };
+        replacement.price = price;
+        replacement.amount = amount;
+        self.stamp_entry(&mut replacement);
+        self.notify(OrderEvent::Amend { old, new: replacement.clone() });
+        self.enter(replacement);
+        true
     }
-}
 
-impl Default for OrderBook {
-    fn default() -> Self {
-        Self {
-            buy_limits: LimitTree::new(Direction::Buy),
-            sell_limits: LimitTree::new(Direction::Sell),
-            orders: Default::default(),
-            users: Default::default(),
+    // Why the trader cannot afford an amendment that re-enters the order. Reducing it at the same price
+    // or cancelling it only releases funds.
+    fn amend_rejection(&self, order_id: usize, price: u64, amount: u64) -> Option<&'static str> {
+        let old = self.orders.get(&order_id)?;
+        if (amount == 0 || (price == old.price && amount <= old.amount)) {
+            return None;
         }
+        let new = Order { price, amount, ..old.clone() };
+        funds_rejection(&self.users, &new, Some(old))
+    }
+
+    // false, crediting nothing, if the balance would overflow
+    pub fn deposit(&mut self, trader_name: &str, asset: Option<AssetName>, amount: u64) -> bool {
+        self.users
+            .entry(trader_name.to_string())
+            .or_insert_with(|| Trader::deserialize(format!("{} 0 0 0 0 0", trader_name)))
+            .deposit(asset, amount)
+    }
+
+    pub fn limit(&mut self, mut order: Order) {
+        self.stamp_entry(&mut order);
+        self.notify(OrderEvent::Accept(order.clone()));
+        self.enter(order);
+    }
+
+    fn stamp_entry(&mut self, order: &mut Order) {
+        order.entry_sequence = self.entry_sequence;
+        self.entry_sequence += 1;
+        order.entry_timestamp = self.now;
+        order.update_timestamp = self.now;
+    }
+
+    // Matches an accepted order while the instrument trades continuously and rests what is left of it
+    fn enter(&mut self, order: Order) {
+        let (id, direction, asset) = (order.id, order.direction, order.asset);
+        let handle = self.orders.insert(order);
+        if (self.session(asset) == SessionState::Continuous) {
+            let policy = self.matching_policy(asset);
+            let mut fills = Vec::new();
+            let opposite = match direction {
+                Direction::Buy => self.sell_limits.entry(asset).or_insert_with(|| LimitTree::new(Direction::Sell)),
+                Direction::Sell => self.buy_limits.entry(asset).or_insert_with(|| LimitTree::new(Direction::Buy)),
+            };
+            opposite.market(handle, policy, &mut self.orders, &mut fills);
+            for fill in fills {
+                self.notify(fill);
+            }
+        }
+        if let Some(order) = self.orders.get(&id).cloned() {
+            self.rest(handle);
+            self.notify(OrderEvent::Rest(order));
+        }
+    }
+
+    // Queues an order that is already in the arena at its limit
+    pub(crate) fn rest(&mut self, handle: OrderHandle) {
+        let (asset, direction) = (self.orders[handle].asset, self.orders[handle].direction);
+        let limits = match direction {
+            Direction::Buy => &mut self.buy_limits,
+            Direction::Sell => &mut self.sell_limits,
+        };
+        limits
+            .entry(asset)
+            .or_insert_with(|| LimitTree::new(direction))
+            .new_limit(handle, &mut self.orders);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{asset_name::AssetName, deserialize::Deserialize, order, trader::Trader};
+    use crate::order_matching_system::testing::{
+        check_invariants, clients, command_strategy, execute_all, funded_order_book, trading_strategy,
+        underfunded_order_book, Holdings,
+    };
+    use proptest::prelude::*;
 
     use super::*;
 
     #[test]
     fn orderbook_orders_len_increased_after_new_order_inserted() {
         let mut order_book = OrderBook {
-            users: Trader::deserialize_all(),
+            users: clients(),
             ..Default::default()
         };
-        let orders = Order::deserialize_all();
-        order_book.limit(&orders.iter().next().unwrap().1.clone());
+        order_book.limit(Order::parse("C1 b A 8 10"));
         assert_eq!(order_book.orders.len(), 1);
     }
 
     #[test]
     fn trader_balance_changed_after_new_buy_order_inserted() {
-        let mut orderbook = OrderBook::from_files();
-        let balance_before = orderbook.users["C1"].borrow().usd_balance;
-        orderbook.limit(&orderbook.orders[&usize::MIN].clone());
+        let mut orderbook = OrderBook {
+            users: clients(),
+            ..Default::default()
+        };
+        let balance_before = orderbook.users["C1"].usd_balance;
+        orderbook.limit(Order { id: 0, ..Order::parse("C1 b A 8 10") });
+
+        let order = &orderbook.orders[&usize::MIN];
+        assert_eq!(
+            orderbook.users["C1"].usd_balance,
+            balance_before - order.price * order.amount
+        );
+    }
+
+    #[test]
+    fn matched_orders_produce_trades_with_sequential_ids() {
+        let mut order_book = OrderBook {
+            users: clients(),
+            ..Default::default()
+        };
+        execute_all(&mut order_book, &["new C1 b A 8 10", "new C3 b A 7 10", "new C2 s A 7 20"]);
+
+        let trade_ids: Vec<usize> = order_book.trades.iter().map(|trade| trade.id).collect();
+        assert_eq!(trade_ids, vec![0, 1]);
+        // the highest bid fills first
+        assert_eq!(order_book.trades[0].buyer, "C1");
+        assert_eq!(order_book.trades[0].seller, "C2");
+        assert_eq!(order_book.trades[0].amount, 10);
+        assert!(order_book.orders.is_empty());
+    }
+
+    #[test]
+    fn orders_for_different_assets_do_not_match() {
+        let mut order_book = OrderBook {
+            users: clients(),
+            ..Default::default()
+        };
+        execute_all(&mut order_book, &["new C1 b A 8 10", "new C2 s B 7 10"]);
 
-        let order: &Rc<RefCell<Order>> = &orderbook.orders[&usize::MIN];
+        assert!(order_book.trades.is_empty());
+        assert_eq!(order_book.buy_limits[&AssetName::A].limits.len(), 1);
+        assert_eq!(order_book.sell_limits[&AssetName::B].limits.len(), 1);
+    }
+
+    #[test]
+    fn cancel_releases_reserved_funds() {
+        let mut order_book = OrderBook {
+            users: clients(),
+            ..Default::default()
+        };
+        execute_all(&mut order_book, &["new C1 b A 8 10", "new C2 s B 9 5", "cancel 0", "cancel 1"]);
+
+        assert_eq!(order_book.users["C1"].usd_balance, 2000);
+        assert_eq!(order_book.users["C1"].reserved_usd, 0);
+        assert_eq!(order_book.users["C2"].assets_count[&AssetName::B], 35);
+        assert!(order_book.buy_limits[&AssetName::A].empty());
+        assert!(order_book.sell_limits[&AssetName::B].empty());
+        assert!(order_book.cancel(0).is_none());
+    }
+
+    #[test]
+    fn amend_down_keeps_queue_priority_and_amend_up_loses_it() {
+        let mut order_book = OrderBook {
+            users: clients(),
+            ..Default::default()
+        };
+        execute_all(&mut order_book, &["new C1 b A 8 10", "new C3 b A 8 10", "amend 0 8 4"]);
+        let queue: Vec<(usize, u64)> = order_book
+            .buy_limits[&AssetName::A]
+            .orders(&order_book.orders)
+            .iter()
+            .map(|order| (order_book.orders[*order].id, order_book.orders[*order].amount))
+            .collect();
+        assert_eq!(queue, vec![(0, 4), (1, 10)]);
+        assert_eq!(order_book.users["C1"].reserved_usd, 8 * 4);
+
+        execute_all(&mut order_book, &["amend 0 8 6"]);
+        let queue: Vec<usize> = order_book.buy_limits[&AssetName::A]
+            .orders(&order_book.orders).iter().map(|order| order_book.orders[*order].id).collect();
+        assert_eq!(queue, vec![1, 0]);
+        assert_eq!(order_book.users["C1"].reserved_usd, 8 * 6);
+    }
+
+    #[test]
+    fn order_book_can_move_between_threads() {
+        fn assert_send<T: Send>() {}
+        assert_send::<OrderBook>();
+    }
+
+    #[test]
+    fn trader_on_both_sides_of_a_trade_is_settled_once_per_side() {
+        let mut order_book = OrderBook {
+            users: clients(),
+            ..Default::default()
+        };
+        execute_all(&mut order_book, &["new C1 b A 8 10", "new C1 s A 8 4"]);
+
+        assert_eq!((order_book.trades[0].buyer.as_str(), order_book.trades[0].seller.as_str()), ("C1", "C1"));
+        let c1 = &order_book.users["C1"];
+        assert_eq!((c1.usd_balance, c1.reserved_usd), (2000 - 8 * 6, 8 * 6));
+        assert_eq!((c1.assets_count[&AssetName::A], c1.reserved_assets[&AssetName::A]), (10, 0));
+        assert_eq!(order_book.depth(AssetName::A, 1).bids[0].volume, 6);
+    }
+
+    #[test]
+    fn cancel_unlinks_orders_anywhere_in_the_queue() {
+        let mut order_book = funded_order_book();
+        execute_all(
+            &mut order_book,
+            &["new C1 b A 8 1", "new C3 b A 8 2", "new C1 b A 8 3", "new C3 b A 8 4", "new C1 b A 8 5"],
+        );
+        execute_all(&mut order_book, &["cancel 2", "cancel 4", "cancel 0", "new C3 b A 8 6"]);
+
+        let queue: Vec<usize> = order_book.buy_limits[&AssetName::A]
+            .orders(&order_book.orders)
+            .iter()
+            .map(|order| order_book.orders[*order].id)
+            .collect();
+        assert_eq!(queue, vec![1, 3, 5]);
+        assert_eq!(order_book.depth(AssetName::A, 1).bids, vec![Level { price: 8, volume: 12, order_count: 3 }]);
+        execute_all(&mut order_book, &["new C2 s A 8 3"]);
+        assert_eq!(order_book.trades.iter().map(|trade| trade.buy_order_id).collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    #[test]
+    fn each_instrument_matches_by_its_own_policy() {
+        let mut order_book = funded_order_book();
+        order_book.matching_policies.insert(AssetName::A, MatchingPolicy::ProRata { lot: 1 });
+        for asset in ["A", "B"] {
+            execute_all(
+                &mut order_book,
+                &[&format!("new C2 s {} 10 30", asset), &format!("new C4 s {} 10 10", asset), &format!("new C1 b {} 10 20", asset)],
+            );
+        }
+
+        let fills = |asset: AssetName| {
+            order_book
+                .trades
+                .iter()
+                .filter(|trade| trade.asset == asset)
+                .map(|trade| (trade.seller.as_str(), trade.price, trade.amount))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(fills(AssetName::A), vec![("C2", 10, 15), ("C4", 10, 5)]);
+        assert_eq!(fills(AssetName::B), vec![("C2", 10, 20)]);
+        assert_eq!(order_book.depth(AssetName::A, 1).asks, vec![Level { price: 10, volume: 20, order_count: 2 }]);
+        let (c1, c2, c4) = (&order_book.users["C1"], &order_book.users["C2"], &order_book.users["C4"]);
+        assert_eq!((c1.usd_balance, c1.assets_count[&AssetName::A]), (1_000_000 - 2 * 200, 10_020));
+        assert_eq!((c2.usd_balance, c2.reserved_assets[&AssetName::A]), (1_000_000 + 150 + 200, 15));
+        assert_eq!((c4.usd_balance, c4.reserved_assets[&AssetName::A]), (1_000_000 + 50, 5));
+    }
+
+    #[test]
+    fn split_fills_trade_at_the_resting_level_when_the_incoming_limit_is_through_it() {
+        let mut order_book = funded_order_book();
+        order_book.matching_policies.insert(AssetName::A, MatchingPolicy::ProRata { lot: 1 });
+        order_book.matching_policies.insert(AssetName::B, MatchingPolicy::Hybrid { priority_slice: 40, lot: 1 });
+        for asset in ["A", "B"] {
+            execute_all(
+                &mut order_book,
+                &[&format!("new C2 s {} 10 30", asset), &format!("new C4 s {} 10 10", asset), &format!("new C1 b {} 12 20", asset)],
+            );
+        }
+
+        let fills = |asset: AssetName| {
+            order_book
+                .trades
+                .iter()
+                .filter(|trade| trade.asset == asset)
+                .map(|trade| (trade.seller.as_str(), trade.price, trade.amount))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(fills(AssetName::A), vec![("C2", 10, 15), ("C4", 10, 5)]);
+        // 8 of the priority slice and 9 of the pro-rata rest to the top order
+        assert_eq!(fills(AssetName::B), vec![("C2", 10, 17), ("C4", 10, 3)]);
+        // the buyer pays 10 for all 40 and gets back what they reserved at 12
+        let c1 = &order_book.users["C1"];
+        assert_eq!((c1.usd_balance, c1.reserved_usd), (1_000_000 - 2 * 200, 0));
+    }
+
+    #[test]
+    fn deposit_credits_existing_and_new_traders() {
+        let mut order_book = OrderBook {
+            users: clients(),
+            ..Default::default()
+        };
+        execute_all(&mut order_book, &["deposit C1 USD 100", "deposit C9 B 3"]);
+
+        assert_eq!(order_book.users["C1"].usd_balance, 2100);
+        assert_eq!(order_book.users["C9"].assets_count[&AssetName::B], 3);
+    }
+
+    #[test]
+    fn depth_aggregates_partially_filled_levels() {
+        let mut order_book = OrderBook {
+            users: clients(),
+            ..Default::default()
+        };
+        execute_all(
+            &mut order_book,
+            &["new C2 s A 9 10", "new C2 s A 9 5", "new C2 s A 11 5", "new C1 b A 7 2", "new C3 b A 8 3", "new C1 b A 9 4"],
+        );
+
+        let depth = order_book.depth(AssetName::A, 2);
         assert_eq!(
-            orderbook.users["C1"].borrow().usd_balance,
-            balance_before - order.as_ref().borrow().price * order.as_ref().borrow().amount
+            depth.asks,
+            vec![
+                Level { price: 9, volume: 11, order_count: 2 },
+                Level { price: 11, volume: 5, order_count: 1 }
+            ]
         );
+        assert_eq!(
+            depth.bids,
+            vec![
+                Level { price: 8, volume: 3, order_count: 1 },
+                Level { price: 7, volume: 2, order_count: 1 }
+            ]
+        );
+        assert_eq!(order_book.depth(AssetName::B, 5), Depth { bids: vec![], asks: vec![] });
+    }
+
+    proptest! {
+        #[test]
+        fn depth_volume_matches_resting_orders(commands in prop::collection::vec(command_strategy(), 1..60)) {
+            let mut order_book = funded_order_book();
+
+            for command in &commands {
+                order_book.execute(Command::parse(command).unwrap());
+
+                for asset in [AssetName::A, AssetName::B] {
+                    let mut expected = Depth { bids: vec![], asks: vec![] };
+                    for order in order_book.orders.values() {
+                        if order.asset != asset {
+                            continue;
+                        }
+                        let levels = match order.direction {
+                            Direction::Buy => &mut expected.bids,
+                            Direction::Sell => &mut expected.asks,
+                        };
+                        match levels.iter_mut().find(|level| level.price == order.price) {
+                            Some(level) => {
+                                level.volume += order.amount;
+                                level.order_count += 1;
+                            }
+                            None => levels.push(Level { price: order.price, volume: order.amount, order_count: 1 }),
+                        }
+                    }
+                    expected.bids.sort_by_key(|level| std::cmp::Reverse(level.price));
+                    expected.asks.sort_by_key(|level| level.price);
+
+                    prop_assert_eq!(order_book.depth(asset, usize::MAX), expected);
+                }
+            }
+        }
+
+        // balances are unsigned, so one going negative shows up as an overflow panic in the test build
+        #[test]
+        fn invariants_hold_after_every_command(commands in prop::collection::vec(trading_strategy(), 1..100)) {
+            let mut order_book = funded_order_book();
+            let initial = Holdings::of(&order_book);
+
+            for command in &commands {
+                order_book.execute(Command::parse(command).unwrap());
+                check_invariants(&order_book, &initial)?;
+            }
+        }
+
+        // most orders are more than their traders have, those are rejected and nothing is overdrawn or created
+        #[test]
+        fn invariants_hold_for_traders_who_cannot_pay(commands in prop::collection::vec(trading_strategy(), 1..100)) {
+            let mut order_book = underfunded_order_book();
+            let initial = Holdings::of(&order_book);
+
+            for command in &commands {
+                order_book.execute(Command::parse(command).unwrap());
+                check_invariants(&order_book, &initial)?;
+            }
+        }
     }
 }
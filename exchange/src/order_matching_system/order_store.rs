@@ -0,0 +1,270 @@
+use crate::command::Command;
+use crate::order::Order;
+use crate::order_matching_system::listener::OrderListener;
+use crate::trade::Trade;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+// Where an order is in its life. New and partially filled orders rest in the book, the rest are final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Rejected,
+    Expired,
+}
+
+impl OrderStatus {
+    pub fn is_open(self) -> bool {
+        matches!(self, OrderStatus::New | OrderStatus::PartiallyFilled)
+    }
+}
+
+impl FromStr for OrderStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "new" => Ok(OrderStatus::New),
+            "partially_filled" => Ok(OrderStatus::PartiallyFilled),
+            "filled" => Ok(OrderStatus::Filled),
+            "cancelled" => Ok(OrderStatus::Cancelled),
+            "rejected" => Ok(OrderStatus::Rejected),
+            "expired" => Ok(OrderStatus::Expired),
+            _ => Err(format!("Bad order status: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            OrderStatus::New => "new",
+            OrderStatus::PartiallyFilled => "partially_filled",
+            OrderStatus::Filled => "filled",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Rejected => "rejected",
+            OrderStatus::Expired => "expired",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// The life of one order. order is its latest state: what is left of it, its price after amendments
+// and when it last changed. A rejected new order never got an id, its id stays usize::MAX.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderRecord {
+    pub order: Order,
+    pub status: OrderStatus,
+    pub filled: u64,
+    // price times amount summed over the fills
+    pub notional: u64,
+    pub trade_ids: Vec<usize>,
+}
+
+impl OrderRecord {
+    pub fn new(order: Order, status: OrderStatus) -> Self {
+        OrderRecord {
+            order,
+            status,
+            filled: 0,
+            notional: 0,
+            trade_ids: Vec::new(),
+        }
+    }
+
+    pub fn average_price(&self) -> Option<f64> {
+        (self.filled > 0).then(|| self.notional as f64 / self.filled as f64)
+    }
+}
+
+// Every order the book has seen, in the order they arrived, kept after they leave the book
+#[derive(Debug, Default)]
+pub struct OrderStore {
+    records: Vec<OrderRecord>,
+    // positions in records
+    ids: HashMap<usize, usize>,
+    traders: BTreeMap<String, Vec<usize>>,
+    statuses: BTreeMap<OrderStatus, BTreeSet<usize>>,
+}
+
+impl OrderStore {
+    pub fn get(&self, order_id: usize) -> Option<&OrderRecord> {
+        self.ids.get(&order_id).map(|position| &self.records[*position])
+    }
+
+    pub fn by_trader(&self, trader_name: &str) -> Vec<&OrderRecord> {
+        let positions = self.traders.get(trader_name).map(Vec::as_slice).unwrap_or_default();
+        positions.iter().map(|position| &self.records[*position]).collect()
+    }
+
+    pub fn by_status(&self, status: OrderStatus) -> Vec<&OrderRecord> {
+        let positions = self.statuses.get(&status).into_iter().flatten();
+        positions.map(|position| &self.records[*position]).collect()
+    }
+
+    // Every record in the order the orders arrived
+    pub fn records(&self) -> &[OrderRecord] {
+        &self.records
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn insert(&mut self, record: OrderRecord) {
+        let position = self.records.len();
+        if (record.status != OrderStatus::Rejected) {
+            self.ids.insert(record.order.id, position);
+        }
+        self.traders.entry(record.order.trader_name.clone()).or_default().push(position);
+        self.statuses.entry(record.status).or_default().insert(position);
+        self.records.push(record);
+    }
+
+    fn update(&mut self, order_id: usize, change: impl FnOnce(&mut OrderRecord)) {
+        let Some(&position) = self.ids.get(&order_id) else {
+            return;
+        };
+        let record = &mut self.records[position];
+        let status = record.status;
+        change(record);
+        if (record.status != status) {
+            self.statuses.get_mut(&status).unwrap().remove(&position);
+            self.statuses.entry(record.status).or_default().insert(position);
+        }
+    }
+}
+
+impl OrderListener for OrderStore {
+    fn on_accept(&mut self, order: &Order) {
+        self.insert(OrderRecord::new(order.clone(), OrderStatus::New));
+    }
+
+    // Only a new order has a life to record, anything else was rejected for an order that has one already
+    fn on_reject(&mut self, command: &Command, reason: &str) {
+        if let Command::NewOrder(order) = command {
+            self.insert(OrderRecord::new(
+                Order {
+                    id: usize::MAX,
+                    ..order.clone()
+                },
+                OrderStatus::Rejected,
+            ));
+        }
+    }
+
+    fn on_fill(&mut self, trade: &Trade, buy: &Order, sell: &Order) {
+        for order in [buy, sell] {
+            self.update(order.id, |record| {
+                record.filled += trade.amount;
+                record.notional += trade.price * trade.amount;
+                record.trade_ids.push(trade.id);
+                record.order.amount = order.amount - trade.amount;
+                record.order.update_timestamp = trade.timestamp;
+                record.status = if (record.order.amount == 0) {
+                    OrderStatus::Filled
+                } else {
+                    OrderStatus::PartiallyFilled
+                };
+            });
+        }
+    }
+
+    fn on_amend(&mut self, old: &Order, new: &Order) {
+        self.update(new.id, |record| record.order = new.clone());
+    }
+
+    fn on_cancel(&mut self, order: &Order) {
+        self.update(order.id, |record| record.status = OrderStatus::Cancelled);
+    }
+
+    fn on_expire(&mut self, order: &Order) {
+        self.update(order.id, |record| record.status = OrderStatus::Expired);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_matching_system::order_book::OrderBook;
+    use crate::order_matching_system::testing::{execute_all, funded_order_book};
+
+    fn statuses(records: Vec<&OrderRecord>) -> Vec<(usize, OrderStatus)> {
+        records.iter().map(|record| (record.order.id, record.status)).collect()
+    }
+
+    #[test]
+    fn filled_orders_keep_their_fills_after_leaving_the_book() {
+        let mut order_book = funded_order_book();
+        execute_all(&mut order_book, &["new C2 s A 7 4", "new C4 s A 8 6", "new C1 b A 9 10"]);
+        assert!(order_book.orders.is_empty());
+
+        let buy = order_book.order_store.get(2).unwrap();
+        assert_eq!((buy.status, buy.filled, buy.order.amount), (OrderStatus::Filled, 10, 0));
+        assert_eq!(buy.trade_ids, vec![0, 1]);
+        assert_eq!(buy.average_price(), Some((7.0 * 4.0 + 8.0 * 6.0) / 10.0));
+        assert_eq!(order_book.order_store.get(0).unwrap().trade_ids, vec![0]);
+        assert_eq!(order_book.order_store.get(3), None);
+    }
+
+    #[test]
+    fn every_end_of_an_order_has_its_status() {
+        let mut order_book = funded_order_book();
+        execute_all(
+            &mut order_book,
+            &[
+                "new C1 b B 8 10",
+                "new C2 s B 8 4",
+                "new C3 b B 6 5",
+                "cancel 2",
+                "new C3 b B 7 5 day",
+                "amend 0 8 3",
+                "session B halted",
+                "new C4 s B 9 1",
+                "session B closed",
+            ],
+        );
+        let store = &order_book.order_store;
+        assert_eq!(
+            statuses(store.records().iter().collect()),
+            vec![
+                (0, OrderStatus::PartiallyFilled),
+                (1, OrderStatus::Filled),
+                (2, OrderStatus::Cancelled),
+                (3, OrderStatus::Expired),
+                (usize::MAX, OrderStatus::Rejected),
+            ]
+        );
+        // the amendment is the order's latest state, its fills stay
+        let amended = store.get(0).unwrap();
+        assert_eq!((amended.order.amount, amended.filled), (3, 4));
+
+        assert_eq!(statuses(store.by_trader("C3")), vec![(2, OrderStatus::Cancelled), (3, OrderStatus::Expired)]);
+        assert_eq!(statuses(store.by_trader("C4")), vec![(usize::MAX, OrderStatus::Rejected)]);
+        assert_eq!(statuses(store.by_status(OrderStatus::PartiallyFilled)), vec![(0, OrderStatus::PartiallyFilled)]);
+        assert!(store.by_status(OrderStatus::New).is_empty());
+    }
+
+    #[test]
+    fn status_names_round_trip() {
+        for status in [
+            OrderStatus::New,
+            OrderStatus::PartiallyFilled,
+            OrderStatus::Filled,
+            OrderStatus::Cancelled,
+            OrderStatus::Rejected,
+            OrderStatus::Expired,
+        ] {
+            assert_eq!(status.to_string().parse::<OrderStatus>(), Ok(status));
+        }
+        assert!("open".parse::<OrderStatus>().is_err());
+    }
+}
@@ -0,0 +1,343 @@
+use crate::asset_name::AssetName;
+use crate::order::Order;
+use crate::order_matching_system::limit_tree::LimitTree;
+use crate::order_matching_system::listener::OrderListener;
+use crate::order_matching_system::order_book::OrderBook;
+use crate::trade::Trade;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+pub const PNL_CONFIG_PATH: &str = "resources/pnl_config.txt";
+pub const PNL_REPORT_PATH: &str = "resources/pnl.txt";
+
+// Which bought lots a sale closes: the oldest first, or all of them at their average price
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CostBasis {
+    #[default]
+    Fifo,
+    Average,
+}
+
+// Price an open position is marked to: the last trade, or the middle of the best bid and ask
+// while both exist and the last trade otherwise
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MarkPrice {
+    #[default]
+    LastTrade,
+    Mid,
+}
+
+impl FromStr for CostBasis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fifo" => Ok(CostBasis::Fifo),
+            "average" => Ok(CostBasis::Average),
+            _ => Err(format!("Bad cost basis: {}", s)),
+        }
+    }
+}
+
+impl FromStr for MarkPrice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "last" => Ok(MarkPrice::LastTrade),
+            "mid" => Ok(MarkPrice::Mid),
+            _ => Err(format!("Bad mark price: {}", s)),
+        }
+    }
+}
+
+// "cost_basis <fifo|average>" and "mark <last|mid>" lines, defaults for what the file leaves out
+// and for a missing file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PnlConfig {
+    pub cost_basis: CostBasis,
+    pub mark: MarkPrice,
+}
+
+impl PnlConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let mut config = PnlConfig::default();
+        let Ok(file) = File::open(path) else {
+            return config;
+        };
+        for line in BufReader::new(file).lines().map_while(Result::ok).filter(|line| !line.trim().is_empty()) {
+            match line.trim().split_once(' ') {
+                Some(("cost_basis", cost_basis)) => config.cost_basis = cost_basis.parse().expect("Unknown cost basis"),
+                Some(("mark", mark)) => config.mark = mark.parse().expect("Unknown mark price"),
+                _ => panic!("PnL config line is cost_basis <fifo|average> or mark <last|mid>: {}", line),
+            }
+        }
+        config
+    }
+}
+
+// Open quantity bought (positive) or sold (negative) at one price
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lot {
+    pub quantity: i64,
+    pub price: f64,
+}
+
+// What a trader's fills did in one asset. Holdings the trader started with are not part of it:
+// selling them opens a short position, buying back closes it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Position {
+    // oldest first, all of the same sign. Average cost keeps one lot at the average price.
+    pub lots: VecDeque<Lot>,
+    pub realized: f64,
+    pub volume: u64,
+    pub notional: u64,
+}
+
+impl Position {
+    pub fn quantity(&self) -> i64 {
+        self.lots.iter().map(|lot| lot.quantity).sum()
+    }
+
+    // What the open quantity cost, negative for a short position
+    pub fn cost(&self) -> f64 {
+        self.lots.iter().map(|lot| lot.quantity as f64 * lot.price).sum()
+    }
+
+    // quantity is positive for a buy, negative for a sale. It closes open lots of the other side
+    // first, what is left opens a lot of its own.
+    pub fn fill(&mut self, quantity: i64, price: u64, cost_basis: CostBasis) {
+        self.volume += quantity.unsigned_abs();
+        self.notional += quantity.unsigned_abs() * price;
+        let price = price as f64;
+        let mut left = quantity;
+        while let Some(lot) = self.lots.front_mut().filter(|lot| lot.quantity.signum() == -left.signum()) {
+            let closed = lot.quantity.abs().min(left.abs()) * lot.quantity.signum();
+            self.realized += closed as f64 * (price - lot.price);
+            lot.quantity -= closed;
+            left += closed;
+            if (lot.quantity == 0) {
+                self.lots.pop_front();
+            }
+        }
+        if (left == 0) {
+            return;
+        }
+        match (cost_basis, self.lots.front_mut()) {
+            (CostBasis::Average, Some(lot)) => {
+                let quantity = lot.quantity + left;
+                lot.price = (lot.quantity as f64 * lot.price + left as f64 * price) / quantity as f64;
+                lot.quantity = quantity;
+            }
+            _ => self.lots.push_back(Lot { quantity: left, price }),
+        }
+    }
+}
+
+// Positions of every trader in every asset they traded, folded from the fills as they happen
+#[derive(Debug, Default)]
+pub struct PnlTracker {
+    pub config: PnlConfig,
+    pub positions: BTreeMap<(String, AssetName), Position>,
+}
+
+impl OrderListener for PnlTracker {
+    fn on_fill(&mut self, trade: &Trade, buy: &Order, sell: &Order) {
+        let quantity = trade.amount as i64;
+        for (trader_name, quantity) in [(&trade.buyer, quantity), (&trade.seller, -quantity)] {
+            let position = self.positions.entry((trader_name.clone(), trade.asset)).or_default();
+            position.fill(quantity, trade.price, self.config.cost_basis);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetPnl {
+    pub asset: AssetName,
+    pub position: i64,
+    // None while the position is flat
+    pub average_cost: Option<f64>,
+    pub realized: f64,
+    pub unrealized: f64,
+    // None before the asset's first trade, the position is then not marked
+    pub mark: Option<f64>,
+    // the exchange charges no fees, this stays 0 until it does
+    pub fees: u64,
+    pub volume: u64,
+    pub notional: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraderPnl {
+    pub trader_name: String,
+    pub assets: Vec<AssetPnl>,
+}
+
+impl TraderPnl {
+    pub fn realized(&self) -> f64 {
+        self.assets.iter().map(|asset| asset.realized).sum()
+    }
+
+    pub fn unrealized(&self) -> f64 {
+        self.assets.iter().map(|asset| asset.unrealized).sum()
+    }
+}
+
+fn optional(value: Option<f64>) -> String {
+    value.map_or("-".to_string(), |value| format!("{:.2}", value))
+}
+
+// C1 A position 5 average_cost 7.00 realized 0.00 unrealized 10.00 mark 9.00 fees 0 volume 5 notional 35
+// ...one line per asset, then
+// C1 total realized 0.00 unrealized 10.00 fees 0 volume 5 notional 35
+impl fmt::Display for TraderPnl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for asset in &self.assets {
+            writeln!(
+                f,
+                "{} {:?} position {} average_cost {} realized {:.2} unrealized {:.2} mark {} fees {} volume {} notional {}",
+                self.trader_name,
+                asset.asset,
+                asset.position,
+                optional(asset.average_cost),
+                asset.realized,
+                asset.unrealized,
+                optional(asset.mark),
+                asset.fees,
+                asset.volume,
+                asset.notional
+            )?;
+        }
+        writeln!(
+            f,
+            "{} total realized {:.2} unrealized {:.2} fees {} volume {} notional {}",
+            self.trader_name,
+            self.realized(),
+            self.unrealized(),
+            self.assets.iter().map(|asset| asset.fees).sum::<u64>(),
+            self.assets.iter().map(|asset| asset.volume).sum::<u64>(),
+            self.assets.iter().map(|asset| asset.notional).sum::<u64>()
+        )
+    }
+}
+
+impl OrderBook {
+    pub fn mark_price(&self, asset: AssetName) -> Option<f64> {
+        let last_trade = self.tickers.get(&asset).and_then(|ticker| ticker.last_price).map(|price| price as f64);
+        let best = |limits: &BTreeMap<AssetName, LimitTree>| limits.get(&asset).and_then(LimitTree::best_price);
+        match (self.pnl.config.mark, best(&self.buy_limits), best(&self.sell_limits)) {
+            (MarkPrice::Mid, Some(bid), Some(ask)) => Some((bid + ask) as f64 / 2.0),
+            _ => last_trade,
+        }
+    }
+
+    // PnL of one trader, None if they never traded
+    pub fn pnl(&self, trader_name: &str) -> Option<TraderPnl> {
+        let assets: Vec<AssetPnl> = self
+            .pnl
+            .positions
+            .iter()
+            .filter(|((name, _), _)| name == trader_name)
+            .map(|((_, asset), position)| {
+                let quantity = position.quantity();
+                let mark = self.mark_price(*asset);
+                AssetPnl {
+                    asset: *asset,
+                    position: quantity,
+                    average_cost: (quantity != 0).then(|| position.cost() / quantity as f64),
+                    realized: position.realized,
+                    unrealized: mark.map_or(0.0, |mark| quantity as f64 * mark - position.cost()),
+                    mark,
+                    fees: 0,
+                    volume: position.volume,
+                    notional: position.notional,
+                }
+            })
+            .collect();
+        (!assets.is_empty()).then(|| TraderPnl {
+            trader_name: trader_name.to_string(),
+            assets,
+        })
+    }
+
+    // Every trader who traded, by name
+    pub fn pnl_report(&self) -> Vec<TraderPnl> {
+        self.users.keys().filter_map(|trader_name| self.pnl(trader_name)).collect()
+    }
+
+    pub fn save_pnl_report<P: AsRef<Path>>(&self, path: P) {
+        let file = File::create(path).expect("Couldn't create PnL report");
+        let mut file = LineWriter::new(file);
+        for trader in self.pnl_report() {
+            write!(file, "{}", trader).expect("Couldn't write PnL report");
+        }
+        file.flush().expect("Couldn't flush PnL report");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+    use crate::order_matching_system::testing::funded_order_book;
+
+    fn position(fills: &[(i64, u64)], cost_basis: CostBasis) -> Position {
+        let mut position = Position::default();
+        for (quantity, price) in fills {
+            position.fill(*quantity, *price, cost_basis);
+        }
+        position
+    }
+
+    #[test]
+    fn sale_closes_the_oldest_lots_or_the_average_cost() {
+        let fills = [(10, 5), (10, 7), (-15, 8)];
+
+        let fifo = position(&fills, CostBasis::Fifo);
+        assert_eq!((fifo.realized, fifo.quantity(), fifo.cost()), (10.0 * 3.0 + 5.0 * 1.0, 5, 35.0));
+
+        let average = position(&fills, CostBasis::Average);
+        assert_eq!((average.realized, average.quantity(), average.cost()), (15.0 * 2.0, 5, 30.0));
+        assert_eq!((average.volume, average.notional), (35, 50 + 70 + 120));
+    }
+
+    #[test]
+    fn selling_what_was_not_bought_opens_a_short_that_buying_back_closes() {
+        let short = position(&[(-5, 10), (8, 7)], CostBasis::Fifo);
+        assert_eq!(short.realized, 5.0 * 3.0);
+        assert_eq!(short.lots, VecDeque::from([Lot { quantity: 3, price: 7.0 }]));
+    }
+
+    #[test]
+    fn report_marks_open_positions_to_the_last_trade_or_the_mid() {
+        let mut order_book = funded_order_book();
+        for serialized_command in ["new C2 s A 7 4", "new C1 b A 7 4", "new C2 s A 8 2", "new C1 b A 8 1", "new C3 b A 6 1"] {
+            order_book.execute(Command::parse(serialized_command).unwrap());
+        }
+
+        assert_eq!(
+            order_book.pnl("C1").unwrap().to_string(),
+            "C1 A position 5 average_cost 7.20 realized 0.00 unrealized 4.00 mark 8.00 fees 0 volume 5 notional 36\n\
+             C1 total realized 0.00 unrealized 4.00 fees 0 volume 5 notional 36\n"
+        );
+        assert_eq!(order_book.pnl("C3"), None);
+        assert_eq!(order_book.pnl_report().len(), 2);
+
+        order_book.pnl.config.mark = MarkPrice::Mid;
+        assert_eq!(order_book.mark_price(AssetName::A), Some(7.0));
+        assert_eq!(order_book.pnl("C1").unwrap().unrealized(), 35.0 - 36.0);
+        assert_eq!(order_book.pnl("C2").unwrap().unrealized(), -35.0 + 36.0);
+    }
+
+    #[test]
+    fn config_lines_override_the_defaults() {
+        let path = std::env::temp_dir().join("exchange_pnl_config.txt");
+        std::fs::write(&path, "cost_basis average\nmark mid\n").unwrap();
+        assert_eq!(PnlConfig::load(&path), PnlConfig { cost_basis: CostBasis::Average, mark: MarkPrice::Mid });
+        assert_eq!(PnlConfig::load("resources/no_such_pnl_config.txt"), PnlConfig::default());
+    }
+}
@@ -0,0 +1,364 @@
+use crate::asset_name::AssetName;
+use crate::clock::Clock;
+use crate::command::Command;
+use crate::order::{Order, TimeInForce};
+use crate::order_matching_system::listener::OrderEvent;
+use crate::order_matching_system::market_data::InstrumentStatus;
+use crate::order_matching_system::order_book::OrderBook;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+pub const SCHEDULE_PATH: &str = "resources/schedule.txt";
+
+pub const INSTRUMENT_HALTED: &str = "Instrument is halted";
+pub const DAY_ORDER_WHILE_CLOSED: &str = "Day orders are not accepted while the instrument is closed";
+
+// Trading phase of one instrument. What the book does in each:
+//   pre-open          orders are accepted and rest without matching
+//   opening auction   a call: orders rest and the indicative uncross is published
+//   continuous        orders match as they arrive
+//   halted            only cancels are accepted
+//   closing auction   a call, as the opening one
+//   closed            good-till-cancelled orders are accepted and rest, day orders are rejected
+// Entering continuous trading uncrosses whatever the book collected, and so does a call that closes.
+// Day orders expire when the instrument closes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SessionState {
+    PreOpen,
+    OpeningAuction,
+    // instruments nobody scheduled trade continuously
+    #[default]
+    Continuous,
+    Halted,
+    ClosingAuction,
+    Closed,
+}
+
+impl SessionState {
+    pub fn is_call(self) -> bool {
+        matches!(self, SessionState::OpeningAuction | SessionState::ClosingAuction)
+    }
+
+    pub fn status(self) -> InstrumentStatus {
+        match self {
+            SessionState::PreOpen => InstrumentStatus::PreOpen,
+            SessionState::OpeningAuction | SessionState::ClosingAuction => InstrumentStatus::Auction,
+            SessionState::Continuous => InstrumentStatus::Trading,
+            SessionState::Halted => InstrumentStatus::Halted,
+            SessionState::Closed => InstrumentStatus::Closed,
+        }
+    }
+
+    // Why the command is not accepted for an instrument in this state, None if it is
+    pub fn rejection(self, command: &Command) -> Option<&'static str> {
+        match (self, command) {
+            (_, Command::Cancel { .. } | Command::Deposit { .. } | Command::Session { .. }) => None,
+            (SessionState::Halted, _) => Some(INSTRUMENT_HALTED),
+            (SessionState::Closed, Command::NewOrder(order)) if order.time_in_force == TimeInForce::Day => {
+                Some(DAY_ORDER_WHILE_CLOSED)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for SessionState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pre_open" => Ok(SessionState::PreOpen),
+            "opening_auction" => Ok(SessionState::OpeningAuction),
+            "continuous" => Ok(SessionState::Continuous),
+            "halted" => Ok(SessionState::Halted),
+            "closing_auction" => Ok(SessionState::ClosingAuction),
+            "closed" => Ok(SessionState::Closed),
+            _ => Err(format!("Bad session state: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SessionState::PreOpen => "pre_open",
+            SessionState::OpeningAuction => "opening_auction",
+            SessionState::Continuous => "continuous",
+            SessionState::Halted => "halted",
+            SessionState::ClosingAuction => "closing_auction",
+            SessionState::Closed => "closed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// The states of a trading day by the second of the day (UTC) they start at, the same for every instrument
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    transitions: Vec<(u64, SessionState)>,
+}
+
+impl Schedule {
+    pub fn new(mut transitions: Vec<(u64, SessionState)>) -> Self {
+        assert!(!transitions.is_empty(), "Schedule needs at least one transition");
+        transitions.sort();
+        Schedule { transitions }
+    }
+
+    // Lines of "<HH:MM[:SS]> <state>", in any order. No file means no schedule.
+    pub fn load<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        let transitions = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (time, state) = line.split_once(' ').expect("Schedule line is <HH:MM[:SS]> <state>");
+                (parse_time(time).expect("Couldn't parse schedule time"), state.trim().parse().expect("Unknown session state"))
+            })
+            .collect();
+        Some(Self::new(transitions))
+    }
+
+    // Before the first transition of the day the last one of the day before still holds
+    pub fn state_at(&self, second_of_day: u64) -> SessionState {
+        self.transitions
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= second_of_day)
+            .or(self.transitions.last())
+            .map(|(_, state)| *state)
+            .unwrap()
+    }
+}
+
+fn parse_time(time: &str) -> Option<u64> {
+    let parts: Vec<u64> = time.split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    match parts.as_slice() {
+        [hours, minutes] if *hours < 24 && *minutes < 60 => Some(hours * 3600 + minutes * 60),
+        [hours, minutes, seconds] if *hours < 24 && *minutes < 60 && *seconds < 60 => {
+            Some(hours * 3600 + minutes * 60 + seconds)
+        }
+        _ => None,
+    }
+}
+
+// Turns the schedule into session commands as the clock passes its transitions. The commands go
+// through the journal like any other, so a replay does not depend on when it runs.
+pub struct SessionScheduler {
+    schedule: Schedule,
+    clock: Box<dyn Clock>,
+    scheduled: Option<SessionState>,
+}
+
+impl SessionScheduler {
+    pub fn new(schedule: Schedule, clock: impl Clock + 'static) -> Self {
+        SessionScheduler {
+            schedule,
+            clock: Box::new(clock),
+            scheduled: None,
+        }
+    }
+
+    // Transitions the book is due for. The first call brings every instrument to the scheduled state,
+    // except one an admin halted: a forced state holds until the next scheduled transition.
+    pub fn due(&mut self, order_book: &OrderBook) -> Vec<Command> {
+        let state = self.schedule.state_at(self.clock.second_of_day());
+        let first = self.scheduled.is_none();
+        if (self.scheduled == Some(state)) {
+            return Vec::new();
+        }
+        self.scheduled = Some(state);
+
+        let mut commands = Vec::new();
+        let mut asset = AssetName::A;
+        while asset != AssetName::Unknown {
+            let current = order_book.session(asset);
+            if (current != state && !(first && current == SessionState::Halted)) {
+                commands.push(Command::Session { asset, state });
+            }
+            asset = asset.next();
+        }
+        commands
+    }
+}
+
+impl OrderBook {
+    pub fn session(&self, asset: AssetName) -> SessionState {
+        self.sessions.get(&asset).copied().unwrap_or_default()
+    }
+
+    // Why the book would not accept the command in the current session, None if it would
+    pub fn session_rejection(&self, command: &Command) -> Option<&'static str> {
+        let asset = self.command_asset(command)?;
+        self.session(asset).rejection(command)
+    }
+
+    pub fn set_session(&mut self, asset: AssetName, state: SessionState) {
+        let previous = self.session(asset);
+        if (previous == state) {
+            return;
+        }
+        self.sessions.insert(asset, state);
+        if (state == SessionState::Continuous || (previous.is_call() && state == SessionState::Closed)) {
+            self.uncross(asset);
+        }
+        if (state == SessionState::Closed) {
+            self.expire_day_orders(asset);
+        }
+    }
+
+    // Takes every resting day order of the asset off the book, the listeners release what they reserved
+    pub fn expire_day_orders(&mut self, asset: AssetName) -> Vec<Order> {
+        let trees = [self.buy_limits.get(&asset), self.sell_limits.get(&asset)];
+        let expired: Vec<usize> = trees
+            .into_iter()
+            .flatten()
+            .flat_map(|limits| limits.orders(&self.orders))
+            .map(|handle| &self.orders[handle])
+            .filter(|order| order.time_in_force == TimeInForce::Day)
+            .map(|order| order.id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|order_id| {
+                let order = self.remove_order(order_id)?;
+                self.notify(OrderEvent::Expire(order.clone()));
+                Some(order)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{SimulatedClock, NANOS_PER_SECOND};
+    use crate::order_matching_system::market_data::MarketDataEvent;
+    use crate::order_matching_system::testing::{execute_all, funded_order_book};
+
+    fn schedule() -> Schedule {
+        Schedule::new(
+            [
+                ("08:00", SessionState::PreOpen),
+                ("09:00", SessionState::OpeningAuction),
+                ("09:30", SessionState::Continuous),
+                ("17:30", SessionState::ClosingAuction),
+                ("17:35", SessionState::Closed),
+            ]
+            .map(|(time, state)| (parse_time(time).unwrap(), state))
+            .to_vec(),
+        )
+    }
+
+    #[test]
+    fn schedule_holds_the_last_state_started() {
+        let schedule = schedule();
+        assert_eq!(schedule.state_at(parse_time("08:59:59").unwrap()), SessionState::PreOpen);
+        assert_eq!(schedule.state_at(parse_time("09:00").unwrap()), SessionState::OpeningAuction);
+        assert_eq!(schedule.state_at(parse_time("12:00").unwrap()), SessionState::Continuous);
+        assert_eq!(schedule.state_at(parse_time("23:59:59").unwrap()), SessionState::Closed);
+        // before the first transition it is still yesterday's close
+        assert_eq!(schedule.state_at(parse_time("03:00").unwrap()), SessionState::Closed);
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("9"), None);
+    }
+
+    #[test]
+    fn each_state_accepts_its_own_actions() {
+        let mut order_book = funded_order_book();
+        execute_all(&mut order_book, &["new C1 b A 8 10", "session A halted"]);
+        let (new, amend, cancel) = (
+            Command::parse("new C3 b A 8 5").unwrap(),
+            Command::parse("amend 0 8 5").unwrap(),
+            Command::parse("cancel 0").unwrap(),
+        );
+        assert_eq!(order_book.session_rejection(&new), Some(INSTRUMENT_HALTED));
+        assert_eq!(order_book.session_rejection(&amend), Some(INSTRUMENT_HALTED));
+        assert_eq!(order_book.session_rejection(&cancel), None);
+
+        // rejected commands leave the book as it was
+        execute_all(&mut order_book, &["new C3 b A 8 5", "amend 0 8 5"]);
+        assert_eq!(order_book.depth(AssetName::A, 5).bids[0].volume, 10);
+        assert_eq!(order_book.order_id_sequence, 1);
+
+        execute_all(&mut order_book, &["session A closed"]);
+        let day = Command::parse("new C3 b A 8 5 day").unwrap();
+        assert_eq!(order_book.session_rejection(&day), Some(DAY_ORDER_WHILE_CLOSED));
+        assert_eq!(order_book.session_rejection(&new), None);
+        assert_eq!(order_book.session_rejection(&amend), None);
+    }
+
+    #[test]
+    fn orders_rest_before_the_open_and_uncross_when_trading_starts() {
+        let mut order_book = funded_order_book();
+        execute_all(
+            &mut order_book,
+            &["session B pre_open", "new C1 b B 12 10", "new C2 s B 10 4", "session B opening_auction"],
+        );
+        assert!(order_book.trades.is_empty() && order_book.in_auction(AssetName::B));
+
+        execute_all(&mut order_book, &["session B continuous", "new C4 s B 12 6"]);
+        let trades: Vec<(u64, u64)> = order_book.trades.iter().map(|trade| (trade.price, trade.amount)).collect();
+        assert_eq!(trades, vec![(12, 4), (12, 6)]);
+        let statuses: Vec<MarketDataEvent> = order_book
+            .market_data
+            .messages
+            .iter()
+            .map(|message| message.event.clone())
+            .filter(|event| matches!(event, MarketDataEvent::Status(_)))
+            .collect();
+        assert_eq!(
+            statuses,
+            [InstrumentStatus::PreOpen, InstrumentStatus::Auction, InstrumentStatus::Trading].map(MarketDataEvent::Status)
+        );
+    }
+
+    #[test]
+    fn closing_uncrosses_the_call_and_expires_day_orders() {
+        let mut order_book = funded_order_book();
+        execute_all(
+            &mut order_book,
+            &[
+                "session A closing_auction",
+                "new C1 b A 10 5 day",
+                "new C2 s A 10 2 day",
+                "new C3 b A 9 5",
+                "new C4 s A 20 3 day",
+                "session A closed",
+            ],
+        );
+        assert_eq!(order_book.trades.len(), 1);
+        // the rest of C1's bid and C4's ask expire, C3's good-till-cancelled bid stays
+        let resting: Vec<usize> = order_book.orders.values().map(|order| order.id).collect();
+        assert_eq!(resting, vec![2]);
+        let (c1, c4) = (&order_book.users["C1"], &order_book.users["C4"]);
+        assert_eq!((c1.usd_balance, c1.reserved_usd), (1_000_000 - 20, 0));
+        assert_eq!((c4.assets_count[&AssetName::A], c4.reserved_assets[&AssetName::A]), (10_000, 0));
+    }
+
+    #[test]
+    fn scheduler_follows_the_clock_without_undoing_a_halt() {
+        let mut order_book = funded_order_book();
+        execute_all(&mut order_book, &["session C halted"]);
+        let clock = SimulatedClock::new(parse_time("08:30").unwrap() * NANOS_PER_SECOND);
+        let mut scheduler = SessionScheduler::new(schedule(), clock.clone());
+
+        let due = scheduler.due(&order_book);
+        assert_eq!(due.len(), 3);
+        assert!(due.iter().all(|command| matches!(command, Command::Session { asset, state: SessionState::PreOpen } if *asset != AssetName::C)));
+        for command in due {
+            order_book.execute(command);
+        }
+        clock.advance(10 * 60 * NANOS_PER_SECOND);
+        assert!(scheduler.due(&order_book).is_empty());
+
+        clock.advance(30 * 60 * NANOS_PER_SECOND);
+        let due = scheduler.due(&order_book);
+        assert_eq!(due.len(), 4);
+        assert!(due.iter().all(|command| matches!(command, Command::Session { state: SessionState::OpeningAuction, .. })));
+    }
+}
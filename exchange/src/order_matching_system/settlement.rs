@@ -0,0 +1,73 @@
+use crate::order::Order;
+use crate::order_matching_system::listener::OrderListener;
+use crate::trade::Trade;
+use crate::trader::Trader;
+use std::collections::BTreeMap;
+
+pub const UNKNOWN_TRADER: &str = "Unknown trader";
+pub const INSUFFICIENT_FUNDS: &str = "Insufficient funds";
+pub const BALANCE_OVERFLOW: &str = "Balance overflow";
+
+// Keeps the traders' balances as a listener: an accepted order reserves what it may spend, a fill pays
+// out of the reservations, and whatever is cancelled, expires or is amended away is released.
+// The book rejects orders of unknown traders and orders they cannot afford before anything is settled.
+pub struct Settlement<'a>(pub &'a mut BTreeMap<String, Trader>);
+
+impl Settlement<'_> {
+    fn trader(&mut self, order: &Order) -> Option<&mut Trader> {
+        self.0.get_mut(&order.trader_name)
+    }
+}
+
+// Why the trader of order cannot reserve for it, once old is released if the order replaces it
+pub fn funds_rejection(users: &BTreeMap<String, Trader>, order: &Order, old: Option<&Order>) -> Option<&'static str> {
+    let Some(trader) = users.get(&order.trader_name) else {
+        return Some(UNKNOWN_TRADER);
+    };
+    let affordable = match old {
+        Some(old) => trader.can_afford_amend(old, order),
+        None => trader.can_afford(order),
+    };
+    (!affordable).then_some(INSUFFICIENT_FUNDS)
+}
+
+impl OrderListener for Settlement<'_> {
+    fn on_accept(&mut self, order: &Order) {
+        if let Some(trader) = self.trader(order) {
+            trader.block_funds(order);
+        }
+    }
+
+    // The buyer reserved at their limit, what a lower trade price saves goes back to them.
+    // Each side is settled on its own, so a trader on both sides of the trade gets both.
+    fn on_fill(&mut self, trade: &Trade, buy: &Order, sell: &Order) {
+        if let Some(buyer) = self.trader(buy) {
+            *buyer.assets_count.entry(trade.asset).or_insert(0) += trade.amount;
+            buyer.reserved_usd -= buy.price * trade.amount;
+            buyer.usd_balance += (buy.price - trade.price) * trade.amount;
+        }
+
+        let Some(seller) = self.trader(sell) else {
+            return;
+        };
+        seller.usd_balance += trade.price * trade.amount;
+        *seller.reserved_assets.entry(trade.asset).or_insert(0) -= trade.amount;
+    }
+
+    fn on_amend(&mut self, old: &Order, new: &Order) {
+        if let Some(trader) = self.trader(old) {
+            trader.release_funds(old, old.amount);
+            trader.block_funds(new);
+        }
+    }
+
+    fn on_cancel(&mut self, order: &Order) {
+        if let Some(trader) = self.trader(order) {
+            trader.release_funds(order, order.amount);
+        }
+    }
+
+    fn on_expire(&mut self, order: &Order) {
+        self.on_cancel(order);
+    }
+}
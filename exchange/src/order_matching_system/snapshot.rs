@@ -0,0 +1,329 @@
+use crate::asset_name::AssetName;
+use crate::deserialize::Deserialize;
+use crate::order::{Direction, Order};
+use crate::order_matching_system::order_book::OrderBook;
+use crate::order_matching_system::order_store::{OrderRecord, OrderStatus};
+use crate::order_matching_system::pnl::{Lot, Position};
+use crate::order_matching_system::session::SessionState;
+use crate::trader::Trader;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+pub const SNAPSHOT_PATH: &str = "resources/snapshot.txt";
+
+// Snapshot lines:
+//   N <order_id_sequence>
+//   X <trade_id_sequence>
+//   J <journal_sequence>
+//   E <entry_sequence>
+//   T <name> <usd> <A> <B> <C> <D> <reserved usd> <reserved A> <reserved B> <reserved C> <reserved D>
+//   O <id> <entry_sequence> <entry_timestamp> <update_timestamp> <trader> <b|s> <asset> <price> <amount> [day] [cl=<client order id>]
+//   S <asset> <session state> for an asset that is not trading continuously
+//   H <status> <filled> <notional> <trade ids joined by commas, - for none> <id> <entry_sequence>
+//     <entry_timestamp> <update_timestamp> <trader> <b|s> <asset> <price> <amount> [day] [cl=<client order id>]
+//     for every record of the order store, in the order the orders arrived
+//   P <trader> <asset> <realized> <volume> <notional> <open lots as quantity@price joined by commas, - for none>
+//     for every position of the PnL tracker
+// Orders are written per side and asset in price order and queue order inside each limit,
+// so restoring them one by one rebuilds the same queues. A snapshot without H lines
+// restores its resting orders as new ones.
+impl OrderBook {
+    // Written next to the path and renamed over it once synced, so a crash leaves the old snapshot or the new one
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let mut file = BufWriter::new(File::create(&temp_path)?);
+
+        writeln!(file, "N {}", self.order_id_sequence)?;
+        writeln!(file, "X {}", self.trade_id_sequence)?;
+        writeln!(file, "J {}", self.journal_sequence)?;
+        writeln!(file, "E {}", self.entry_sequence)?;
+        for trader in self.users.values() {
+            let mut line = format!("T {} {}", trader.serialize(), trader.reserved_usd);
+            let mut asset_name = AssetName::A;
+            while asset_name != AssetName::Unknown {
+                let reserved = trader.reserved_assets.get(&asset_name).unwrap_or(&0);
+                line.push_str(&format!(" {}", reserved));
+                asset_name = asset_name.next();
+            }
+            writeln!(file, "{}", line)?;
+        }
+        let trees = self.buy_limits.values().chain(self.sell_limits.values());
+        for order in trees.flat_map(|limits| limits.orders(&self.orders)) {
+            let order = &self.orders[order];
+            writeln!(
+                file,
+                "O {} {} {} {} {}",
+                order.id,
+                order.entry_sequence,
+                order.entry_timestamp,
+                order.update_timestamp,
+                order.serialize()
+            )
+                ?;
+        }
+        for (asset, state) in &self.sessions {
+            if (*state != SessionState::Continuous) {
+                writeln!(file, "S {:?} {}", asset, state)?;
+            }
+        }
+        for record in self.order_store.records() {
+            let trade_ids: Vec<String> = record.trade_ids.iter().map(usize::to_string).collect();
+            let trade_ids = if trade_ids.is_empty() { "-".to_string() } else { trade_ids.join(",") };
+            let order = &record.order;
+            writeln!(
+                file,
+                "H {} {} {} {} {} {} {} {} {}",
+                record.status,
+                record.filled,
+                record.notional,
+                trade_ids,
+                order.id,
+                order.entry_sequence,
+                order.entry_timestamp,
+                order.update_timestamp,
+                order.serialize()
+            )
+            ?;
+        }
+        for ((trader_name, asset), position) in &self.pnl.positions {
+            let lots: Vec<String> = position.lots.iter().map(|lot| format!("{}@{}", lot.quantity, lot.price)).collect();
+            let lots = if lots.is_empty() { "-".to_string() } else { lots.join(",") };
+            writeln!(
+                file,
+                "P {} {:?} {} {} {} {}",
+                trader_name, asset, position.realized, position.volume, position.notional, lots
+            )
+            ?;
+        }
+        file.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        // the rename itself is durable once the directory is synced
+        let directory = path.parent().filter(|parent| !parent.as_os_str().is_empty());
+        File::open(directory.unwrap_or(Path::new(".")))?.sync_all()
+    }
+
+    pub fn restore_snapshot<P: AsRef<Path>>(path: P) -> Option<Self> {
+        if !path.as_ref().exists() {
+            return None;
+        }
+        let mut order_book = OrderBook::default();
+
+        let file = File::open(path).expect("Unable to open snapshot file");
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            let (kind, rest) = line.split_once(' ').expect("Malformed snapshot line");
+            match kind {
+                "N" => {
+                    order_book.order_id_sequence = rest.parse().expect("Can't parse to usize");
+                }
+                "X" => {
+                    order_book.trade_id_sequence = rest.parse().expect("Can't parse to usize");
+                }
+                "J" => {
+                    order_book.journal_sequence = rest.parse().expect("Can't parse to u64");
+                }
+                "E" => {
+                    order_book.entry_sequence = rest.parse().expect("Can't parse to u64");
+                }
+                "T" => {
+                    let mut trader = Trader::deserialize(rest.to_string());
+                    let reserved: Vec<u64> = rest
+                        .split(' ')
+                        .skip(AssetName::Unknown.index() + 2)
+                        .map(|part| part.parse::<u64>().expect("Can't parse to u64"))
+                        .collect();
+                    trader.id = order_book.users.len();
+                    trader.reserved_usd = reserved[0];
+                    let mut asset_name = AssetName::A;
+                    while asset_name != AssetName::Unknown {
+                        trader.reserved_assets.insert(asset_name, reserved[asset_name.index() + 1]);
+                        asset_name = asset_name.next();
+                    }
+                    order_book.users.insert(trader.name.clone(), trader);
+                }
+                "O" => {
+                    let parts: Vec<&str> = rest.splitn(5, ' ').collect();
+                    let [id, entry_sequence, entry_timestamp, update_timestamp, serialized_order] = parts[..] else {
+                        panic!("Malformed snapshot order: {}", line);
+                    };
+                    let mut order = Order::deserialize(serialized_order.to_string());
+                    order.id = id.parse().expect("Can't parse to usize");
+                    order.entry_sequence = entry_sequence.parse().expect("Can't parse to u64");
+                    order.entry_timestamp = entry_timestamp.parse().expect("Can't parse to u64");
+                    order.update_timestamp = update_timestamp.parse().expect("Can't parse to u64");
+                    order_book.client_order_ids.insert(&order);
+                    let handle = order_book.orders.insert(order);
+                    order_book.rest(handle);
+                }
+                "S" => {
+                    let (asset, state) = rest.split_once(' ').expect("Malformed snapshot session");
+                    order_book.sessions.insert(
+                        AssetName::from_str(asset).expect("Unknown asset"),
+                        state.parse().expect("Unknown session state"),
+                    );
+                }
+                "H" => {
+                    let parts: Vec<&str> = rest.splitn(9, ' ').collect();
+                    let [status, filled, notional, trade_ids, id, entry_sequence, entry_timestamp, update_timestamp, serialized_order] =
+                        parts[..]
+                    else {
+                        panic!("Malformed snapshot order record: {}", line);
+                    };
+                    let mut order = Order::deserialize(serialized_order.to_string());
+                    order.id = id.parse().expect("Can't parse to usize");
+                    order.entry_sequence = entry_sequence.parse().expect("Can't parse to u64");
+                    order.entry_timestamp = entry_timestamp.parse().expect("Can't parse to u64");
+                    order.update_timestamp = update_timestamp.parse().expect("Can't parse to u64");
+                    order_book.order_store.insert(OrderRecord {
+                        order,
+                        status: status.parse().expect("Unknown order status"),
+                        filled: filled.parse().expect("Can't parse to u64"),
+                        notional: notional.parse().expect("Can't parse to u64"),
+                        trade_ids: match trade_ids {
+                            "-" => Vec::new(),
+                            _ => trade_ids.split(',').map(|id| id.parse().expect("Can't parse to usize")).collect(),
+                        },
+                    });
+                }
+                "P" => {
+                    let parts: Vec<&str> = rest.split(' ').collect();
+                    let [trader_name, asset, realized, volume, notional, lots] = parts[..] else {
+                        panic!("Malformed snapshot position: {}", line);
+                    };
+                    let lots = match lots {
+                        "-" => Default::default(),
+                        _ => lots
+                            .split(',')
+                            .map(|lot| {
+                                let (quantity, price) = lot.split_once('@').expect("Malformed snapshot lot");
+                                Lot {
+                                    quantity: quantity.parse().expect("Can't parse to i64"),
+                                    price: price.parse().expect("Can't parse to f64"),
+                                }
+                            })
+                            .collect(),
+                    };
+                    order_book.pnl.positions.insert(
+                        (trader_name.to_string(), AssetName::from_str(asset).expect("Unknown asset")),
+                        Position {
+                            lots,
+                            realized: realized.parse().expect("Can't parse to f64"),
+                            volume: volume.parse().expect("Can't parse to u64"),
+                            notional: notional.parse().expect("Can't parse to u64"),
+                        },
+                    );
+                }
+                _ => panic!("Unknown snapshot line: {}", line),
+            }
+        }
+
+        let mut unrecorded: Vec<Order> = order_book
+            .orders
+            .values()
+            .filter(|order| order_book.order_store.get(order.id).is_none())
+            .cloned()
+            .collect();
+        unrecorded.sort_by_key(|order| order.id);
+        for order in unrecorded {
+            order_book.order_store.insert(OrderRecord::new(order, OrderStatus::New));
+        }
+        Some(order_book)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+    use crate::order_matching_system::arena::OrderHandle;
+
+    fn order_book_with_resting_orders() -> OrderBook {
+        let mut order_book = OrderBook {
+            users: [
+                Trader::deserialize("C1 2000 10 5 15 0".to_string()),
+                Trader::deserialize("C2 1000 20 35 40 10".to_string()),
+            ]
+            .into_iter()
+            .map(|trader| (trader.name.clone(), trader))
+            .collect(),
+            ..Default::default()
+        };
+        for serialized_order in ["C1 b A 7 10", "C2 b A 7 5", "C1 b A 6 1", "C2 s A 9 4", "C1 s B 10 2 day"] {
+            let mut order = Order::deserialize(serialized_order.to_string());
+            order.id = order_book.next_order_id();
+            order_book.limit(order);
+        }
+        order_book
+    }
+
+    fn queue_ids(order_book: &OrderBook, orders: Vec<OrderHandle>) -> Vec<usize> {
+        orders.iter().map(|order| order_book.orders[*order].id).collect()
+    }
+
+    #[test]
+    fn restore_of_missing_snapshot_returns_none() {
+        assert!(OrderBook::restore_snapshot("resources/no_such_snapshot.txt").is_none());
+    }
+
+    #[test]
+    fn save_replaces_the_snapshot_whole_or_fails_without_touching_it() {
+        let path = std::env::temp_dir().join("exchange_snapshot_replaced.txt");
+        std::fs::write(&path, "stale").unwrap();
+
+        order_book_with_resting_orders().save_snapshot(&path).unwrap();
+        assert!(OrderBook::restore_snapshot(&path).is_some());
+        assert!(!std::env::temp_dir().join("exchange_snapshot_replaced.txt.tmp").exists());
+
+        let missing_directory = std::env::temp_dir().join("exchange_no_such_directory").join("snapshot.txt");
+        assert!(OrderBook::default().save_snapshot(missing_directory).is_err());
+    }
+
+    #[test]
+    fn restored_order_book_keeps_levels_queues_and_reserved_balances() {
+        let order_book = order_book_with_resting_orders();
+        let path = std::env::temp_dir().join("exchange_snapshot_round_trip.txt");
+
+        order_book.save_snapshot(&path).unwrap();
+        let restored = OrderBook::restore_snapshot(&path).unwrap();
+
+        assert_eq!(restored.order_id_sequence, 5);
+        assert_eq!(restored.entry_sequence, 5);
+        assert_eq!(restored.buy_limits[&AssetName::A].limits.len(), 2);
+        assert_eq!(queue_ids(&restored, restored.buy_limits[&AssetName::A].orders(&restored.orders)), vec![2, 0, 1]);
+        assert_eq!(queue_ids(&restored, restored.sell_limits[&AssetName::A].orders(&restored.orders)), vec![3]);
+        assert_eq!(queue_ids(&restored, restored.sell_limits[&AssetName::B].orders(&restored.orders)), vec![4]);
+        assert_eq!(restored.orders.len(), 5);
+
+        let c1 = &restored.users["C1"];
+        assert_eq!(c1.usd_balance, 2000 - 7 * 10 - 6);
+        assert_eq!(c1.reserved_usd, 7 * 10 + 6);
+        assert_eq!(c1.reserved_assets[&AssetName::B], 2);
+        assert_eq!(restored.users["C2"].reserved_assets[&AssetName::A], 4);
+        assert_eq!(restored.order_store.records(), order_book.order_store.records());
+    }
+
+    #[test]
+    fn snapshot_of_restored_order_book_is_identical() {
+        let path = std::env::temp_dir().join("exchange_snapshot_identical.txt");
+        let restored_path = std::env::temp_dir().join("exchange_snapshot_identical_restored.txt");
+
+        let mut order_book = order_book_with_resting_orders();
+        order_book.execute(Command::parse("new C2 s A 6 12").unwrap());
+        order_book.set_session(AssetName::B, SessionState::OpeningAuction);
+        order_book.save_snapshot(&path).unwrap();
+        let restored = OrderBook::restore_snapshot(&path).unwrap();
+        restored.save_snapshot(&restored_path).unwrap();
+
+        assert!(restored.in_auction(AssetName::B) && !restored.in_auction(AssetName::A));
+        assert_eq!(restored.order_store.get(5).unwrap().trade_ids, vec![0, 1]);
+        assert_eq!(restored.pnl.positions, order_book.pnl.positions);
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            std::fs::read_to_string(&restored_path).unwrap()
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::asset_name::AssetName;
+use crate::command::Command;
+use crate::deserialize::Deserialize;
+use crate::order::Direction;
+use crate::order_matching_system::order_book::OrderBook;
+use crate::trader::Trader;
+use proptest::prelude::*;
+use std::collections::BTreeMap;
+
+// Traders with enough funds that no generated order runs out of them
+pub fn funded_order_book() -> OrderBook {
+    OrderBook {
+        users: ["C1", "C2", "C3", "C4"]
+            .into_iter()
+            .map(|name| Trader::deserialize(format!("{} 1000000 10000 10000 10000 10000", name)))
+            .map(|trader| (trader.name.clone(), trader))
+            .collect(),
+        ..Default::default()
+    }
+}
+
+pub fn execute_all(order_book: &mut OrderBook, serialized_commands: &[&str]) {
+    for serialized_command in serialized_commands {
+        order_book.execute(Command::parse(serialized_command).unwrap());
+    }
+}
+
+// Traders who can pay for only some of the orders trading_strategy generates: the rest are buys they
+// cannot afford and sales of assets they do not hold, which the book has to reject
+pub fn underfunded_order_book() -> OrderBook {
+    OrderBook {
+        users: ["C1 100 5 0 0 0", "C2 0 20 10 0 0", "C3 60 0 0 0 0", "C4 500 0 30 0 0"]
+            .into_iter()
+            .map(|serialized| Trader::deserialize(serialized.to_string()))
+            .map(|trader| (trader.name.clone(), trader))
+            .collect(),
+        ..Default::default()
+    }
+}
+
+// Serialized commands over assets A and B for funded_order_book, day orders, session changes and
+// client order ids included, few enough of them to repeat. Buyers and sellers are different traders.
+pub fn command_strategy() -> impl Strategy<Value = String> {
+    let client_order_ids = ["", " cl=x", " cl=y"];
+    let new_order = (
+        any::<bool>(),
+        0..2usize,
+        0..2usize,
+        5..15u64,
+        1..20u64,
+        prop::bool::weighted(0.3),
+        prop::sample::select(client_order_ids.to_vec()),
+    )
+        .prop_map(|(buy, trader, asset, price, amount, day, client_order_id)| {
+            let (direction, trader) = if buy { ("b", ["C1", "C3"][trader]) } else { ("s", ["C2", "C4"][trader]) };
+            let time_in_force = if day { " day" } else { "" };
+            let (asset, tail) = (["A", "B"][asset], format!("{}{}", time_in_force, client_order_id));
+            format!("new {} {} {} {} {}{}", trader, direction, asset, price, amount, tail)
+        });
+    let client_order = (prop::sample::select(vec!["C1", "C2", "C3", "C4"]), prop::sample::select(vec!["x", "y"]))
+        .prop_map(|(trader, client_order_id)| format!("{} {}", trader, client_order_id));
+    let order = prop_oneof![(0..40usize).prop_map(|order_id| order_id.to_string()), client_order];
+    let states = ["pre_open", "opening_auction", "continuous", "halted", "closing_auction", "closed"];
+    prop_oneof![
+        3 => new_order,
+        1 => order.clone().prop_map(|order| format!("cancel {}", order)),
+        1 => (order, 5..15u64, 0..20u64)
+            .prop_map(|(order, price, amount)| format!("amend {} {} {}", order, price, amount)),
+        1 => (0..2usize, prop::sample::select(states.to_vec()))
+            .prop_map(|(asset, state)| format!("session {} {}", ["A", "B"][asset], state)),
+    ]
+}
+
+// Serialized orders, cancels and amendments over assets A and B for funded_order_book or
+// underfunded_order_book, in continuous trading only. Any trader may take either side.
+pub fn trading_strategy() -> impl Strategy<Value = String> {
+    let new_order = (any::<bool>(), 0..4usize, 0..2usize, 5..15u64, 1..20u64).prop_map(
+        |(buy, trader, asset, price, amount)| {
+            let direction = if buy { "b" } else { "s" };
+            format!("new {} {} {} {} {}", ["C1", "C2", "C3", "C4"][trader], direction, ["A", "B"][asset], price, amount)
+        },
+    );
+    prop_oneof![
+        3 => new_order,
+        1 => (0..40usize).prop_map(|order_id| format!("cancel {}", order_id)),
+        1 => (0..40usize, 5..15u64, 0..20u64)
+            .prop_map(|(order_id, price, amount)| format!("amend {} {} {}", order_id, price, amount)),
+    ]
+}
+
+// USD and per asset quantities held by all traders, reserved included
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Holdings {
+    pub usd: u64,
+    pub assets: BTreeMap<AssetName, u64>,
+}
+
+impl Holdings {
+    pub fn of(order_book: &OrderBook) -> Self {
+        let mut holdings = Holdings { usd: 0, assets: BTreeMap::new() };
+        for trader in order_book.users.values() {
+            holdings.usd += trader.usd_balance + trader.reserved_usd;
+            for (asset, count) in trader.assets_count.iter().chain(trader.reserved_assets.iter()) {
+                *holdings.assets.entry(*asset).or_insert(0) += count;
+            }
+        }
+        holdings
+    }
+}
+
+// Checks what has to hold between any two commands of continuous trading: the book is not crossed,
+// nothing is created or destroyed, every reservation is backed by an open order, and each level
+// agrees with the orders queued at it
+pub fn check_invariants(order_book: &OrderBook, initial: &Holdings) -> Result<(), TestCaseError> {
+    for (asset, bids) in &order_book.buy_limits {
+        let best_ask = order_book.sell_limits.get(asset).and_then(|asks| asks.best_price());
+        if let (Some(bid), Some(ask)) = (bids.best_price(), best_ask) {
+            prop_assert!(bid < ask, "{:?} crossed: bid {} ask {}", asset, bid, ask);
+        }
+    }
+
+    prop_assert_eq!(&Holdings::of(order_book), initial);
+
+    let mut reserved: BTreeMap<&str, Holdings> = BTreeMap::new();
+    for order in order_book.orders.values() {
+        let holdings = reserved
+            .entry(&order.trader_name)
+            .or_insert_with(|| Holdings { usd: 0, assets: BTreeMap::new() });
+        match order.direction {
+            Direction::Buy => holdings.usd += order.price * order.amount,
+            Direction::Sell => *holdings.assets.entry(order.asset).or_insert(0) += order.amount,
+        }
+    }
+    for trader in order_book.users.values() {
+        let expected = reserved.remove(trader.name.as_str());
+        let expected_usd = expected.as_ref().map_or(0, |holdings| holdings.usd);
+        prop_assert_eq!(trader.reserved_usd, expected_usd, "reserved USD of {}", trader.name);
+        for asset in [AssetName::A, AssetName::B, AssetName::C, AssetName::D] {
+            let expected_count = expected
+                .as_ref()
+                .and_then(|holdings| holdings.assets.get(&asset).copied())
+                .unwrap_or(0);
+            let count = trader.reserved_assets.get(&asset).copied().unwrap_or(0);
+            prop_assert_eq!(count, expected_count, "reserved {:?} of {}", asset, trader.name);
+        }
+    }
+
+    for tree in order_book.buy_limits.values().chain(order_book.sell_limits.values()) {
+        for (level, (price, queue)) in tree.levels(usize::MAX).into_iter().zip(tree.queues(&order_book.orders)) {
+            prop_assert_eq!(level.price, price);
+            prop_assert_eq!(level.order_count, queue.len());
+            let volume: u64 = queue.iter().map(|handle| order_book.orders[*handle].amount).sum();
+            prop_assert_eq!(level.volume, volume, "volume at {}", price);
+            prop_assert!(queue.iter().all(|handle| order_book.orders[*handle].price == price));
+        }
+    }
+    Ok(())
+}
+
+// The clients resources/clients.txt started with, inline so tests don't change with the file
+pub fn clients() -> BTreeMap<String, Trader> {
+    ["C1 2000 10 5 15 0", "C2 1000 20 35 40 10", "C3 2000 0 15 10 0"]
+        .into_iter()
+        .enumerate()
+        .map(|(id, serialized)| {
+            let mut trader = Trader::deserialize(serialized.to_string());
+            trader.id = id;
+            (trader.name.clone(), trader)
+        })
+        .collect()
+}
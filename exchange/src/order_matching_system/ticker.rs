@@ -0,0 +1,132 @@
+use crate::asset_name::AssetName;
+use crate::order::Order;
+use crate::order_matching_system::limit_tree::Level;
+use crate::order_matching_system::listener::OrderListener;
+use crate::order_matching_system::order_book::OrderBook;
+use crate::trade::Trade;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// Statistics of one asset since the book was started, prices and sizes are None until they exist
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Ticker {
+    pub asset: AssetName,
+    pub last_price: Option<u64>,
+    pub last_size: Option<u64>,
+    pub best_bid: Option<Level>,
+    pub best_ask: Option<Level>,
+    pub open: Option<u64>,
+    pub high: Option<u64>,
+    pub low: Option<u64>,
+    pub volume: u64,
+    pub notional: u64,
+    pub trade_count: u64,
+}
+
+impl Ticker {
+    pub fn vwap(&self) -> Option<f64> {
+        (self.volume > 0).then(|| self.notional as f64 / self.volume as f64)
+    }
+}
+
+// The tickers of the book fold in every trade as it happens
+impl OrderListener for BTreeMap<AssetName, Ticker> {
+    fn on_fill(&mut self, trade: &Trade, buy: &Order, sell: &Order) {
+        let ticker = self.entry(trade.asset).or_insert_with(|| Ticker {
+            asset: trade.asset,
+            ..Default::default()
+        });
+        ticker.last_price = Some(trade.price);
+        ticker.last_size = Some(trade.amount);
+        ticker.open.get_or_insert(trade.price);
+        ticker.high = ticker.high.max(Some(trade.price));
+        ticker.low = Some(ticker.low.map_or(trade.price, |low| low.min(trade.price)));
+        ticker.volume += trade.amount;
+        ticker.notional += trade.price * trade.amount;
+        ticker.trade_count += 1;
+    }
+}
+
+impl OrderBook {
+    // Brings the asset's ticker to the current top of book
+    pub(crate) fn update_ticker(&mut self, asset: AssetName) {
+        let depth = self.depth(asset, 1);
+        let ticker = self.tickers.entry(asset).or_insert_with(|| Ticker {
+            asset,
+            ..Default::default()
+        });
+        ticker.best_bid = depth.bids.first().copied();
+        ticker.best_ask = depth.asks.first().copied();
+    }
+}
+
+// A last 7x5 bid 6x10 ask 8x3 open 7 high 7 low 7 volume 5 notional 35 vwap 7.0000 trades 1
+impl fmt::Display for Ticker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |value: Option<u64>| value.map_or("-".to_string(), |value| value.to_string());
+        let level = |level: Option<Level>| level.map_or("-".to_string(), |level| format!("{}x{}", level.price, level.volume));
+        let last = match (self.last_price, self.last_size) {
+            (Some(price), Some(size)) => format!("{}x{}", price, size),
+            _ => "-".to_string(),
+        };
+        write!(
+            f,
+            "{:?} last {} bid {} ask {} open {} high {} low {} volume {} notional {} vwap {} trades {}",
+            self.asset,
+            last,
+            level(self.best_bid),
+            level(self.best_ask),
+            value(self.open),
+            value(self.high),
+            value(self.low),
+            self.volume,
+            self.notional,
+            self.vwap().map_or("-".to_string(), |vwap| format!("{:.4}", vwap)),
+            self.trade_count
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+    use crate::order_matching_system::testing::funded_order_book;
+
+    #[test]
+    fn ticker_follows_trades_and_top_of_book() {
+        let mut order_book = funded_order_book();
+        for serialized_command in [
+            "new C2 s A 9 5",
+            "new C2 s A 11 5",
+            "new C1 b A 9 2",
+            "new C1 b A 11 6",
+            "new C3 b A 6 4",
+            "new C4 s B 3 1",
+        ] {
+            order_book.execute(Command::parse(serialized_command).unwrap());
+        }
+
+        let ticker = &order_book.tickers[&AssetName::A];
+        assert_eq!((ticker.last_price, ticker.last_size), (Some(11), Some(3)));
+        assert_eq!((ticker.open, ticker.high, ticker.low), (Some(9), Some(11), Some(9)));
+        assert_eq!((ticker.volume, ticker.notional, ticker.trade_count), (8, 9 * 2 + 9 * 3 + 11 * 3, 3));
+        assert_eq!(
+            ticker.best_bid,
+            Some(Level {
+                price: 6,
+                volume: 4,
+                order_count: 1
+            })
+        );
+        assert_eq!(ticker.best_ask.map(|level| (level.price, level.volume)), Some((11, 2)));
+        assert_eq!(
+            ticker.to_string(),
+            "A last 11x3 bid 6x4 ask 11x2 open 9 high 11 low 9 volume 8 notional 78 vwap 9.7500 trades 3"
+        );
+        assert_eq!(
+            order_book.tickers[&AssetName::B].to_string(),
+            "B last - bid - ask 3x1 open - high - low - volume 0 notional 0 vwap - trades 0"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use crate::asset_name::AssetName;
+use crate::batch;
+use crate::clock::SimulatedClock;
+use crate::command::Command;
+use crate::deserialize::Deserialize;
+use crate::order_matching_system::order_book::OrderBook;
+use crate::trader::Trader;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+pub const SCENARIOS_DIR: &str = "scenarios";
+const GOLDEN_START: &str = "[balances]";
+
+// A self-contained run of the batch pipeline: the clients it starts from, the commands it executes,
+// and the golden outcome it has to reproduce. The file is sections of lines, # starts a comment:
+//   [clients]   lines of clients.txt
+//   [commands]  one command per line, as journaled
+//   [balances]  every client after the run, then "reserved" and what their open orders hold
+//   [trades]    every trade in execution order
+//   [book]      every resting order of every asset, as the book admin command prints it
+// The last three are the golden part, rendered by the runner and rewritten when it regenerates.
+pub struct Scenario {
+    pub name: String,
+    pub clients: BTreeMap<String, Trader>,
+    pub commands: Vec<Command>,
+    // None until the scenario is first regenerated
+    pub expected: Option<String>,
+    // clients and commands as written, comments included, kept when the golden part is rewritten
+    input: String,
+}
+
+impl Scenario {
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).expect("Unable to read scenario");
+        let name = path.file_stem().and_then(|name| name.to_str()).unwrap_or_default();
+        Self::parse(name, &content)
+    }
+
+    pub fn parse(name: &str, content: &str) -> Self {
+        let (input, expected) = match content.find(GOLDEN_START) {
+            Some(start) => (&content[..start], Some(content[start..].to_string())),
+            None => (content, None),
+        };
+
+        let mut clients = BTreeMap::new();
+        let mut commands = Vec::new();
+        let mut section = "";
+        for line in input.lines().map(str::trim) {
+            if (line.is_empty() || line.starts_with('#')) {
+                continue;
+            }
+            if (line.starts_with('[')) {
+                section = line;
+                continue;
+            }
+            match section {
+                "[clients]" => {
+                    let mut trader = Trader::deserialize(line.to_string());
+                    trader.id = clients.len();
+                    clients.insert(trader.name.clone(), trader);
+                }
+                "[commands]" => commands.push(Command::parse(line).unwrap_or_else(|| panic!("Can't parse command {}", line))),
+                _ => panic!("Scenario {} has a line outside [clients] and [commands]: {}", name, line),
+            }
+        }
+
+        Scenario {
+            name: name.to_string(),
+            clients,
+            commands,
+            expected,
+            input: input.to_string(),
+        }
+    }
+
+    // Golden part of the file for what the commands do to the clients now
+    pub fn run(&self) -> String {
+        let mut order_book = OrderBook {
+            users: self.clients.clone(),
+            ..Default::default()
+        };
+        batch::run(&mut order_book, self.commands.clone(), None, SimulatedClock::default(), None);
+
+        let mut outcome = format!("{}\n", GOLDEN_START);
+        for trader in order_book.users.values() {
+            let mut reserved = trader.reserved_usd.to_string();
+            let mut asset = AssetName::A;
+            while asset != AssetName::Unknown {
+                write!(reserved, " {}", trader.reserved_assets.get(&asset).unwrap_or(&0)).unwrap();
+                asset = asset.next();
+            }
+            writeln!(outcome, "{} reserved {}", trader.serialize(), reserved).unwrap();
+        }
+        outcome.push_str("[trades]\n");
+        for trade in &order_book.trades {
+            writeln!(outcome, "{}", trade.serialize()).unwrap();
+        }
+        outcome.push_str("[book]\n");
+        let mut asset = AssetName::A;
+        while asset != AssetName::Unknown {
+            write!(outcome, "{}", order_book.level3(asset, None, false)).unwrap();
+            asset = asset.next();
+        }
+        outcome
+    }
+
+    // None when the run reproduces the golden part, otherwise the diff from it to the run
+    pub fn check(&self) -> Option<String> {
+        let actual = self.run();
+        let expected = self.expected.as_deref().unwrap_or_default();
+        if (actual == expected) {
+            return None;
+        }
+        Some(diff(expected, &actual))
+    }
+
+    pub fn regenerate<P: AsRef<Path>>(&self, path: P) {
+        let input = if self.input.ends_with('\n') { self.input.clone() } else { format!("{}\n", self.input) };
+        std::fs::write(path, input + &self.run()).expect("Unable to write scenario");
+    }
+}
+
+// Every *.scenario file in the directory, by name
+pub fn scenario_paths<P: AsRef<Path>>(dir: P) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .expect("Unable to read scenarios directory")
+        .map_while(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "scenario"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+// Line diff by longest common subsequence: "-" lines only expected, "+" lines only in the run
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut common = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if (expected[i] == actual[j]) {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if (i < expected.len() && j < actual.len() && expected[i] == actual[j]) {
+            writeln!(diff, "  {}", expected[i]).unwrap();
+            i += 1;
+            j += 1;
+        } else if (i < expected.len() && (j == actual.len() || common[i + 1][j] >= common[i][j + 1])) {
+            writeln!(diff, "- {}", expected[i]).unwrap();
+            i += 1;
+        } else {
+            writeln!(diff, "+ {}", actual[j]).unwrap();
+            j += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENARIO: &str = "\
+# a buyer lifts the only ask
+[clients]
+C1 100 0 0 0 0
+C2 0 5 0 0 0
+[commands]
+new C2 s A 7 5
+new C1 b A 7 3
+";
+
+    #[test]
+    fn run_renders_balances_trades_and_book() {
+        let scenario = Scenario::parse("lift", SCENARIO);
+        assert_eq!(scenario.expected, None);
+        assert_eq!(
+            scenario.run(),
+            "\
+[balances]
+C1 79 3 0 0 0 reserved 0 0 0 0 0
+C2 21 0 0 0 0 reserved 0 2 0 0 0
+[trades]
+0 A 7 3 1 0 C1 C2
+[book]
+A s 7 #0 order 0 C2 2 seq 0
+"
+        );
+    }
+
+    #[test]
+    fn check_diffs_the_run_against_the_golden_part() {
+        let golden = Scenario::parse("lift", SCENARIO).run();
+        assert_eq!(Scenario::parse("lift", &format!("{}{}", SCENARIO, golden)).check(), None);
+
+        let stale = golden.replace("C1 79 3", "C1 80 3");
+        let diff = Scenario::parse("lift", &format!("{}{}", SCENARIO, stale)).check().unwrap();
+        assert!(diff.contains("- C1 80 3 0 0 0 reserved 0 0 0 0 0\n+ C1 79 3 0 0 0 reserved 0 0 0 0 0\n"), "{}", diff);
+    }
+
+    #[test]
+    fn corpus_matches_golden_outputs() {
+        let paths = scenario_paths(SCENARIOS_DIR);
+        assert!(!paths.is_empty());
+
+        let mut failures = String::new();
+        for path in paths {
+            let scenario = Scenario::load(&path);
+            if let Some(diff) = scenario.check() {
+                write!(failures, "{}:\n{}", path.display(), diff).unwrap();
+            }
+        }
+        assert!(failures.is_empty(), "{}`cargo run -- scenarios --regenerate` accepts the new outputs", failures);
+    }
+}
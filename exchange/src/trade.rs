@@ -0,0 +1,31 @@
+use crate::asset_name::AssetName;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Trade {
+    pub id: usize,
+    pub asset: AssetName,
+    pub price: u64,
+    pub amount: u64,
+    pub buy_order_id: usize,
+    pub sell_order_id: usize,
+    pub buyer: String,
+    pub seller: String,
+    // nanoseconds since the epoch when the trade executed, by the book's clock
+    pub timestamp: u64,
+}
+
+impl Trade {
+    pub fn serialize(&self) -> String {
+        format!(
+            "{} {:?} {} {} {} {} {} {}",
+            self.id,
+            self.asset,
+            self.price,
+            self.amount,
+            self.buy_order_id,
+            self.sell_order_id,
+            self.buyer,
+            self.seller
+        )
+    }
+}
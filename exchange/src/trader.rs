@@ -1,68 +1,130 @@
 use std::fmt::Display;
 
 use crate::asset_name::*;
-use std::cell::RefCell;
 use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{self, BufRead, LineWriter, Write};
 use std::path::Path;
-use std::rc::Rc;
 
 use crate::deserialize::Deserialize;
 use crate::order::{Direction, Order};
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Trader {
     pub id: usize,
     pub name: String,
     pub usd_balance: u64,
     pub assets_count: HashMap<AssetName, u64>,
+    pub reserved_usd: u64,
+    pub reserved_assets: HashMap<AssetName, u64>,
 }
 
 impl Trader {
-    pub fn block_funds(&mut self, order: Rc<RefCell<Order>>) {
-
-        let mut order_ref = order.borrow();
-        if (order_ref.direction == Direction::Sell) {
-            let success = self.assets_count.get_mut(&order_ref.asset).unwrap().checked_sub(order_ref.amount);
-            match success {
-                None => {
-                    //todo: update order somehow
-                    *self.assets_count.get_mut(&order_ref.asset).unwrap() = 0
-                },
-                Some(result) => *self.assets_count.get_mut(&order_ref.asset).unwrap() = result,
-            }
+    // The book rejects an order the trader cannot afford (see can_afford) before it reserves for it
+    pub fn block_funds(&mut self, order: &Order) {
+        if (order.direction == Direction::Sell) {
+            *self.assets_count.entry(order.asset).or_insert(0) -= order.amount;
+            *self.reserved_assets.entry(order.asset).or_insert(0) += order.amount;
+        } else {
+            self.usd_balance -= order.amount * order.price;
+            self.reserved_usd += order.amount * order.price;
+        }
+    }
+
+    pub fn can_afford(&self, order: &Order) -> bool {
+        Self::reservation(order).is_some_and(|cost| cost <= self.available(order))
+    }
+
+    // An amendment reserves for new once old is released, so what old reserved counts as available
+    pub fn can_afford_amend(&self, old: &Order, new: &Order) -> bool {
+        let released = Self::reservation(old).unwrap_or(0);
+        Self::reservation(new).is_some_and(|cost| cost <= self.available(new).saturating_add(released))
+    }
+
+    // What an order reserves: USD for a buy, the asset for a sale. None if it does not fit in a balance.
+    fn reservation(order: &Order) -> Option<u64> {
+        match order.direction {
+            Direction::Buy => order.price.checked_mul(order.amount),
+            Direction::Sell => Some(order.amount),
+        }
+    }
+
+    fn available(&self, order: &Order) -> u64 {
+        match order.direction {
+            Direction::Buy => self.usd_balance,
+            Direction::Sell => self.assets_count.get(&order.asset).copied().unwrap_or(0),
+        }
+    }
+
+    pub fn release_funds(&mut self, order: &Order, amount: u64) {
+        if (order.direction == Direction::Sell) {
+            *self.reserved_assets.entry(order.asset).or_insert(0) -= amount;
+            *self.assets_count.entry(order.asset).or_insert(0) += amount;
         } else {
-            // todo: if order cost greater than user's usd_balance, update order to user max available resources
-            // or throw exceptions
-            self.usd_balance -= order_ref.amount * order_ref.price;
+            self.reserved_usd -= amount * order.price;
+            self.usd_balance += amount * order.price;
         }
     }
 
-    pub fn serialize_all(traders: &BTreeMap<String, Rc<RefCell<Trader>>>) {
+    // false, changing nothing, if the balance would overflow
+    pub fn deposit(&mut self, asset: Option<AssetName>, amount: u64) -> bool {
+        let balance = match asset {
+            Some(asset) => self.assets_count.entry(asset).or_insert(0),
+            None => &mut self.usd_balance,
+        };
+        match balance.checked_add(amount) {
+            Some(sum) => {
+                *balance = sum;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // "<name> <usd> <A> <B> <C> <D>", None for a line deserialize would panic on
+    pub fn try_parse(serialized_str: &str) -> Option<Trader> {
+        let parts: Vec<&str> = serialized_str.split(' ').collect();
+        if (parts.len() < AssetName::Unknown.index() + 2) {
+            return None;
+        }
+
+        let trader_name: String = parts[0].to_string();
+        let usd_balance = parts[1].parse::<u64>().ok()?;
+        let mut assets_count: HashMap<AssetName, u64> = HashMap::new();
+
+        let mut asset_name = AssetName::A;
+        while asset_name != AssetName::Unknown {
+            let asset_count = parts[asset_name.index() + 2].parse::<u64>().ok()?;
+            assets_count.insert(asset_name, asset_count);
+            asset_name = asset_name.next();
+        }
+
+        Some(Trader {
+            name: trader_name,
+            usd_balance,
+            assets_count,
+            ..Default::default()
+        })
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut serialized = format!("{} {}", self.name, self.usd_balance);
+        let mut asset_name = AssetName::A;
+        while asset_name != AssetName::Unknown {
+            serialized.push_str(&format!(" {}", self.assets_count[&asset_name]));
+            asset_name = asset_name.next();
+        }
+        serialized
+    }
+
+    pub fn serialize_all(traders: &BTreeMap<String, Trader>) {
         let file = File::create("resources/clients_updated.txt").expect("Couldn't create file");
         let mut file = LineWriter::new(file);
 
-        for (name, trader) in traders {
-
-            let trader = trader.as_ref().borrow();
-            file.write(trader.name.as_bytes());
-            file.write(b" ");
-            file.write(trader.usd_balance.to_string().as_bytes());
-            file.write(b" ");
-
-            let mut asset_name = AssetName::A;
-            while asset_name != AssetName::Unknown {
-                let count = trader.assets_count[&asset_name];
-                file.write(count.to_string().as_bytes());
-                if (asset_name != AssetName::D) {
-                    file.write(b" ");
-                }
-                asset_name = asset_name.next();
-            }
-            file.write(b"\n");
+        for trader in traders.values() {
+            writeln!(file, "{}", trader.serialize()).expect("Couldn't write trader");
         }
-        file.flush();
+        file.flush().expect("Couldn't flush file");
     }
 }
 
@@ -81,41 +143,18 @@ impl std::fmt::Display for Trader {
     }
 }
 
-impl Deserialize<String, Rc<RefCell<Trader>>> for Trader {
-    fn deserialize(serialized_str: String) -> Rc<RefCell<Trader>> {
-        let parts: Vec<&str> = serialized_str.split(' ').collect();
-
-        let trader_name: String = parts[0].to_string();
-        let usd_balance = parts[1].parse::<u64>().expect("Can't parse to u64");
-        let mut assets_count: HashMap<AssetName, u64> = HashMap::new();
-
-        let mut asset_name = AssetName::A;
-        while asset_name != AssetName::Unknown {
-            let asset_count = parts[asset_name.index() + 2]
-                .parse::<u64>()
-                .expect("Can't parse to u64");
-            assets_count.insert(asset_name, asset_count);
-            asset_name = asset_name.next();
-        }
-
-        let trader = Trader {
-            name: trader_name,
-            usd_balance: usd_balance,
-            assets_count: assets_count,
-            ..Default::default()
-        };
-        Rc::new(RefCell::new(trader))
+impl Deserialize<String, Trader> for Trader {
+    fn deserialize(serialized_str: String) -> Trader {
+        Self::try_parse(&serialized_str).expect("Can't parse trader")
     }
 
-    fn deserialize_all() -> BTreeMap<String, Rc<RefCell<Trader>>> {
+    fn deserialize_all() -> BTreeMap<String, Trader> {
         let mut traders = BTreeMap::new();
         let lines = Self::read_lines(Path::new("./resources/clients.txt"));
-        for line in lines {
-            if let Ok(serialized_trader) = line {
-                let mut trader = Self::deserialize(serialized_trader);
-                trader.borrow_mut().id = traders.len();
-                traders.insert(trader.borrow().name.clone(), trader.clone());
-            }
+        for serialized_trader in lines.map_while(Result::ok) {
+            let mut trader = Self::deserialize(serialized_trader);
+            trader.id = traders.len();
+            traders.insert(trader.name.clone(), trader);
         }
         traders
     }
@@ -133,7 +172,7 @@ mod tests {
     #[test]
     fn first_trader_has_all_fields_filled() {
         let traders = Trader::deserialize_all();
-        let trader = &traders["C1"].borrow();
+        let trader = &traders["C1"];
 
         assert_eq!(trader.name, "C1");
         assert_eq!(trader.usd_balance, 2000);
@@ -146,13 +185,19 @@ mod tests {
         let serialized_str = "C1 1000 10 5 15 0".to_string();
 
         let trader = Trader::deserialize(serialized_str);
-        let assets = &trader.borrow().assets_count;
+        let assets = &trader.assets_count;
 
-        assert_eq!(trader.borrow().name, "C1");
-        assert_eq!(trader.borrow().usd_balance, 1000u64);
+        assert_eq!(trader.name, "C1");
+        assert_eq!(trader.usd_balance, 1000u64);
         assert_eq!(assets.len(), 4);
     }
 
+    #[test]
+    fn malformed_trader_is_not_parsed() {
+        assert!(Trader::try_parse("C1 1000 10 5 15").is_none());
+        assert!(Trader::try_parse("C1 1000 10 5 x 0").is_none());
+    }
+
     #[test]
     fn usd_balance_changes_after_buy() {
         let order = Order {
@@ -166,12 +211,13 @@ mod tests {
             ..Default::default()
         };
 
-        trader.block_funds(Rc::new(RefCell::new(order)));
+        trader.block_funds(&order);
 
         assert_eq!(trader.usd_balance, 1000 - 12 * 7);
     }
 
     #[test]
+    #[allow(clippy::clone_on_copy)]
     fn asset_amount_changes_after_sell() {
         let order = Order {
             direction: Direction::Sell,
@@ -185,8 +231,85 @@ mod tests {
             ..Default::default()
         };
 
-        trader.block_funds(Rc::new(RefCell::new(order)));
+        trader.block_funds(&order);
 
         assert_eq!(trader.assets_count.iter().next().unwrap().1.clone(), 0);
     }
+
+    #[test]
+    fn blocked_funds_are_reserved() {
+        let buy = Order {
+            direction: Direction::Buy,
+            amount: 12,
+            price: 7,
+            ..Default::default()
+        };
+        let sell = Order {
+            direction: Direction::Sell,
+            asset: AssetName::A,
+            amount: 10,
+            ..Default::default()
+        };
+        let mut trader = Trader {
+            usd_balance: 1000,
+            assets_count: [(AssetName::A, 10)].into_iter().collect(),
+            ..Default::default()
+        };
+
+        trader.block_funds(&buy);
+        trader.block_funds(&sell);
+
+        assert_eq!(trader.reserved_usd, 12 * 7);
+        assert_eq!(trader.reserved_assets[&AssetName::A], 10);
+    }
+
+    #[test]
+    fn can_afford_checks_balance_for_order_side() {
+        let trader = Trader::deserialize("C1 100 10 5 15 0".to_string());
+        let buy = Order {
+            direction: Direction::Buy,
+            price: 10,
+            amount: 10,
+            ..Default::default()
+        };
+        let sell = Order {
+            direction: Direction::Sell,
+            asset: AssetName::A,
+            amount: 11,
+            ..Default::default()
+        };
+
+        assert!(trader.can_afford(&buy));
+        assert!(!trader.can_afford(&Order { amount: 11, ..buy }));
+        assert!(!trader.can_afford(&sell));
+    }
+
+    #[test]
+    fn released_funds_return_to_balance() {
+        let order = Order {
+            direction: Direction::Buy,
+            amount: 12,
+            price: 7,
+            ..Default::default()
+        };
+        let mut trader = Trader {
+            usd_balance: 1000,
+            ..Default::default()
+        };
+        trader.block_funds(&order);
+
+        trader.release_funds(&order, 2);
+
+        assert_eq!(trader.usd_balance, 1000 - 10 * 7);
+        assert_eq!(trader.reserved_usd, 10 * 7);
+    }
+
+    #[test]
+    fn serialized_trader_equals_deserialize_input() {
+        let serialized_str = "C1 1000 10 5 15 0".to_string();
+
+        let trader = Trader::deserialize(serialized_str.clone());
+
+        assert_eq!(trader.serialize(), serialized_str);
+    }
 }
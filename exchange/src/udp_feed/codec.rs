@@ -0,0 +1,385 @@
+use crate::asset_name::AssetName;
+use crate::order::Direction;
+use crate::order_matching_system::limit_tree::Level;
+use crate::order_matching_system::market_data::{InstrumentStatus, MarketDataEvent, MarketDataMessage};
+use crate::order_matching_system::order_book::Depth;
+
+// Packet: sequence u64, message_count u16, reserved u16, then the messages.
+// Message: length u16, kind u8, asset u8, asset_sequence u64, timestamp u64 (nanoseconds since the epoch),
+// then the body of the kind:
+//   'A' add / 'C' change  side u8, reserved 3, order_count u32, price u64, volume u64
+//   'D' delete            side u8, reserved 7, price u64
+//   'T' trade             trade_id u64, price u64, amount u64
+//   'S' status            status u8, reserved 7
+//   'I' indicative        price u64, volume u64, imbalance i64
+//   'N' snapshot          bid_count u16, ask_count u16, reserved 4, then levels of
+//                         price u64, volume u64, order_count u32, reserved 4, bids first
+// All integers are little-endian. A packet without messages is a heartbeat carrying the last sequence sent.
+pub const PACKET_HEADER_LEN: usize = 12;
+const MESSAGE_HEADER_LEN: usize = 20;
+const SNAPSHOT_LEVEL_LEN: usize = 24;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated,
+    UnknownKind(u8),
+    BadField,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub sequence: u64,
+    pub messages: Vec<MarketDataMessage>,
+}
+
+impl Packet {
+    pub fn is_heartbeat(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PACKET_HEADER_LEN + self.messages.iter().map(encoded_len).sum::<usize>());
+        bytes.extend_from_slice(&self.sequence.to_le_bytes());
+        bytes.extend_from_slice(&(self.messages.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&[0; 2]);
+        for message in &self.messages {
+            encode_message(&mut bytes, message);
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Packet, DecodeError> {
+        let mut reader = Reader { bytes, offset: 0 };
+        let sequence = reader.u64()?;
+        let message_count = reader.u16()?;
+        reader.skip(2)?;
+        let messages = (0..message_count)
+            .map(|_| decode_message(&mut reader))
+            .collect::<Result<_, _>>()?;
+        Ok(Packet { sequence, messages })
+    }
+}
+
+pub fn encoded_len(message: &MarketDataMessage) -> usize {
+    MESSAGE_HEADER_LEN
+        + match &message.event {
+            MarketDataEvent::LevelAdd { .. } | MarketDataEvent::LevelChange { .. } => 24,
+            MarketDataEvent::LevelDelete { .. } => 16,
+            MarketDataEvent::Trade { .. } | MarketDataEvent::Indicative { .. } => 24,
+            MarketDataEvent::Status(_) => 8,
+            MarketDataEvent::Snapshot(depth) => 8 + SNAPSHOT_LEVEL_LEN * (depth.bids.len() + depth.asks.len()),
+        }
+}
+
+fn encode_message(bytes: &mut Vec<u8>, message: &MarketDataMessage) {
+    let kind = match &message.event {
+        MarketDataEvent::LevelAdd { .. } => b'A',
+        MarketDataEvent::LevelChange { .. } => b'C',
+        MarketDataEvent::LevelDelete { .. } => b'D',
+        MarketDataEvent::Trade { .. } => b'T',
+        MarketDataEvent::Status(_) => b'S',
+        MarketDataEvent::Indicative { .. } => b'I',
+        MarketDataEvent::Snapshot(_) => b'N',
+    };
+    bytes.extend_from_slice(&(encoded_len(message) as u16).to_le_bytes());
+    bytes.extend_from_slice(&[kind, message.asset.index() as u8]);
+    bytes.extend_from_slice(&message.sequence.to_le_bytes());
+    bytes.extend_from_slice(&message.timestamp.to_le_bytes());
+
+    match &message.event {
+        MarketDataEvent::LevelAdd { side, level } | MarketDataEvent::LevelChange { side, level } => {
+            bytes.extend_from_slice(&[side_byte(*side), 0, 0, 0]);
+            bytes.extend_from_slice(&(level.order_count as u32).to_le_bytes());
+            bytes.extend_from_slice(&level.price.to_le_bytes());
+            bytes.extend_from_slice(&level.volume.to_le_bytes());
+        }
+        MarketDataEvent::LevelDelete { side, price } => {
+            bytes.extend_from_slice(&[side_byte(*side), 0, 0, 0, 0, 0, 0, 0]);
+            bytes.extend_from_slice(&price.to_le_bytes());
+        }
+        MarketDataEvent::Trade { trade_id, price, amount } => {
+            bytes.extend_from_slice(&(*trade_id as u64).to_le_bytes());
+            bytes.extend_from_slice(&price.to_le_bytes());
+            bytes.extend_from_slice(&amount.to_le_bytes());
+        }
+        MarketDataEvent::Status(status) => {
+            bytes.extend_from_slice(&[status_byte(*status), 0, 0, 0, 0, 0, 0, 0]);
+        }
+        MarketDataEvent::Indicative { price, volume, imbalance } => {
+            bytes.extend_from_slice(&price.to_le_bytes());
+            bytes.extend_from_slice(&volume.to_le_bytes());
+            bytes.extend_from_slice(&imbalance.to_le_bytes());
+        }
+        MarketDataEvent::Snapshot(depth) => {
+            bytes.extend_from_slice(&(depth.bids.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(&(depth.asks.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(&[0; 4]);
+            for level in depth.bids.iter().chain(&depth.asks) {
+                bytes.extend_from_slice(&level.price.to_le_bytes());
+                bytes.extend_from_slice(&level.volume.to_le_bytes());
+                bytes.extend_from_slice(&(level.order_count as u32).to_le_bytes());
+                bytes.extend_from_slice(&[0; 4]);
+            }
+        }
+    }
+}
+
+fn decode_message(reader: &mut Reader) -> Result<MarketDataMessage, DecodeError> {
+    let start = reader.offset;
+    let length = reader.u16()? as usize;
+    let kind = reader.u8()?;
+    let asset = asset_from_byte(reader.u8()?)?;
+    let sequence = reader.u64()?;
+    let timestamp = reader.u64()?;
+
+    let event = match kind {
+        b'A' | b'C' => {
+            let side = side_from_byte(reader.u8()?)?;
+            reader.skip(3)?;
+            let order_count = reader.u32()? as usize;
+            let level = Level {
+                price: reader.u64()?,
+                volume: reader.u64()?,
+                order_count,
+            };
+            if kind == b'A' {
+                MarketDataEvent::LevelAdd { side, level }
+            } else {
+                MarketDataEvent::LevelChange { side, level }
+            }
+        }
+        b'D' => {
+            let side = side_from_byte(reader.u8()?)?;
+            reader.skip(7)?;
+            MarketDataEvent::LevelDelete {
+                side,
+                price: reader.u64()?,
+            }
+        }
+        b'T' => MarketDataEvent::Trade {
+            trade_id: reader.u64()? as usize,
+            price: reader.u64()?,
+            amount: reader.u64()?,
+        },
+        b'S' => {
+            let status = status_from_byte(reader.u8()?)?;
+            reader.skip(7)?;
+            MarketDataEvent::Status(status)
+        }
+        b'I' => MarketDataEvent::Indicative {
+            price: reader.u64()?,
+            volume: reader.u64()?,
+            imbalance: reader.u64()? as i64,
+        },
+        b'N' => {
+            let bid_count = reader.u16()? as usize;
+            let ask_count = reader.u16()? as usize;
+            reader.skip(4)?;
+            let mut levels = Vec::with_capacity(bid_count + ask_count);
+            for _ in 0..bid_count + ask_count {
+                let price = reader.u64()?;
+                let volume = reader.u64()?;
+                let order_count = reader.u32()? as usize;
+                reader.skip(4)?;
+                levels.push(Level {
+                    price,
+                    volume,
+                    order_count,
+                });
+            }
+            let asks = levels.split_off(bid_count);
+            MarketDataEvent::Snapshot(Depth { bids: levels, asks })
+        }
+        unknown => return Err(DecodeError::UnknownKind(unknown)),
+    };
+    if reader.offset - start != length {
+        return Err(DecodeError::BadField);
+    }
+    Ok(MarketDataMessage {
+        asset,
+        sequence,
+        timestamp,
+        event,
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let bytes = self.bytes.get(self.offset..self.offset + len).ok_or(DecodeError::Truncated)?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), DecodeError> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+fn side_byte(side: Direction) -> u8 {
+    match side {
+        Direction::Buy => b'B',
+        Direction::Sell => b'S',
+    }
+}
+
+fn side_from_byte(side: u8) -> Result<Direction, DecodeError> {
+    match side {
+        b'B' => Ok(Direction::Buy),
+        b'S' => Ok(Direction::Sell),
+        _ => Err(DecodeError::BadField),
+    }
+}
+
+fn status_byte(status: InstrumentStatus) -> u8 {
+    match status {
+        InstrumentStatus::Trading => 0,
+        InstrumentStatus::Halted => 1,
+        InstrumentStatus::Closed => 2,
+        InstrumentStatus::Auction => 3,
+        InstrumentStatus::PreOpen => 4,
+    }
+}
+
+fn status_from_byte(status: u8) -> Result<InstrumentStatus, DecodeError> {
+    match status {
+        0 => Ok(InstrumentStatus::Trading),
+        1 => Ok(InstrumentStatus::Halted),
+        2 => Ok(InstrumentStatus::Closed),
+        3 => Ok(InstrumentStatus::Auction),
+        4 => Ok(InstrumentStatus::PreOpen),
+        _ => Err(DecodeError::BadField),
+    }
+}
+
+fn asset_from_byte(asset: u8) -> Result<AssetName, DecodeError> {
+    let mut asset_name = AssetName::A;
+    while asset_name != AssetName::Unknown {
+        if asset_name.index() == asset as usize {
+            return Ok(asset_name);
+        }
+        asset_name = asset_name.next();
+    }
+    Err(DecodeError::BadField)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(price: u64, volume: u64, order_count: usize) -> Level {
+        Level {
+            price,
+            volume,
+            order_count,
+        }
+    }
+
+    #[test]
+    fn packet_with_every_event_round_trips() {
+        let events = vec![
+            MarketDataEvent::Status(InstrumentStatus::PreOpen),
+            MarketDataEvent::Status(InstrumentStatus::Auction),
+            MarketDataEvent::Indicative {
+                price: 9,
+                volume: 4,
+                imbalance: -3,
+            },
+            MarketDataEvent::Status(InstrumentStatus::Trading),
+            MarketDataEvent::LevelAdd {
+                side: Direction::Buy,
+                level: level(7, 10, 1),
+            },
+            MarketDataEvent::LevelChange {
+                side: Direction::Sell,
+                level: level(9, 3, 2),
+            },
+            MarketDataEvent::LevelDelete {
+                side: Direction::Sell,
+                price: 11,
+            },
+            MarketDataEvent::Trade {
+                trade_id: 4,
+                price: 8,
+                amount: 2,
+            },
+            MarketDataEvent::Snapshot(Depth {
+                bids: vec![level(7, 10, 1), level(6, 1, 1)],
+                asks: vec![level(9, 3, 2)],
+            }),
+        ];
+        let packet = Packet {
+            sequence: 42,
+            messages: events
+                .into_iter()
+                .enumerate()
+                .map(|(sequence, event)| MarketDataMessage {
+                    asset: AssetName::C,
+                    sequence: sequence as u64 + 1,
+                    timestamp: 1_700_000_000_000_000_000 + sequence as u64,
+                    event,
+                })
+                .collect(),
+        };
+
+        let bytes = packet.encode();
+
+        assert_eq!(bytes.len(), PACKET_HEADER_LEN + packet.messages.iter().map(encoded_len).sum::<usize>());
+        assert_eq!(Packet::decode(&bytes), Ok(packet));
+    }
+
+    #[test]
+    fn heartbeat_is_a_bare_header() {
+        let heartbeat = Packet {
+            sequence: 7,
+            messages: Vec::new(),
+        };
+
+        let bytes = heartbeat.encode();
+
+        assert_eq!(bytes, [7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(Packet::decode(&bytes).unwrap().is_heartbeat());
+    }
+
+    #[test]
+    fn malformed_packets_are_rejected() {
+        let packet = Packet {
+            sequence: 1,
+            messages: vec![MarketDataMessage {
+                asset: AssetName::A,
+                sequence: 1,
+                timestamp: 0,
+                event: MarketDataEvent::Trade {
+                    trade_id: 0,
+                    price: 8,
+                    amount: 2,
+                },
+            }],
+        };
+        let mut bytes = packet.encode();
+
+        assert_eq!(Packet::decode(&bytes[..bytes.len() - 1]), Err(DecodeError::Truncated));
+        bytes[PACKET_HEADER_LEN + 2] = b'?';
+        assert_eq!(Packet::decode(&bytes), Err(DecodeError::UnknownKind(b'?')));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod codec;
+pub mod publisher;
+pub mod subscriber;
@@ -0,0 +1,180 @@
+use crate::order_matching_system::market_data::MarketDataMessage;
+use crate::udp_feed::codec::{encoded_len, Packet, PACKET_HEADER_LEN};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Messages are batched into packets up to this size, a bigger snapshot goes out alone
+pub const MAX_PACKET_LEN: usize = 1400;
+// Packets kept for retransmission, older gaps are only healed by the next snapshot
+pub const HISTORY_LEN: usize = 100_000;
+pub const RETRANSMIT_REQUEST_LEN: usize = 16;
+
+struct History {
+    first_sequence: u64,
+    packets: VecDeque<Vec<u8>>,
+}
+
+// Sends packets with consecutive sequence numbers starting from 1 over UDP and keeps them for
+// the TCP retransmission service. A retransmission request is first u64, last u64 (inclusive);
+// the reply is every packet of the range still kept, each prefixed by its u32 length, then EOF.
+// A range that ends before it starts gets just the EOF.
+pub struct MarketDataPublisher {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    next_sequence: u64,
+    history: Arc<Mutex<History>>,
+    retransmit_addr: SocketAddr,
+}
+
+impl MarketDataPublisher {
+    pub fn bind<A: ToSocketAddrs, R: ToSocketAddrs>(destination: A, retransmit_addr: R) -> io::Result<Self> {
+        let destination = destination
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No destination address"))?;
+        let socket = match destination.ip() {
+            IpAddr::V4(ip) if ip.is_loopback() => UdpSocket::bind("127.0.0.1:0")?,
+            _ => UdpSocket::bind("0.0.0.0:0")?,
+        };
+        if destination.ip().is_multicast() {
+            socket.set_multicast_loop_v4(true)?;
+            socket.set_multicast_ttl_v4(1)?;
+        }
+
+        let history = Arc::new(Mutex::new(History {
+            first_sequence: 1,
+            packets: VecDeque::new(),
+        }));
+        let listener = TcpListener::bind(retransmit_addr)?;
+        let retransmit_addr = listener.local_addr()?;
+        let retransmit_history = history.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().map_while(Result::ok) {
+                let _ = serve_retransmission(stream, &retransmit_history);
+            }
+        });
+
+        Ok(MarketDataPublisher {
+            socket,
+            destination,
+            next_sequence: 1,
+            history,
+            retransmit_addr,
+        })
+    }
+
+    pub fn retransmit_addr(&self) -> SocketAddr {
+        self.retransmit_addr
+    }
+
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    pub fn publish(&mut self, messages: &[MarketDataMessage]) -> io::Result<()> {
+        let mut batch: Vec<MarketDataMessage> = Vec::new();
+        let mut batch_len = PACKET_HEADER_LEN;
+        for message in messages {
+            if !batch.is_empty() && batch_len + encoded_len(message) > MAX_PACKET_LEN {
+                self.send_packet(std::mem::take(&mut batch))?;
+                batch_len = PACKET_HEADER_LEN;
+            }
+            batch_len += encoded_len(message);
+            batch.push(message.clone());
+        }
+        if !batch.is_empty() {
+            self.send_packet(batch)?;
+        }
+        Ok(())
+    }
+
+    // Lets subscribers notice that the last packets were lost while nothing else is published
+    pub fn heartbeat(&self) -> io::Result<()> {
+        let heartbeat = Packet {
+            sequence: self.next_sequence - 1,
+            messages: Vec::new(),
+        };
+        self.socket.send_to(&heartbeat.encode(), self.destination).map(|_| ())
+    }
+
+    fn send_packet(&mut self, messages: Vec<MarketDataMessage>) -> io::Result<()> {
+        let packet = Packet {
+            sequence: self.next_sequence,
+            messages,
+        };
+        self.next_sequence += 1;
+        let bytes = packet.encode();
+        {
+            let mut history = self.history.lock().unwrap();
+            history.packets.push_back(bytes.clone());
+            if history.packets.len() > HISTORY_LEN {
+                history.packets.pop_front();
+                history.first_sequence += 1;
+            }
+        }
+        self.socket.send_to(&bytes, self.destination).map(|_| ())
+    }
+}
+
+fn serve_retransmission(mut stream: TcpStream, history: &Mutex<History>) -> io::Result<()> {
+    let mut request = [0u8; RETRANSMIT_REQUEST_LEN];
+    stream.read_exact(&mut request)?;
+    let first = u64::from_le_bytes(request[..8].try_into().unwrap());
+    let last = u64::from_le_bytes(request[8..].try_into().unwrap());
+    if (first > last) {
+        return Ok(());
+    }
+
+    let packets: Vec<Vec<u8>> = {
+        let history = history.lock().unwrap();
+        let skip = first.saturating_sub(history.first_sequence) as usize;
+        let take = last.saturating_add(1).saturating_sub(first.max(history.first_sequence)) as usize;
+        history.packets.iter().skip(skip).take(take).cloned().collect()
+    };
+    for packet in packets {
+        stream.write_all(&(packet.len() as u32).to_le_bytes())?;
+        stream.write_all(&packet)?;
+    }
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset_name::AssetName;
+    use crate::order_matching_system::market_data::MarketDataEvent;
+
+    fn retransmit(addr: SocketAddr, first: u64, last: u64) -> Vec<u8> {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut request = [0u8; RETRANSMIT_REQUEST_LEN];
+        request[..8].copy_from_slice(&first.to_le_bytes());
+        request[8..].copy_from_slice(&last.to_le_bytes());
+        stream.write_all(&request).unwrap();
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).unwrap();
+        reply
+    }
+
+    #[test]
+    fn requests_at_the_ends_of_the_sequence_range_leave_the_publisher_running() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut publisher = MarketDataPublisher::bind(receiver.local_addr().unwrap(), "127.0.0.1:0").unwrap();
+        let message = MarketDataMessage {
+            asset: AssetName::A,
+            sequence: 1,
+            timestamp: 0,
+            event: MarketDataEvent::Trade { trade_id: 0, price: 7, amount: 5 },
+        };
+        publisher.publish(std::slice::from_ref(&message)).unwrap();
+
+        let packet = retransmit(publisher.retransmit_addr(), 1, u64::MAX);
+        assert_eq!(&packet[4..], &Packet { sequence: 1, messages: vec![message.clone()] }.encode()[..]);
+        assert!(retransmit(publisher.retransmit_addr(), 9, 3).is_empty());
+
+        publisher.publish(&[message]).unwrap();
+        assert_eq!(publisher.next_sequence(), 3);
+    }
+}
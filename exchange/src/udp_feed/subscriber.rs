@@ -0,0 +1,173 @@
+use crate::asset_name::AssetName;
+use crate::order_matching_system::market_data::LocalBook;
+use crate::udp_feed::codec::Packet;
+use crate::udp_feed::publisher::RETRANSMIT_REQUEST_LEN;
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+const MAX_DATAGRAM_LEN: usize = 65_536;
+
+// Reference consumer: rebuilds every asset's L2 book from the UDP feed and fetches
+// missing packets from the retransmission service as soon as a gap shows up
+pub struct MarketDataSubscriber {
+    socket: UdpSocket,
+    retransmit_addr: SocketAddr,
+    next_sequence: u64,
+    books: BTreeMap<AssetName, LocalBook>,
+}
+
+impl MarketDataSubscriber {
+    pub fn bind<A: ToSocketAddrs>(addr: A, retransmit_addr: SocketAddr) -> io::Result<Self> {
+        Ok(MarketDataSubscriber {
+            socket: UdpSocket::bind(addr)?,
+            retransmit_addr,
+            next_sequence: 1,
+            books: BTreeMap::new(),
+        })
+    }
+
+    pub fn join_multicast(&self, group: Ipv4Addr, interface: Ipv4Addr) -> io::Result<()> {
+        self.socket.join_multicast_v4(&group, &interface)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_read_timeout(timeout)
+    }
+
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    pub fn book(&self, asset: AssetName) -> Option<&LocalBook> {
+        self.books.get(&asset)
+    }
+
+    // Handles one datagram, packets that fail to decode are treated as lost
+    pub fn receive(&mut self) -> io::Result<()> {
+        let mut buffer = vec![0u8; MAX_DATAGRAM_LEN];
+        let (len, _) = self.socket.recv_from(&mut buffer)?;
+        let Ok(packet) = Packet::decode(&buffer[..len]) else {
+            return Ok(());
+        };
+
+        let last_missing = if packet.is_heartbeat() { packet.sequence } else { packet.sequence.saturating_sub(1) };
+        if last_missing >= self.next_sequence {
+            self.recover(self.next_sequence, last_missing)?;
+            // whatever could not be recovered is left to the books' next snapshot
+            self.next_sequence = self.next_sequence.max(last_missing + 1);
+        }
+        if !packet.is_heartbeat() && packet.sequence == self.next_sequence {
+            self.apply(&packet);
+        }
+        Ok(())
+    }
+
+    fn recover(&mut self, first: u64, last: u64) -> io::Result<()> {
+        let mut stream = TcpStream::connect(self.retransmit_addr)?;
+        let mut request = [0u8; RETRANSMIT_REQUEST_LEN];
+        request[..8].copy_from_slice(&first.to_le_bytes());
+        request[8..].copy_from_slice(&last.to_le_bytes());
+        stream.write_all(&request)?;
+
+        loop {
+            let mut len = [0u8; 4];
+            match stream.read_exact(&mut len) {
+                Ok(()) => {}
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                Err(error) => return Err(error),
+            }
+            let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+            stream.read_exact(&mut bytes)?;
+            if let Ok(packet) = Packet::decode(&bytes) {
+                if packet.sequence == self.next_sequence {
+                    self.apply(&packet);
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, packet: &Packet) {
+        for message in &packet.messages {
+            let book = self
+                .books
+                .entry(message.asset)
+                .or_insert_with(|| LocalBook::new(message.asset));
+            // a gap inside one asset unsyncs only that book until its next snapshot
+            let _ = book.apply(message);
+        }
+        self.next_sequence = packet.sequence + 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+    use crate::order_matching_system::testing::funded_order_book;
+    use crate::udp_feed::publisher::MarketDataPublisher;
+    use std::collections::HashSet;
+    use std::thread;
+
+    // Forwards datagrams to the subscriber except the packets with the dropped sequence numbers
+    fn start_lossy_relay(subscriber: SocketAddr, dropped: HashSet<u64>) -> SocketAddr {
+        let relay = UdpSocket::bind("127.0.0.1:0").unwrap();
+        relay.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = relay.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buffer = vec![0u8; MAX_DATAGRAM_LEN];
+            while let Ok((len, _)) = relay.recv_from(&mut buffer) {
+                let packet = Packet::decode(&buffer[..len]).unwrap();
+                if packet.is_heartbeat() || !dropped.contains(&packet.sequence) {
+                    relay.send_to(&buffer[..len], subscriber).unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn subscriber_recovers_dropped_packets_and_rebuilds_the_book() {
+        let commands = [
+            "new C1 b A 7 10",
+            "new C3 b A 6 5",
+            "new C2 s A 9 4",
+            "new C4 s B 12 1",
+            "new C2 s A 7 3",
+            "amend 1 6 2",
+            "new C3 b B 12 1",
+            "cancel 2",
+        ];
+        // the retransmission address is only known once the publisher is bound
+        let mut subscriber = MarketDataSubscriber::bind("127.0.0.1:0", "127.0.0.1:1".parse().unwrap()).unwrap();
+        subscriber.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        // one packet per command, the last one is only noticed through the heartbeat
+        let relay = start_lossy_relay(subscriber.local_addr().unwrap(), HashSet::from([2, 3, 6, 8]));
+        let mut publisher = MarketDataPublisher::bind(relay, "127.0.0.1:0").unwrap();
+        subscriber.retransmit_addr = publisher.retransmit_addr();
+
+        let mut order_book = funded_order_book();
+        for command in commands {
+            order_book.execute(Command::parse(command).unwrap());
+            let messages = order_book.market_data.drain();
+            publisher.publish(&messages).unwrap();
+        }
+        publisher.heartbeat().unwrap();
+        assert_eq!(publisher.next_sequence(), commands.len() as u64 + 1);
+
+        while subscriber.next_sequence() < publisher.next_sequence() {
+            subscriber.receive().unwrap();
+        }
+
+        for asset in [AssetName::A, AssetName::B] {
+            let book = subscriber.book(asset).unwrap();
+            assert!(book.is_synced());
+            assert_eq!(book.depth(usize::MAX), order_book.depth(asset, usize::MAX));
+        }
+    }
+}